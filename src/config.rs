@@ -0,0 +1,169 @@
+use ipnet::IpNet;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use std::env;
+use std::net::IpAddr;
+
+// Bounds for how long an invite link stays valid, in seconds
+pub const MIN_INVITE_EXPIRY_SECONDS: u64 = 15 * 60;
+pub const MAX_INVITE_EXPIRY_SECONDS: u64 = 7 * 24 * 3600;
+const DEFAULT_INVITE_EXPIRY_SECONDS: u64 = 3600;
+
+// Lists this service's base URL per environment under `[urls]`
+const SERVICES_TOML: &str = include_str!("../services.toml");
+
+/// Deployment environment, `DEPLOY_ENV` or `dev`, named like the `[urls]` entries of
+/// services.toml.
+pub fn deploy_env() -> String {
+    env::var("DEPLOY_ENV").unwrap_or_else(|_| "dev".to_string())
+}
+
+/// Base URL services.toml lists for `environment`, if any.
+pub fn service_url(environment: &str) -> Option<String> {
+    Figment::from(Toml::string(SERVICES_TOML))
+        .extract_inner::<String>(&format!("urls.{}", environment))
+        .ok()
+        .map(|url| url.trim_end_matches('/').to_string())
+}
+
+fn link_base(name: &str, route: &str) -> Result<String, String> {
+    if let Ok(url) = env::var(name) {
+        return Ok(url.trim_end_matches('/').to_string());
+    }
+
+    let environment = deploy_env();
+    service_url(&environment)
+        .map(|url| format!("{}/#/{}", url, route))
+        .ok_or_else(|| {
+            format!(
+                "{} is not set and services.toml has no [urls] entry for DEPLOY_ENV \"{}\"",
+                name, environment
+            )
+        })
+}
+
+/// Base of the invite acceptance link; the token is appended as the last path segment.
+/// `INVITE_ACCEPT_URL`, or the environment's `[urls]` entry followed by `/#/accept-invite`.
+pub fn invite_accept_url() -> Result<String, String> {
+    link_base("INVITE_ACCEPT_URL", "accept-invite")
+}
+
+/// Base of the password reset link; the token is appended as the last path segment.
+/// `PASSWORD_RESET_URL`, or the environment's `[urls]` entry followed by `/#/reset-password`.
+pub fn password_reset_url() -> Result<String, String> {
+    link_base("PASSWORD_RESET_URL", "reset-password")
+}
+
+/// Expiry used when an invite doesn't ask for one, `INVITE_DEFAULT_EXPIRY_SECONDS` or an hour.
+pub fn default_invite_expiry_seconds() -> u64 {
+    env::var("INVITE_DEFAULT_EXPIRY_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_INVITE_EXPIRY_SECONDS)
+        .clamp(MIN_INVITE_EXPIRY_SECONDS, MAX_INVITE_EXPIRY_SECONDS)
+}
+
+/// How long before expiry the invitee is reminded: a quarter of the invite's lifetime, at
+/// most a day. Invites shorter than an hour get no reminder.
+pub fn invite_reminder_lead_seconds(expiration: u64) -> Option<u64> {
+    if expiration < 3600 {
+        return None;
+    }
+    Some((expiration / 4).min(24 * 3600))
+}
+
+fn domain_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// Services allowed to make the internal calls behind `name`, e.g. `USERS_READ_CALLERS`,
+/// matched against ISC token subjects and API key names. Empty allows none.
+pub fn internal_callers(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|caller| !caller.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Domains invites may be sent to, from `INVITE_ALLOWED_DOMAINS`. Empty allows any domain.
+pub fn invite_allowed_domains() -> Vec<String> {
+    domain_list("INVITE_ALLOWED_DOMAINS")
+}
+
+/// Domains invites are never sent to, from `INVITE_DENIED_DOMAINS`.
+pub fn invite_denied_domains() -> Vec<String> {
+    domain_list("INVITE_DENIED_DOMAINS")
+}
+
+fn limit_from_env(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Existence lookups a single caller may make per minute, `EXISTENCE_CHECK_RATE_LIMIT`.
+pub fn existence_check_caller_limit() -> u64 {
+    limit_from_env("EXISTENCE_CHECK_RATE_LIMIT", 120)
+}
+
+/// Existence lookups per minute from one client IP, `EXISTENCE_CHECK_IP_RATE_LIMIT`. Looser
+/// than the per-caller limit since several services may share an egress address.
+pub fn existence_check_ip_limit() -> u64 {
+    limit_from_env("EXISTENCE_CHECK_IP_RATE_LIMIT", 600)
+}
+
+/// Proxies allowed to report the client address in `X-Forwarded-For`, from
+/// `TRUSTED_PROXIES` as comma separated addresses or CIDR ranges. Unparseable entries are
+/// skipped.
+pub fn trusted_proxies() -> Vec<IpNet> {
+    env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .ok()
+        })
+        .collect()
+}
+
+/// How long force-logout markers are kept, `SESSION_REVOCATION_TTL_SECONDS` or a week. It
+/// has to cover the lifetime of the longest-lived token, or revoked tokens come back.
+pub fn revocation_marker_ttl_seconds() -> usize {
+    limit_from_env("SESSION_REVOCATION_TTL_SECONDS", 7 * 24 * 3600) as usize
+}
+
+/// Lifetime access tokens are issued with, `ACCESS_TOKEN_LIFETIME_SECONDS` or an hour. Used
+/// to tell when a token without `iat` was issued.
+pub fn access_token_lifetime_seconds() -> i64 {
+    limit_from_env("ACCESS_TOKEN_LIFETIME_SECONDS", 3600) as i64
+}
+
+/// Failed logins in a row after which an account is locked, `FAILED_LOGIN_LOCK_THRESHOLD`.
+/// Zero turns automatic locking off.
+pub fn failed_login_lock_threshold() -> u32 {
+    limit_from_env("FAILED_LOGIN_LOCK_THRESHOLD", 10) as u32
+}
+
+/// How long failed logins are remembered after the last one, `FAILED_LOGIN_WINDOW_SECONDS`
+/// or an hour. Failures further apart than this never add up to a lock.
+pub fn failed_login_window_seconds() -> usize {
+    limit_from_env("FAILED_LOGIN_WINDOW_SECONDS", 3600) as usize
+}
+
+/// How long an automatic lock lasts, `FAILED_LOGIN_LOCK_SECONDS` or 15 minutes.
+pub fn failed_login_lock_seconds() -> i64 {
+    limit_from_env("FAILED_LOGIN_LOCK_SECONDS", 15 * 60) as i64
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const PENDING_SET: &str = "access_requests:pending";
+// Decided requests are kept for a month so they show up in listings and audits
+const DECIDED_TTL_SECONDS: usize = 30 * 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AccessRequest {
+    pub id: String,
+    pub requester: String,
+    pub group_identifier: String,
+    pub reason: String,
+    pub duration_hours: i64,
+    pub status: AccessRequestStatus,
+    pub created_at: DateTime<Utc>,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+fn request_key(request_id: &str) -> String {
+    format!("access_request:{}", request_id)
+}
+
+pub fn save(conn: &mut redis::Connection, request: &AccessRequest) -> RedisResult<()> {
+    let key = request_key(&request.id);
+    let payload = serde_json::to_string(request).unwrap_or_default();
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    match request.status {
+        AccessRequestStatus::Pending => {
+            pipe.set(&key, payload).sadd(PENDING_SET, &request.id);
+        }
+        _ => {
+            pipe.set_ex(&key, payload, DECIDED_TTL_SECONDS)
+                .srem(PENDING_SET, &request.id);
+        }
+    }
+    pipe.query(conn)
+}
+
+pub fn get(conn: &mut redis::Connection, request_id: &str) -> RedisResult<Option<AccessRequest>> {
+    let raw: Option<String> = conn.get(request_key(request_id))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+pub fn pending(conn: &mut redis::Connection) -> RedisResult<Vec<AccessRequest>> {
+    let ids: Vec<String> = conn.smembers(PENDING_SET)?;
+
+    let mut requests = Vec::new();
+    for request_id in ids {
+        if let Some(request) = get(conn, &request_id)? {
+            requests.push(request);
+        }
+    }
+    requests.sort_by_key(|request| request.created_at);
+    Ok(requests)
+}
@@ -0,0 +1,341 @@
+use crate::db::groups::GroupRef;
+use chrono::{DateTime, Utc};
+use diesel::sql_types::{Array, BigInt, Bool, Nullable, Timestamptz, Varchar};
+use diesel::{
+    sql_query, Connection, OptionalExtension, PgConnection, QueryResult, QueryableByName,
+    RunQueryDsl,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Campaigns and their items live in the `access_review` and `access_review_item` tables,
+// which the admin schema does not render. Statuses and decisions are stored by their API
+// names.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    Open,
+    Completed,
+}
+
+impl CampaignStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            CampaignStatus::Open => "open",
+            CampaignStatus::Completed => "completed",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [CampaignStatus::Open, CampaignStatus::Completed]
+            .into_iter()
+            .find(|status| status.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+    Approve,
+    Revoke,
+}
+
+impl ReviewDecision {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReviewDecision::Approve => "approve",
+            ReviewDecision::Revoke => "revoke",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [ReviewDecision::Approve, ReviewDecision::Revoke]
+            .into_iter()
+            .find(|decision| decision.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewCampaign {
+    pub id: String,
+    pub name: String,
+    /// Set when the campaign was scoped to an app rather than directly to a group
+    pub app_id: Option<i64>,
+    pub group_id: i64,
+    pub group_identifier: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    pub status: CampaignStatus,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub last_reminded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewItem {
+    pub id: String,
+    pub user_id: i64,
+    pub email_id: String,
+    /// Owners of the group at campaign creation; any of them may decide the item
+    pub reviewers: Vec<String>,
+    pub decision: Option<ReviewDecision>,
+    pub comment: Option<String>,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+    /// True when the item was revoked because nobody reviewed it before the deadline
+    #[serde(default)]
+    pub auto_revoked: bool,
+    /// Nested group the member inherits access through; revoking removes them from it
+    #[serde(default)]
+    pub via_group: Option<GroupRef>,
+}
+
+impl ReviewItem {
+    /// Group whose direct membership a revocation removes.
+    pub fn membership_group(&self, campaign: &ReviewCampaign) -> GroupRef {
+        self.via_group.clone().unwrap_or_else(|| GroupRef {
+            id: campaign.group_id,
+            identifier: campaign.group_identifier.clone(),
+        })
+    }
+}
+
+#[derive(QueryableByName)]
+struct CampaignRow {
+    #[diesel(sql_type = Varchar)]
+    id: String,
+    #[diesel(sql_type = Varchar)]
+    name: String,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    app_id: Option<i64>,
+    #[diesel(sql_type = BigInt)]
+    group_id: i64,
+    #[diesel(sql_type = Varchar)]
+    group_identifier: String,
+    #[diesel(sql_type = Varchar)]
+    created_by: String,
+    #[diesel(sql_type = Timestamptz)]
+    created_at: DateTime<Utc>,
+    #[diesel(sql_type = Timestamptz)]
+    deadline: DateTime<Utc>,
+    #[diesel(sql_type = Varchar)]
+    status: String,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    completed_at: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    last_reminded_at: Option<DateTime<Utc>>,
+}
+
+impl CampaignRow {
+    /// Rows with a status this version doesn't know are skipped.
+    fn into_campaign(self) -> Option<ReviewCampaign> {
+        Some(ReviewCampaign {
+            status: CampaignStatus::parse(&self.status)?,
+            id: self.id,
+            name: self.name,
+            app_id: self.app_id,
+            group_id: self.group_id,
+            group_identifier: self.group_identifier,
+            created_by: self.created_by,
+            created_at: self.created_at,
+            deadline: self.deadline,
+            completed_at: self.completed_at,
+            last_reminded_at: self.last_reminded_at,
+        })
+    }
+}
+
+#[derive(QueryableByName)]
+struct ItemRow {
+    #[diesel(sql_type = Varchar)]
+    id: String,
+    #[diesel(sql_type = BigInt)]
+    user_id: i64,
+    #[diesel(sql_type = Varchar)]
+    email_id: String,
+    #[diesel(sql_type = Array<Varchar>)]
+    reviewers: Vec<String>,
+    #[diesel(sql_type = Nullable<Varchar>)]
+    decision: Option<String>,
+    #[diesel(sql_type = Nullable<Varchar>)]
+    comment: Option<String>,
+    #[diesel(sql_type = Nullable<Varchar>)]
+    decided_by: Option<String>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    decided_at: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Bool)]
+    auto_revoked: bool,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    via_group_id: Option<i64>,
+    #[diesel(sql_type = Nullable<Varchar>)]
+    via_group_identifier: Option<String>,
+}
+
+impl ItemRow {
+    /// Rows with a decision this version doesn't know are skipped.
+    fn into_item(self) -> Option<ReviewItem> {
+        let decision = match &self.decision {
+            Some(decision) => Some(ReviewDecision::parse(decision)?),
+            None => None,
+        };
+        Some(ReviewItem {
+            id: self.id,
+            user_id: self.user_id,
+            email_id: self.email_id,
+            reviewers: self.reviewers,
+            decision,
+            comment: self.comment,
+            decided_by: self.decided_by,
+            decided_at: self.decided_at,
+            auto_revoked: self.auto_revoked,
+            via_group: self
+                .via_group_id
+                .zip(self.via_group_identifier)
+                .map(|(id, identifier)| GroupRef { id, identifier }),
+        })
+    }
+}
+
+const CAMPAIGN_COLUMNS: &str = "id, name, app_id, group_id, group_identifier, created_by, \
+    created_at, deadline, status, completed_at, last_reminded_at";
+const ITEM_COLUMNS: &str = "id, user_id, email_id, reviewers, decision, comment, decided_by, \
+    decided_at, auto_revoked, via_group_id, via_group_identifier";
+
+/// Inserts a campaign with its items in one transaction.
+pub fn create(
+    conn: &mut PgConnection,
+    campaign: &ReviewCampaign,
+    items: &[ReviewItem],
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        sql_query(format!(
+            "INSERT INTO access_review ({}) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            CAMPAIGN_COLUMNS
+        ))
+        .bind::<Varchar, _>(&campaign.id)
+        .bind::<Varchar, _>(&campaign.name)
+        .bind::<Nullable<BigInt>, _>(campaign.app_id)
+        .bind::<BigInt, _>(campaign.group_id)
+        .bind::<Varchar, _>(&campaign.group_identifier)
+        .bind::<Varchar, _>(&campaign.created_by)
+        .bind::<Timestamptz, _>(campaign.created_at)
+        .bind::<Timestamptz, _>(campaign.deadline)
+        .bind::<Varchar, _>(campaign.status.as_str())
+        .bind::<Nullable<Timestamptz>, _>(campaign.completed_at)
+        .bind::<Nullable<Timestamptz>, _>(campaign.last_reminded_at)
+        .execute(conn)?;
+
+        for item in items {
+            sql_query(format!(
+                "INSERT INTO access_review_item (campaign_id, {}) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+                ITEM_COLUMNS
+            ))
+            .bind::<Varchar, _>(&campaign.id)
+            .bind::<Varchar, _>(&item.id)
+            .bind::<BigInt, _>(item.user_id)
+            .bind::<Varchar, _>(&item.email_id)
+            .bind::<Array<Varchar>, _>(&item.reviewers)
+            .bind::<Nullable<Varchar>, _>(item.decision.map(ReviewDecision::as_str))
+            .bind::<Nullable<Varchar>, _>(&item.comment)
+            .bind::<Nullable<Varchar>, _>(&item.decided_by)
+            .bind::<Nullable<Timestamptz>, _>(item.decided_at)
+            .bind::<Bool, _>(item.auto_revoked)
+            .bind::<Nullable<BigInt>, _>(item.via_group.as_ref().map(|group| group.id))
+            .bind::<Nullable<Varchar>, _>(
+                item.via_group
+                    .as_ref()
+                    .map(|group| group.identifier.as_str()),
+            )
+            .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Saves the parts of a campaign that change after creation.
+pub fn save_campaign(conn: &mut PgConnection, campaign: &ReviewCampaign) -> QueryResult<()> {
+    sql_query(
+        "UPDATE access_review SET status = $2, completed_at = $3, last_reminded_at = $4 \
+         WHERE id = $1",
+    )
+    .bind::<Varchar, _>(&campaign.id)
+    .bind::<Varchar, _>(campaign.status.as_str())
+    .bind::<Nullable<Timestamptz>, _>(campaign.completed_at)
+    .bind::<Nullable<Timestamptz>, _>(campaign.last_reminded_at)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Saves an item's decision.
+pub fn save_item(conn: &mut PgConnection, campaign_id: &str, item: &ReviewItem) -> QueryResult<()> {
+    sql_query(
+        "UPDATE access_review_item SET decision = $3, comment = $4, decided_by = $5, \
+         decided_at = $6, auto_revoked = $7 WHERE campaign_id = $1 AND id = $2",
+    )
+    .bind::<Varchar, _>(campaign_id)
+    .bind::<Varchar, _>(&item.id)
+    .bind::<Nullable<Varchar>, _>(item.decision.map(ReviewDecision::as_str))
+    .bind::<Nullable<Varchar>, _>(&item.comment)
+    .bind::<Nullable<Varchar>, _>(&item.decided_by)
+    .bind::<Nullable<Timestamptz>, _>(item.decided_at)
+    .bind::<Bool, _>(item.auto_revoked)
+    .execute(conn)?;
+    Ok(())
+}
+
+pub fn get_campaign(
+    conn: &mut PgConnection,
+    campaign_id: &str,
+) -> QueryResult<Option<ReviewCampaign>> {
+    let row: Option<CampaignRow> = sql_query(format!(
+        "SELECT {} FROM access_review WHERE id = $1",
+        CAMPAIGN_COLUMNS
+    ))
+    .bind::<Varchar, _>(campaign_id)
+    .get_result(conn)
+    .optional()?;
+    Ok(row.and_then(CampaignRow::into_campaign))
+}
+
+pub fn get_item(
+    conn: &mut PgConnection,
+    campaign_id: &str,
+    item_id: &str,
+) -> QueryResult<Option<ReviewItem>> {
+    let row: Option<ItemRow> = sql_query(format!(
+        "SELECT {} FROM access_review_item WHERE campaign_id = $1 AND id = $2",
+        ITEM_COLUMNS
+    ))
+    .bind::<Varchar, _>(campaign_id)
+    .bind::<Varchar, _>(item_id)
+    .get_result(conn)
+    .optional()?;
+    Ok(row.and_then(ItemRow::into_item))
+}
+
+pub fn items(conn: &mut PgConnection, campaign_id: &str) -> QueryResult<Vec<ReviewItem>> {
+    let rows: Vec<ItemRow> = sql_query(format!(
+        "SELECT {} FROM access_review_item WHERE campaign_id = $1 ORDER BY email_id",
+        ITEM_COLUMNS
+    ))
+    .bind::<Varchar, _>(campaign_id)
+    .load(conn)?;
+    Ok(rows.into_iter().filter_map(ItemRow::into_item).collect())
+}
+
+pub fn open_campaigns(conn: &mut PgConnection) -> QueryResult<Vec<ReviewCampaign>> {
+    let rows: Vec<CampaignRow> = sql_query(format!(
+        "SELECT {} FROM access_review WHERE status = $1 ORDER BY deadline, id",
+        CAMPAIGN_COLUMNS
+    ))
+    .bind::<Varchar, _>(CampaignStatus::Open.as_str())
+    .load(conn)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(CampaignRow::into_campaign)
+        .collect())
+}
@@ -0,0 +1,89 @@
+use crate::permissions::AdminRole;
+use chrono::{DateTime, Utc};
+use diesel::sql_types::{Timestamptz, Varchar};
+use diesel::{
+    sql_query, OptionalExtension, PgConnection, QueryResult, QueryableByName, RunQueryDsl,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Assignments live in the `admin_role` table, keyed by the admin's email, which the admin
+// schema does not render.
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RoleAssignment {
+    pub email_id: String,
+    pub role: AdminRole,
+    pub granted_by: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+#[derive(QueryableByName)]
+struct RoleAssignmentRow {
+    #[diesel(sql_type = Varchar)]
+    email_id: String,
+    #[diesel(sql_type = Varchar)]
+    role: String,
+    #[diesel(sql_type = Varchar)]
+    granted_by: String,
+    #[diesel(sql_type = Timestamptz)]
+    granted_at: DateTime<Utc>,
+}
+
+impl RoleAssignmentRow {
+    /// Rows with a role this version doesn't know are skipped.
+    fn into_assignment(self) -> Option<RoleAssignment> {
+        Some(RoleAssignment {
+            role: AdminRole::ALL
+                .into_iter()
+                .find(|role| role.as_str() == self.role)?,
+            email_id: self.email_id,
+            granted_by: self.granted_by,
+            granted_at: self.granted_at,
+        })
+    }
+}
+
+pub fn get(conn: &mut PgConnection, email: &str) -> QueryResult<Option<RoleAssignment>> {
+    let row: Option<RoleAssignmentRow> = sql_query(
+        "SELECT email_id, role, granted_by, granted_at FROM admin_role WHERE email_id = $1",
+    )
+    .bind::<Varchar, _>(email)
+    .get_result(conn)
+    .optional()?;
+    Ok(row.and_then(RoleAssignmentRow::into_assignment))
+}
+
+pub fn list(conn: &mut PgConnection) -> QueryResult<Vec<RoleAssignment>> {
+    let rows: Vec<RoleAssignmentRow> = sql_query(
+        "SELECT email_id, role, granted_by, granted_at FROM admin_role ORDER BY email_id",
+    )
+    .load(conn)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(RoleAssignmentRow::into_assignment)
+        .collect())
+}
+
+/// Assigns a role, replacing the admin's current one.
+pub fn save(conn: &mut PgConnection, assignment: &RoleAssignment) -> QueryResult<()> {
+    sql_query(
+        "INSERT INTO admin_role (email_id, role, granted_by, granted_at) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (email_id) DO UPDATE SET role = EXCLUDED.role, \
+         granted_by = EXCLUDED.granted_by, granted_at = EXCLUDED.granted_at",
+    )
+    .bind::<Varchar, _>(&assignment.email_id)
+    .bind::<Varchar, _>(assignment.role.as_str())
+    .bind::<Varchar, _>(&assignment.granted_by)
+    .bind::<Timestamptz, _>(assignment.granted_at)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Removes an assignment, returning false if there was none.
+pub fn remove(conn: &mut PgConnection, email: &str) -> QueryResult<bool> {
+    let removed = sql_query("DELETE FROM admin_role WHERE email_id = $1")
+        .bind::<Varchar, _>(email)
+        .execute(conn)?;
+    Ok(removed == 1)
+}
@@ -0,0 +1,195 @@
+use crate::db::redis::random_key;
+use chrono::{DateTime, Duration, Utc};
+use diesel::sql_types::{Array, Nullable, Timestamptz, Varchar};
+use diesel::{
+    sql_query, OptionalExtension, PgConnection, QueryResult, QueryableByName, RunQueryDsl,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Keys live in the `api_key` table, which the admin schema does not render.
+
+// Keys are `gk_<id>_<secret>`, the id lets a key be found without storing the secret
+const KEY_PREFIX: &str = "gk_";
+const SECRET_LENGTH: usize = 40;
+
+/// Scopes a key can be granted. Signed-in admins and ISC tokens implicitly hold all of them.
+pub const KNOWN_SCOPES: &[&str] = &["users:read", "users:exists", "groups:read", "groups:exists"];
+
+/// A named credential for another service. Only a hash of the secret is kept.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub secret_hash: String,
+    /// The secret it was rotated away from, accepted until `previous_valid_until`
+    pub previous_secret_hash: Option<String>,
+    pub previous_valid_until: Option<DateTime<Utc>>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+    }
+
+    /// Replaces the secret, returning the new plaintext key. The old one keeps working for
+    /// `grace` so callers can roll over.
+    pub fn rotate(&mut self, grace: Duration) -> String {
+        let (key, secret_hash) = new_secret(&self.id);
+        let now = Utc::now();
+        self.previous_secret_hash = Some(std::mem::replace(&mut self.secret_hash, secret_hash));
+        self.previous_valid_until = Some(now + grace);
+        self.rotated_at = Some(now);
+        key
+    }
+
+    pub(crate) fn accepts(&self, secret: &str) -> bool {
+        let presented = hash_secret(secret);
+        if presented == self.secret_hash {
+            return true;
+        }
+        match (&self.previous_secret_hash, self.previous_valid_until) {
+            (Some(previous), Some(valid_until)) => {
+                presented == *previous && Utc::now() < valid_until
+            }
+            _ => false,
+        }
+    }
+}
+
+// Secrets are long random strings, so a plain SHA-256 is enough to keep them safe at rest
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn new_secret(key_id: &str) -> (String, String) {
+    let secret = random_key(SECRET_LENGTH);
+    let hash = hash_secret(&secret);
+    (format!("{}{}_{}", KEY_PREFIX, key_id, secret), hash)
+}
+
+/// Builds a key and returns it with its plaintext value, which is only shown once.
+pub fn generate(name: &str, scopes: Vec<String>, created_by: &str) -> (ApiKey, String) {
+    let id = random_key(12);
+    let (key, secret_hash) = new_secret(&id);
+    (
+        ApiKey {
+            id,
+            name: name.to_string(),
+            scopes,
+            secret_hash,
+            previous_secret_hash: None,
+            previous_valid_until: None,
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            rotated_at: None,
+            revoked_at: None,
+        },
+        key,
+    )
+}
+
+/// Splits a presented key into its id and secret.
+pub fn parse(presented: &str) -> Option<(&str, &str)> {
+    presented.strip_prefix(KEY_PREFIX)?.split_once('_')
+}
+
+#[derive(QueryableByName)]
+struct ApiKeyRow {
+    #[diesel(sql_type = Varchar)]
+    id: String,
+    #[diesel(sql_type = Varchar)]
+    name: String,
+    #[diesel(sql_type = Array<Varchar>)]
+    scopes: Vec<String>,
+    #[diesel(sql_type = Varchar)]
+    secret_hash: String,
+    #[diesel(sql_type = Nullable<Varchar>)]
+    previous_secret_hash: Option<String>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    previous_valid_until: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Varchar)]
+    created_by: String,
+    #[diesel(sql_type = Timestamptz)]
+    created_at: DateTime<Utc>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    rotated_at: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id,
+            name: row.name,
+            scopes: row.scopes,
+            secret_hash: row.secret_hash,
+            previous_secret_hash: row.previous_secret_hash,
+            previous_valid_until: row.previous_valid_until,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            rotated_at: row.rotated_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+const KEY_COLUMNS: &str = "id, name, scopes, secret_hash, previous_secret_hash, \
+    previous_valid_until, created_by, created_at, rotated_at, revoked_at";
+
+/// Inserts a key or updates the one with the same id.
+pub fn save(conn: &mut PgConnection, api_key: &ApiKey) -> QueryResult<()> {
+    sql_query(format!(
+        "INSERT INTO api_key ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+         ON CONFLICT (id) DO UPDATE SET scopes = EXCLUDED.scopes, \
+         secret_hash = EXCLUDED.secret_hash, previous_secret_hash = EXCLUDED.previous_secret_hash, \
+         previous_valid_until = EXCLUDED.previous_valid_until, rotated_at = EXCLUDED.rotated_at, \
+         revoked_at = EXCLUDED.revoked_at",
+        KEY_COLUMNS
+    ))
+    .bind::<Varchar, _>(&api_key.id)
+    .bind::<Varchar, _>(&api_key.name)
+    .bind::<Array<Varchar>, _>(&api_key.scopes)
+    .bind::<Varchar, _>(&api_key.secret_hash)
+    .bind::<Nullable<Varchar>, _>(&api_key.previous_secret_hash)
+    .bind::<Nullable<Timestamptz>, _>(api_key.previous_valid_until)
+    .bind::<Varchar, _>(&api_key.created_by)
+    .bind::<Timestamptz, _>(api_key.created_at)
+    .bind::<Nullable<Timestamptz>, _>(api_key.rotated_at)
+    .bind::<Nullable<Timestamptz>, _>(api_key.revoked_at)
+    .execute(conn)?;
+    Ok(())
+}
+
+pub fn get(conn: &mut PgConnection, key_id: &str) -> QueryResult<Option<ApiKey>> {
+    let row: Option<ApiKeyRow> =
+        sql_query(format!("SELECT {} FROM api_key WHERE id = $1", KEY_COLUMNS))
+            .bind::<Varchar, _>(key_id)
+            .get_result(conn)
+            .optional()?;
+    Ok(row.map(ApiKey::from))
+}
+
+pub fn list(conn: &mut PgConnection) -> QueryResult<Vec<ApiKey>> {
+    let rows: Vec<ApiKeyRow> = sql_query(format!(
+        "SELECT {} FROM api_key ORDER BY created_at, id",
+        KEY_COLUMNS
+    ))
+    .load(conn)?;
+    Ok(rows.into_iter().map(ApiKey::from).collect())
+}
+
+/// Finds the active key a presented value belongs to.
+pub fn authenticate(conn: &mut PgConnection, presented: &str) -> QueryResult<Option<ApiKey>> {
+    let Some((key_id, secret)) = parse(presented) else {
+        return Ok(None);
+    };
+    Ok(get(conn, key_id)?.filter(|api_key| api_key.is_active() && api_key.accepts(secret)))
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use diesel::sql_types::{BigInt, Jsonb, Timestamptz, Varchar};
+use diesel::{sql_query, PgConnection, QueryResult, QueryableByName, RunQueryDsl};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Events live in the `audit_event` table, which the admin schema does not render. Nothing
+// trims it, so security events outlive a Redis flush and stay queryable for as long as the
+// database keeps them.
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEvent {
+    pub action: String,
+    pub actor: String,
+    pub subject: String,
+    pub details: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &str, actor: &str, subject: &str, details: Value) -> Self {
+        AuditEvent {
+            action: action.to_string(),
+            actor: actor.to_string(),
+            subject: subject.to_string(),
+            details,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct AuditEventRow {
+    #[diesel(sql_type = Varchar)]
+    action: String,
+    #[diesel(sql_type = Varchar)]
+    actor: String,
+    #[diesel(sql_type = Varchar)]
+    subject: String,
+    #[diesel(sql_type = Jsonb)]
+    details: Value,
+    #[diesel(sql_type = Timestamptz)]
+    created_at: DateTime<Utc>,
+}
+
+impl From<AuditEventRow> for AuditEvent {
+    fn from(row: AuditEventRow) -> Self {
+        AuditEvent {
+            action: row.action,
+            actor: row.actor,
+            subject: row.subject,
+            details: row.details,
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub fn record_event(conn: &mut PgConnection, event: &AuditEvent) -> QueryResult<()> {
+    sql_query(
+        "INSERT INTO audit_event (action, actor, subject, details, created_at) \
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind::<Varchar, _>(&event.action)
+    .bind::<Varchar, _>(&event.actor)
+    .bind::<Varchar, _>(&event.subject)
+    .bind::<Jsonb, _>(&event.details)
+    .bind::<Timestamptz, _>(event.created_at)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Records an event, logging instead of failing the caller when the insert fails.
+pub fn record(conn: &mut PgConnection, event: AuditEvent) {
+    if let Err(err) = record_event(conn, &event) {
+        println!("Failed to record audit event {}: {:?}", event.action, err);
+    }
+}
+
+pub fn recent_events_for(
+    conn: &mut PgConnection,
+    subject: &str,
+    limit: i64,
+) -> QueryResult<Vec<AuditEvent>> {
+    let rows: Vec<AuditEventRow> = sql_query(
+        "SELECT action, actor, subject, details, created_at FROM audit_event \
+         WHERE subject = $1 ORDER BY created_at DESC, id DESC LIMIT $2",
+    )
+    .bind::<Varchar, _>(subject)
+    .bind::<BigInt, _>(limit)
+    .load(conn)?;
+    Ok(rows.into_iter().map(AuditEvent::from).collect())
+}
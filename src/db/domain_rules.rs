@@ -0,0 +1,173 @@
+use crate::validation::{domain_matches, email_domain};
+use chrono::{DateTime, Utc};
+use diesel::sql_types::{Bool, Jsonb, Timestamptz, Varchar};
+use diesel::{
+    sql_query, OptionalExtension, PgConnection, QueryResult, QueryableByName, RunQueryDsl,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Rules live in the `domain_rule` table, which the admin schema does not render, with
+// the action stored as JSON in the same shape the API uses.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DomainRuleAction {
+    /// Users with a matching email join the group
+    JoinGroup { group_identifier: String },
+    /// The app only accepts registrations from the rule's domains. Several rules for the
+    /// same app allow any of their domains.
+    RestrictRegistration { app_client_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DomainRule {
+    pub id: String,
+    /// Matches the domain and its subdomains, e.g. `acme.com` covers `eu.acme.com`
+    pub domain: String,
+    pub action: DomainRuleAction,
+    pub enabled: bool,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DomainRule {
+    pub fn matches(&self, email: &str) -> bool {
+        domain_matches(&email_domain(email).to_lowercase(), &self.domain)
+    }
+}
+
+/// What the rules say about an email, optionally for registering with an app.
+#[derive(Debug, Default, PartialEq, Serialize, JsonSchema)]
+pub struct DomainRuleEvaluation {
+    pub registration_allowed: bool,
+    /// Groups the user joins through `join_group` rules
+    pub group_identifiers: Vec<String>,
+    pub matched_rule_ids: Vec<String>,
+}
+
+/// Applies the enabled rules to an email. Registration is only restricted when an app is
+/// given and it has `restrict_registration` rules.
+pub fn evaluate(
+    rules: &[DomainRule],
+    email: &str,
+    app_client_id: Option<&str>,
+) -> DomainRuleEvaluation {
+    let mut evaluation = DomainRuleEvaluation::default();
+    let mut restricted = false;
+    let mut allowed = false;
+
+    for rule in rules.iter().filter(|rule| rule.enabled) {
+        let matched = rule.matches(email);
+        match &rule.action {
+            DomainRuleAction::JoinGroup { group_identifier } => {
+                if matched && !evaluation.group_identifiers.contains(group_identifier) {
+                    evaluation.group_identifiers.push(group_identifier.clone());
+                }
+            }
+            DomainRuleAction::RestrictRegistration {
+                app_client_id: restricted_app,
+            } => {
+                if app_client_id != Some(restricted_app.as_str()) {
+                    continue;
+                }
+                restricted = true;
+                allowed |= matched;
+            }
+        }
+        if matched {
+            evaluation.matched_rule_ids.push(rule.id.clone());
+        }
+    }
+
+    evaluation.registration_allowed = !restricted || allowed;
+    evaluation
+}
+
+#[derive(QueryableByName)]
+struct DomainRuleRow {
+    #[diesel(sql_type = Varchar)]
+    id: String,
+    #[diesel(sql_type = Varchar)]
+    domain: String,
+    #[diesel(sql_type = Jsonb)]
+    action: serde_json::Value,
+    #[diesel(sql_type = Bool)]
+    enabled: bool,
+    #[diesel(sql_type = Varchar)]
+    created_by: String,
+    #[diesel(sql_type = Timestamptz)]
+    created_at: DateTime<Utc>,
+    #[diesel(sql_type = Timestamptz)]
+    updated_at: DateTime<Utc>,
+}
+
+impl DomainRuleRow {
+    /// Rows whose action this version doesn't know are skipped.
+    fn into_rule(self) -> Option<DomainRule> {
+        Some(DomainRule {
+            id: self.id,
+            domain: self.domain,
+            action: serde_json::from_value(self.action).ok()?,
+            enabled: self.enabled,
+            created_by: self.created_by,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+const RULE_COLUMNS: &str = "id, domain, action, enabled, created_by, created_at, updated_at";
+
+pub fn list(conn: &mut PgConnection) -> QueryResult<Vec<DomainRule>> {
+    let rows: Vec<DomainRuleRow> = sql_query(format!(
+        "SELECT {} FROM domain_rule ORDER BY created_at, id",
+        RULE_COLUMNS
+    ))
+    .load(conn)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(DomainRuleRow::into_rule)
+        .collect())
+}
+
+pub fn get(conn: &mut PgConnection, rule_id: &str) -> QueryResult<Option<DomainRule>> {
+    let row: Option<DomainRuleRow> = sql_query(format!(
+        "SELECT {} FROM domain_rule WHERE id = $1",
+        RULE_COLUMNS
+    ))
+    .bind::<Varchar, _>(rule_id)
+    .get_result(conn)
+    .optional()?;
+    Ok(row.and_then(DomainRuleRow::into_rule))
+}
+
+/// Inserts a rule or updates the one with the same id.
+pub fn save(conn: &mut PgConnection, rule: &DomainRule) -> QueryResult<()> {
+    let action = serde_json::to_value(&rule.action).unwrap_or_default();
+
+    sql_query(
+        "INSERT INTO domain_rule (id, domain, action, enabled, created_by, created_at, updated_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) \
+         ON CONFLICT (id) DO UPDATE SET domain = EXCLUDED.domain, action = EXCLUDED.action, \
+         enabled = EXCLUDED.enabled, updated_at = EXCLUDED.updated_at",
+    )
+    .bind::<Varchar, _>(&rule.id)
+    .bind::<Varchar, _>(&rule.domain)
+    .bind::<Jsonb, _>(action)
+    .bind::<Bool, _>(rule.enabled)
+    .bind::<Varchar, _>(&rule.created_by)
+    .bind::<Timestamptz, _>(rule.created_at)
+    .bind::<Timestamptz, _>(rule.updated_at)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Deletes a rule, returning false if there was none.
+pub fn delete(conn: &mut PgConnection, rule_id: &str) -> QueryResult<bool> {
+    let deleted = sql_query("DELETE FROM domain_rule WHERE id = $1")
+        .bind::<Varchar, _>(rule_id)
+        .execute(conn)?;
+    Ok(deleted == 1)
+}
@@ -0,0 +1,122 @@
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use rocket_prometheus::prometheus::{IntCounterVec, Opts, Registry};
+use std::env;
+
+// Group lists from the IAM service are cached per user for the guards. Entries are
+// dropped whenever this service changes a membership, the TTL covers changes made
+// elsewhere.
+const DEFAULT_TTL_SECONDS: usize = 60;
+
+#[derive(Debug, Clone, Copy)]
+pub enum GroupListKind {
+    Memberships,
+    Ownerships,
+}
+
+impl GroupListKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            GroupListKind::Memberships => "memberships",
+            GroupListKind::Ownerships => "ownerships",
+        }
+    }
+}
+
+pub(crate) fn cache_key(kind: GroupListKind, user_id: &str) -> String {
+    format!("group_cache:{}:{}", kind.as_str(), user_id)
+}
+
+fn ttl_seconds() -> usize {
+    env::var("GROUP_CACHE_TTL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECONDS)
+}
+
+pub fn get(
+    conn: &mut redis::Connection,
+    kind: GroupListKind,
+    user_id: &str,
+) -> RedisResult<Option<Vec<String>>> {
+    let raw: Option<String> = conn.get(cache_key(kind, user_id))?;
+    Ok(raw.and_then(|raw| decode(&raw)))
+}
+
+pub fn put(
+    conn: &mut redis::Connection,
+    kind: GroupListKind,
+    user_id: &str,
+    groups: &[String],
+) -> RedisResult<()> {
+    conn.set_ex(cache_key(kind, user_id), encode(groups), ttl_seconds())
+}
+
+pub(crate) fn encode(groups: &[String]) -> String {
+    serde_json::to_string(groups).unwrap_or_default()
+}
+
+/// An entry that doesn't parse counts as a miss.
+pub(crate) fn decode(raw: &str) -> Option<Vec<String>> {
+    serde_json::from_str(raw).ok()
+}
+
+/// Keys holding the cached group lists of the given users.
+pub(crate) fn invalidation_keys(user_ids: &[i64]) -> Vec<String> {
+    user_ids
+        .iter()
+        .flat_map(|user_id| {
+            [GroupListKind::Memberships, GroupListKind::Ownerships]
+                .map(|kind| cache_key(kind, &user_id.to_string()))
+        })
+        .collect()
+}
+
+/// Drops the cached group lists of the given users.
+pub fn invalidate(conn: &mut redis::Connection, user_ids: &[i64]) -> RedisResult<()> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    redis::cmd("DEL")
+        .arg(&invalidation_keys(user_ids)[..])
+        .query(conn)
+}
+
+/// Same as `invalidate`, for callers that can't do anything about a failure. A stale
+/// entry only lives until its TTL runs out.
+pub fn invalidate_quietly(conn: &mut redis::Connection, user_ids: &[i64]) {
+    if let Err(err) = invalidate(conn, user_ids) {
+        println!("Failed to invalidate cached group lists: {}", err);
+    }
+}
+
+/// Hit and miss counters for the group list cache.
+pub struct GroupCacheMetrics {
+    lookups: IntCounterVec,
+}
+
+impl GroupCacheMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        let lookups = IntCounterVec::new(
+            Opts::new(
+                "group_cache_lookups_total",
+                "Group list cache lookups by kind and result",
+            ),
+            &["kind", "result"],
+        )
+        .expect("valid group cache metric");
+
+        if let Err(err) = registry.register(Box::new(lookups.clone())) {
+            println!("Failed to register group cache metric: {}", err);
+        }
+
+        GroupCacheMetrics { lookups }
+    }
+
+    pub fn record(&self, kind: GroupListKind, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.lookups
+            .with_label_values(&[kind.as_str(), result])
+            .inc();
+    }
+}
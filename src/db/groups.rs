@@ -0,0 +1,238 @@
+use diesel::sql_types::{Array, BigInt, Bool, Integer, Varchar};
+use diesel::{sql_query, Connection, PgConnection, QueryResult, QueryableByName, RunQueryDsl};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Membership and ownership live in the join tables generated for the `group`
+// model's many-to-many fields, which the admin schema does not render.
+#[derive(Debug, Clone, PartialEq, QueryableByName, Serialize, Deserialize, JsonSchema)]
+pub struct GroupRef {
+    #[diesel(sql_type = BigInt)]
+    pub id: i64,
+    #[diesel(sql_type = Varchar)]
+    pub identifier: String,
+}
+
+pub fn groups_for_user(conn: &mut PgConnection, user_id: i64) -> QueryResult<Vec<GroupRef>> {
+    sql_query(
+        "SELECT g.id, g.identifier FROM \"group\" g \
+         JOIN group_users gu ON gu.group_id = g.id \
+         WHERE gu.user_id = $1 ORDER BY g.identifier",
+    )
+    .bind::<BigInt, _>(user_id)
+    .load(conn)
+}
+
+pub fn groups_owned_by_user(conn: &mut PgConnection, user_id: i64) -> QueryResult<Vec<GroupRef>> {
+    sql_query(
+        "SELECT g.id, g.identifier FROM \"group\" g \
+         JOIN group_owners go ON go.group_id = g.id \
+         WHERE go.user_id = $1 ORDER BY g.identifier",
+    )
+    .bind::<BigInt, _>(user_id)
+    .load(conn)
+}
+
+#[derive(QueryableByName)]
+struct UserIdRow {
+    #[diesel(sql_type = BigInt)]
+    user_id: i64,
+}
+
+pub fn owner_ids(conn: &mut PgConnection, group_id: i64) -> QueryResult<Vec<i64>> {
+    let rows: Vec<UserIdRow> = sql_query("SELECT user_id FROM group_owners WHERE group_id = $1")
+        .bind::<BigInt, _>(group_id)
+        .load(conn)?;
+    Ok(rows.into_iter().map(|row| row.user_id).collect())
+}
+
+// Nesting is stored as parent/child edges; members of a child group are
+// effective members of every ancestor.
+const MAX_NESTING_DEPTH: i32 = 32;
+
+#[derive(Debug, Clone, QueryableByName, Serialize, JsonSchema)]
+pub struct EffectiveMember {
+    #[diesel(sql_type = BigInt)]
+    pub user_id: i64,
+    /// Group the membership was granted on
+    #[diesel(sql_type = BigInt)]
+    pub via_group_id: i64,
+    #[diesel(sql_type = Varchar)]
+    pub via_group: String,
+    /// 0 for direct members, otherwise how many levels down the granting group is
+    #[diesel(sql_type = Integer)]
+    pub depth: i32,
+}
+
+#[derive(QueryableByName)]
+struct ExistsRow {
+    #[diesel(sql_type = Bool)]
+    found: bool,
+}
+
+pub fn group_by_identifier(conn: &mut PgConnection, identifier: &str) -> QueryResult<GroupRef> {
+    sql_query("SELECT id, identifier FROM \"group\" WHERE identifier = $1")
+        .bind::<Varchar, _>(identifier)
+        .get_result(conn)
+}
+
+pub fn groups_by_identifiers(
+    conn: &mut PgConnection,
+    identifiers: &[String],
+) -> QueryResult<Vec<GroupRef>> {
+    sql_query("SELECT id, identifier FROM \"group\" WHERE identifier = ANY($1) ORDER BY identifier")
+        .bind::<Array<Varchar>, _>(identifiers)
+        .load(conn)
+}
+
+pub fn groups_by_ids(conn: &mut PgConnection, group_ids: &[i64]) -> QueryResult<Vec<GroupRef>> {
+    sql_query("SELECT id, identifier FROM \"group\" WHERE id = ANY($1) ORDER BY identifier")
+        .bind::<Array<BigInt>, _>(group_ids)
+        .load(conn)
+}
+
+pub fn child_groups(conn: &mut PgConnection, parent_id: i64) -> QueryResult<Vec<GroupRef>> {
+    sql_query(
+        "SELECT g.id, g.identifier FROM \"group\" g \
+         JOIN group_nesting gn ON gn.child_id = g.id \
+         WHERE gn.parent_id = $1 ORDER BY g.identifier",
+    )
+    .bind::<BigInt, _>(parent_id)
+    .load(conn)
+}
+
+/// True when `ancestor_id` can be reached by walking down from `group_id`.
+fn is_descendant(conn: &mut PgConnection, group_id: i64, ancestor_id: i64) -> QueryResult<bool> {
+    let row: ExistsRow = sql_query(
+        "WITH RECURSIVE descendants(id, depth) AS ( \
+             SELECT $1::bigint, 0 \
+             UNION \
+             SELECT gn.child_id, d.depth + 1 FROM group_nesting gn \
+             JOIN descendants d ON gn.parent_id = d.id WHERE d.depth < $3 \
+         ) SELECT EXISTS (SELECT 1 FROM descendants WHERE id = $2) AS found",
+    )
+    .bind::<BigInt, _>(group_id)
+    .bind::<BigInt, _>(ancestor_id)
+    .bind::<Integer, _>(MAX_NESTING_DEPTH)
+    .get_result(conn)?;
+    Ok(row.found)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NestingError {
+    Cycle,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for NestingError {
+    fn from(err: diesel::result::Error) -> Self {
+        NestingError::Database(err)
+    }
+}
+
+/// Nests `child_id` under `parent_id`, rejecting edges that would close a cycle.
+pub fn add_child_group(
+    conn: &mut PgConnection,
+    parent_id: i64,
+    child_id: i64,
+) -> Result<(), NestingError> {
+    conn.transaction(|conn| {
+        // Serialise concurrent nesting changes so two inserts cannot form a cycle together
+        sql_query("LOCK TABLE group_nesting IN SHARE ROW EXCLUSIVE MODE").execute(conn)?;
+
+        if parent_id == child_id || is_descendant(conn, child_id, parent_id)? {
+            return Err(NestingError::Cycle);
+        }
+
+        sql_query(
+            "INSERT INTO group_nesting (parent_id, child_id) VALUES ($1, $2) \
+             ON CONFLICT DO NOTHING",
+        )
+        .bind::<BigInt, _>(parent_id)
+        .bind::<BigInt, _>(child_id)
+        .execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn remove_child_group(
+    conn: &mut PgConnection,
+    parent_id: i64,
+    child_id: i64,
+) -> QueryResult<usize> {
+    sql_query("DELETE FROM group_nesting WHERE parent_id = $1 AND child_id = $2")
+        .bind::<BigInt, _>(parent_id)
+        .bind::<BigInt, _>(child_id)
+        .execute(conn)
+}
+
+/// Direct and inherited members of a group, keeping the shallowest path per user.
+pub fn effective_members(
+    conn: &mut PgConnection,
+    group_id: i64,
+) -> QueryResult<Vec<EffectiveMember>> {
+    sql_query(
+        "WITH RECURSIVE descendants(id, depth) AS ( \
+             SELECT $1::bigint, 0 \
+             UNION \
+             SELECT gn.child_id, d.depth + 1 FROM group_nesting gn \
+             JOIN descendants d ON gn.parent_id = d.id WHERE d.depth < $2 \
+         ) \
+         SELECT DISTINCT ON (gu.user_id) gu.user_id, g.id AS via_group_id, \
+                g.identifier AS via_group, d.depth \
+         FROM descendants d \
+         JOIN group_users gu ON gu.group_id = d.id \
+         JOIN \"group\" g ON g.id = d.id \
+         ORDER BY gu.user_id, d.depth",
+    )
+    .bind::<BigInt, _>(group_id)
+    .bind::<Integer, _>(MAX_NESTING_DEPTH)
+    .load(conn)
+}
+
+/// Every group the user belongs to, directly or through a nested child group.
+pub fn effective_groups_for_user(
+    conn: &mut PgConnection,
+    user_id: i64,
+) -> QueryResult<Vec<GroupRef>> {
+    sql_query(
+        "WITH RECURSIVE ancestors(id, depth) AS ( \
+             SELECT group_id, 0 FROM group_users WHERE user_id = $1 \
+             UNION \
+             SELECT gn.parent_id, a.depth + 1 FROM group_nesting gn \
+             JOIN ancestors a ON gn.child_id = a.id WHERE a.depth < $2 \
+         ) \
+         SELECT DISTINCT g.id, g.identifier FROM ancestors a \
+         JOIN \"group\" g ON g.id = a.id ORDER BY g.identifier",
+    )
+    .bind::<BigInt, _>(user_id)
+    .bind::<Integer, _>(MAX_NESTING_DEPTH)
+    .load(conn)
+}
+
+pub fn is_direct_member(conn: &mut PgConnection, group_id: i64, user_id: i64) -> QueryResult<bool> {
+    let row: ExistsRow = sql_query(
+        "SELECT EXISTS (SELECT 1 FROM group_users WHERE group_id = $1 AND user_id = $2) AS found",
+    )
+    .bind::<BigInt, _>(group_id)
+    .bind::<BigInt, _>(user_id)
+    .get_result(conn)?;
+    Ok(row.found)
+}
+
+pub fn add_member(conn: &mut PgConnection, group_id: i64, user_id: i64) -> QueryResult<usize> {
+    sql_query(
+        "INSERT INTO group_users (group_id, user_id) SELECT $1, $2 \
+         WHERE NOT EXISTS (SELECT 1 FROM group_users WHERE group_id = $1 AND user_id = $2)",
+    )
+    .bind::<BigInt, _>(group_id)
+    .bind::<BigInt, _>(user_id)
+    .execute(conn)
+}
+
+pub fn remove_member(conn: &mut PgConnection, group_id: i64, user_id: i64) -> QueryResult<usize> {
+    sql_query("DELETE FROM group_users WHERE group_id = $1 AND user_id = $2")
+        .bind::<BigInt, _>(group_id)
+        .bind::<BigInt, _>(user_id)
+        .execute(conn)
+}
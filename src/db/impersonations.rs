@@ -0,0 +1,95 @@
+use crate::db::redis::random_key;
+use chrono::{DateTime, Duration, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Sessions expire from Redis with their token. `ACTIVE_SET` is scored by expiry in
+// seconds so the live ones can be listed without scanning.
+const ACTIVE_SET: &str = "impersonations:active";
+
+/// A root admin acting as another user in one app, for as long as the token lives.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImpersonationSession {
+    pub id: String,
+    pub admin_email: String,
+    pub target_email: String,
+    pub app_client_id: String,
+    pub reason: String,
+    pub read_only: bool,
+    pub user_notified: bool,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ImpersonationSession {
+    pub fn new(
+        admin_email: &str,
+        target_email: &str,
+        app_client_id: &str,
+        reason: &str,
+        read_only: bool,
+        duration: Duration,
+    ) -> Self {
+        let started_at = Utc::now();
+        ImpersonationSession {
+            id: random_key(16),
+            admin_email: admin_email.to_string(),
+            target_email: target_email.to_string(),
+            app_client_id: app_client_id.to_string(),
+            reason: reason.to_string(),
+            read_only,
+            user_notified: false,
+            started_at,
+            expires_at: started_at + duration,
+        }
+    }
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("impersonation:{}", session_id)
+}
+
+/// Adds the session to a pipeline so it is stored together with its audit trail.
+pub fn queue_session(pipe: &mut redis::Pipeline, session: &ImpersonationSession) {
+    let payload = serde_json::to_string(session).unwrap_or_default();
+    let ttl = (session.expires_at - Utc::now()).num_seconds().max(1) as usize;
+
+    pipe.set_ex(session_key(&session.id), payload, ttl)
+        .ignore()
+        .zadd(ACTIVE_SET, &session.id, session.expires_at.timestamp())
+        .ignore();
+}
+
+pub fn get(
+    conn: &mut redis::Connection,
+    session_id: &str,
+) -> RedisResult<Option<ImpersonationSession>> {
+    let raw: Option<String> = conn.get(session_key(session_id))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Sessions whose token has not expired yet, newest first.
+pub fn list_active(conn: &mut redis::Connection) -> RedisResult<Vec<ImpersonationSession>> {
+    let now = Utc::now().timestamp();
+    let _: () = conn.zrembyscore(ACTIVE_SET, "-inf", now)?;
+
+    let ids: Vec<String> = conn.zrevrangebyscore(ACTIVE_SET, "+inf", now)?;
+    let mut sessions = Vec::new();
+    for session_id in ids {
+        if let Some(session) = get(conn, &session_id)? {
+            sessions.push(session);
+        }
+    }
+    Ok(sessions)
+}
+
+/// Ends a session before its token expires. Returns whether it was still active.
+pub fn end(conn: &mut redis::Connection, session_id: &str) -> RedisResult<bool> {
+    let (removed, _): (u32, u32) = redis::pipe()
+        .atomic()
+        .del(session_key(session_id))
+        .zrem(ACTIVE_SET, session_id)
+        .query(conn)?;
+    Ok(removed > 0)
+}
@@ -0,0 +1,334 @@
+use crate::models::request::InviteRequest;
+use chrono::{DateTime, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const INVITE_HISTORY_LIMIT: isize = 50;
+// Emails with an outstanding invite, scored by expiry, for listing pending invites
+const OPEN_SET: &str = "invite:open";
+// Invite ids with follow-up work, scored by when it is next due
+const FOLLOW_UP_SET: &str = "invite:follow_ups";
+const INVITE_EVENTS_LIMIT: isize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InviteHistoryEntry {
+    #[serde(default)]
+    pub invite_id: String,
+    pub email_id: String,
+    pub invited_by: String,
+    pub is_root: bool,
+    #[serde(default)]
+    pub app_client_id: Option<String>,
+    #[serde(default)]
+    pub group_identifiers: Vec<String>,
+    #[serde(default)]
+    pub app_client_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// What happened to the invite after it was sent, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<InviteEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InviteEventKind {
+    Reminded,
+    Redeemed,
+    Expired,
+    /// Invalidated by a forced re-issue
+    Replaced,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InviteEvent {
+    pub kind: InviteEventKind,
+    pub at: DateTime<Utc>,
+    /// Who was emailed about it, if anyone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notified: Option<String>,
+}
+
+impl InviteEvent {
+    pub fn new(kind: InviteEventKind, notified: Option<&str>) -> Self {
+        InviteEvent {
+            kind,
+            at: Utc::now(),
+            notified: notified.map(str::to_string),
+        }
+    }
+}
+
+/// An invite waiting to be redeemed, stored under `invite:{token_hash}` until it expires.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingInvite {
+    pub invite_id: String,
+    pub token_hash: String,
+    pub invited_by: String,
+    #[serde(flatten)]
+    pub invite: InviteRequest,
+}
+
+/// Points from an email to its outstanding invite, so duplicates can be detected without
+/// knowing the token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingInviteRef {
+    pub invite_id: String,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    /// Tokens from earlier emails for the same invite, still valid until it is redeemed
+    #[serde(default)]
+    pub previous_token_hashes: Vec<String>,
+}
+
+impl PendingInviteRef {
+    fn token_keys(&self) -> Vec<String> {
+        std::iter::once(&self.token_hash)
+            .chain(&self.previous_token_hashes)
+            .map(|token_hash| pending_key(token_hash))
+            .collect()
+    }
+}
+
+/// Follow-up work for an outstanding invite: a reminder to the invitee and, if it is never
+/// redeemed, a notice to whoever sent it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteFollowUp {
+    pub invite_id: String,
+    pub email_id: String,
+    pub invited_by: String,
+    pub expires_at: DateTime<Utc>,
+    /// Cleared once the reminder has gone out
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+fn pending_key(token_hash: &str) -> String {
+    format!("invite:{}", token_hash)
+}
+
+fn pending_email_key(email: &str) -> String {
+    format!("invite:pending:{}", email)
+}
+
+fn follow_up_key(invite_id: &str) -> String {
+    format!("invite:follow_up:{}", invite_id)
+}
+
+fn events_key(invite_id: &str) -> String {
+    format!("invite:events:{}", invite_id)
+}
+
+fn history_key(email: &str) -> String {
+    format!("invite:history:{}", email)
+}
+
+pub fn queue_history(pipe: &mut redis::Pipeline, entry: &InviteHistoryEntry) {
+    let payload = serde_json::to_string(entry).unwrap_or_default();
+    let key = history_key(&entry.email_id);
+
+    pipe.lpush(&key, payload)
+        .ignore()
+        .ltrim(&key, 0, INVITE_HISTORY_LIMIT - 1)
+        .ignore();
+}
+
+pub fn queue_pending(
+    pipe: &mut redis::Pipeline,
+    pending: &PendingInvite,
+    index: &PendingInviteRef,
+    ttl_seconds: usize,
+) {
+    let payload = serde_json::to_string(pending).unwrap_or_default();
+    let index_payload = serde_json::to_string(index).unwrap_or_default();
+
+    pipe.set_ex(pending_key(&pending.token_hash), payload, ttl_seconds)
+        .ignore()
+        .set_ex(
+            pending_email_key(&pending.invite.email_id),
+            index_payload,
+            ttl_seconds,
+        )
+        .ignore()
+        .zadd(
+            OPEN_SET,
+            &pending.invite.email_id,
+            index.expires_at.timestamp(),
+        )
+        .ignore();
+}
+
+pub fn pending_for_email(
+    conn: &mut redis::Connection,
+    email: &str,
+) -> RedisResult<Option<PendingInviteRef>> {
+    let raw: Option<String> = conn.get(pending_email_key(email))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Adds the commands invalidating an outstanding invite to a pipeline.
+pub fn queue_revoke_pending(pipe: &mut redis::Pipeline, email: &str, pending: &PendingInviteRef) {
+    pipe.del(pending.token_keys())
+        .ignore()
+        .del(pending_email_key(email))
+        .ignore()
+        .zrem(OPEN_SET, email)
+        .ignore();
+    queue_cancel_follow_up(pipe, &pending.invite_id);
+}
+
+/// Starts watching an email's outstanding invite, so a following MULTI is discarded if the
+/// invite is redeemed or replaced in the meantime.
+pub fn watch_pending(conn: &mut redis::Connection, email: &str) -> RedisResult<()> {
+    redis::cmd("WATCH")
+        .arg(pending_email_key(email))
+        .query(conn)
+}
+
+pub fn get_pending(
+    conn: &mut redis::Connection,
+    token_hash: &str,
+) -> RedisResult<Option<PendingInvite>> {
+    let raw: Option<String> = conn.get(pending_key(token_hash))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Deletes a pending invite, returning false if it was already gone. Only the caller that
+/// gets `true` may treat the invite as redeemed.
+pub fn consume_pending(conn: &mut redis::Connection, pending: &PendingInvite) -> RedisResult<bool> {
+    let deleted: i32 = conn.del(pending_key(&pending.token_hash))?;
+    if deleted != 1 {
+        return Ok(false);
+    }
+
+    // The email index may already point at a newer invite
+    let email = &pending.invite.email_id;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    match pending_for_email(conn, email)? {
+        Some(index) if index.invite_id == pending.invite_id => {
+            queue_revoke_pending(&mut pipe, email, &index)
+        }
+        _ => queue_cancel_follow_up(&mut pipe, &pending.invite_id),
+    }
+    pipe.query::<()>(conn)?;
+    Ok(true)
+}
+
+/// Pages through outstanding invites, soonest to expire first.
+pub fn list_pending(
+    conn: &mut redis::Connection,
+    offset: isize,
+    limit: isize,
+) -> RedisResult<(usize, Vec<(PendingInviteRef, PendingInvite)>)> {
+    let _: () = conn.zrembyscore(OPEN_SET, "-inf", Utc::now().timestamp())?;
+    let total: usize = conn.zcard(OPEN_SET)?;
+    let emails: Vec<String> = conn.zrange(OPEN_SET, offset, offset + limit - 1)?;
+    if emails.is_empty() {
+        return Ok((total, Vec::new()));
+    }
+
+    let index_keys: Vec<String> = emails
+        .iter()
+        .map(|email| pending_email_key(email))
+        .collect();
+    let raw_refs: Vec<Option<String>> = redis::cmd("MGET").arg(&index_keys[..]).query(conn)?;
+    let refs: Vec<PendingInviteRef> = raw_refs
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| serde_json::from_str(&raw).ok())
+        .collect();
+    if refs.is_empty() {
+        return Ok((total, Vec::new()));
+    }
+
+    let invite_keys: Vec<String> = refs
+        .iter()
+        .map(|index| pending_key(&index.token_hash))
+        .collect();
+    let raw_invites: Vec<Option<String>> = redis::cmd("MGET").arg(&invite_keys[..]).query(conn)?;
+
+    Ok((
+        total,
+        refs.into_iter()
+            .zip(raw_invites)
+            .filter_map(|(index, raw)| {
+                let pending = serde_json::from_str(&raw?).ok()?;
+                Some((index, pending))
+            })
+            .collect(),
+    ))
+}
+
+pub fn queue_follow_up(pipe: &mut redis::Pipeline, follow_up: &InviteFollowUp) {
+    let payload = serde_json::to_string(follow_up).unwrap_or_default();
+    let due_at = follow_up.remind_at.unwrap_or(follow_up.expires_at);
+
+    pipe.set(follow_up_key(&follow_up.invite_id), payload)
+        .ignore()
+        .zadd(FOLLOW_UP_SET, &follow_up.invite_id, due_at.timestamp())
+        .ignore();
+}
+
+pub fn queue_cancel_follow_up(pipe: &mut redis::Pipeline, invite_id: &str) {
+    pipe.del(follow_up_key(invite_id))
+        .ignore()
+        .zrem(FOLLOW_UP_SET, invite_id)
+        .ignore();
+}
+
+pub fn due_follow_ups(
+    conn: &mut redis::Connection,
+    now: DateTime<Utc>,
+) -> RedisResult<Vec<InviteFollowUp>> {
+    let invite_ids: Vec<String> = conn.zrangebyscore(FOLLOW_UP_SET, "-inf", now.timestamp())?;
+    if invite_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let keys: Vec<String> = invite_ids.iter().map(|id| follow_up_key(id)).collect();
+    let raw: Vec<Option<String>> = redis::cmd("MGET").arg(&keys[..]).query(conn)?;
+    Ok(raw
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| serde_json::from_str(&raw).ok())
+        .collect())
+}
+
+pub fn queue_event(pipe: &mut redis::Pipeline, invite_id: &str, event: &InviteEvent) {
+    let payload = serde_json::to_string(event).unwrap_or_default();
+    let key = events_key(invite_id);
+
+    pipe.rpush(&key, payload)
+        .ignore()
+        .ltrim(&key, -INVITE_EVENTS_LIMIT, -1)
+        .ignore();
+}
+
+pub fn history_for(
+    conn: &mut redis::Connection,
+    email: &str,
+) -> RedisResult<Vec<InviteHistoryEntry>> {
+    let raw: Vec<String> = conn.lrange(history_key(email), 0, INVITE_HISTORY_LIMIT - 1)?;
+    let mut entries: Vec<InviteHistoryEntry> = raw
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect();
+    if entries.is_empty() {
+        return Ok(entries);
+    }
+
+    let mut pipe = redis::pipe();
+    for entry in &entries {
+        pipe.lrange(events_key(&entry.invite_id), 0, -1);
+    }
+    let events: Vec<Vec<String>> = pipe.query(conn)?;
+    for (entry, events) in entries.iter_mut().zip(events) {
+        entry.events = events
+            .iter()
+            .filter_map(|event| serde_json::from_str(event).ok())
+            .collect();
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,117 @@
+use chrono::{DateTime, TimeZone, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Locked accounts live in `LOCKS_HASH` keyed by email. A lock with `unlock_at` lifts
+// itself once that time passes; expired entries are dropped the next time locks are listed.
+// Failed logins are counted per user until a successful login or an unlock, or until
+// none has been recorded for the window.
+const LOCKS_HASH: &str = "account_locks";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AccountLock {
+    pub email_id: String,
+    pub reason: String,
+    /// Admin email, or `system` for locks after too many failed logins
+    pub locked_by: String,
+    pub locked_at: DateTime<Utc>,
+    /// Lifts itself at this time. Never when unset.
+    pub unlock_at: Option<DateTime<Utc>>,
+}
+
+impl AccountLock {
+    pub fn is_active(&self) -> bool {
+        self.unlock_at
+            .is_none_or(|unlock_at| Utc::now() < unlock_at)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FailedLogins {
+    pub count: u32,
+    pub last_failed_at: Option<DateTime<Utc>>,
+}
+
+fn lock_field(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn failed_logins_key(email: &str) -> String {
+    format!("failed_logins:{}", email.trim().to_lowercase())
+}
+
+pub fn lock(conn: &mut redis::Connection, account_lock: &AccountLock) -> RedisResult<()> {
+    let payload = serde_json::to_string(account_lock).unwrap_or_default();
+    conn.hset(LOCKS_HASH, lock_field(&account_lock.email_id), payload)
+}
+
+/// Lifts a lock and clears the failed login count. Returns whether a lock was lifted.
+pub fn unlock(conn: &mut redis::Connection, email: &str) -> RedisResult<bool> {
+    let (removed,): (usize,) = redis::pipe()
+        .atomic()
+        .hdel(LOCKS_HASH, lock_field(email))
+        .del(failed_logins_key(email))
+        .ignore()
+        .query(conn)?;
+    Ok(removed > 0)
+}
+
+/// The user's lock, if it is still in force.
+pub fn active_lock(conn: &mut redis::Connection, email: &str) -> RedisResult<Option<AccountLock>> {
+    let raw: Option<String> = conn.hget(LOCKS_HASH, lock_field(email))?;
+    Ok(raw
+        .and_then(|raw| serde_json::from_str::<AccountLock>(&raw).ok())
+        .filter(AccountLock::is_active))
+}
+
+/// Emails of every account currently locked.
+pub fn locked_emails(conn: &mut redis::Connection) -> RedisResult<Vec<String>> {
+    let entries: HashMap<String, String> = conn.hgetall(LOCKS_HASH)?;
+
+    let mut locked = Vec::new();
+    let mut expired = Vec::new();
+    for (email, raw) in entries {
+        match serde_json::from_str::<AccountLock>(&raw) {
+            Ok(account_lock) if account_lock.is_active() => locked.push(account_lock.email_id),
+            _ => expired.push(email),
+        }
+    }
+    if !expired.is_empty() {
+        let _: () = conn.hdel(LOCKS_HASH, expired)?;
+    }
+    Ok(locked)
+}
+
+pub fn failed_logins(conn: &mut redis::Connection, email: &str) -> RedisResult<FailedLogins> {
+    let (count, last_failed_at): (Option<u32>, Option<i64>) =
+        conn.hget(failed_logins_key(email), &["count", "last_failed_at"])?;
+    Ok(FailedLogins {
+        count: count.unwrap_or(0),
+        last_failed_at: last_failed_at.and_then(|at| Utc.timestamp_opt(at, 0).single()),
+    })
+}
+
+/// Counts a failed login and returns the new total. The count is forgotten once
+/// `window_seconds` pass without another failure.
+pub fn record_failure(
+    conn: &mut redis::Connection,
+    email: &str,
+    window_seconds: usize,
+) -> RedisResult<u32> {
+    let key = failed_logins_key(email);
+    let (count,): (u32,) = redis::pipe()
+        .atomic()
+        .hincr(&key, "count", 1)
+        .hset(&key, "last_failed_at", Utc::now().timestamp())
+        .ignore()
+        .expire(&key, window_seconds)
+        .ignore()
+        .query(conn)?;
+    Ok(count)
+}
+
+pub fn reset_failures(conn: &mut redis::Connection, email: &str) -> RedisResult<()> {
+    conn.del(failed_logins_key(email))
+}
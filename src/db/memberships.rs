@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Validity windows for time-bound memberships. The membership row itself lives in
+// `group_users`; these records tell the sweeper when to add or remove it.
+const PENDING_SET: &str = "membership:pending";
+const EXPIRY_SET: &str = "membership:expiry";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MembershipWindow {
+    pub group_id: i64,
+    pub group_identifier: String,
+    pub user_id: i64,
+    pub email_id: String,
+    pub valid_from: DateTime<Utc>,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub granted_by: String,
+    #[serde(default)]
+    pub reminder_sent: bool,
+}
+
+impl MembershipWindow {
+    fn key(&self) -> String {
+        window_key(self.group_id, self.user_id)
+    }
+}
+
+fn window_key(group_id: i64, user_id: i64) -> String {
+    format!("membership:window:{}:{}", group_id, user_id)
+}
+
+/// Adds the commands storing `window` to a pipeline.
+pub fn queue_window(pipe: &mut redis::Pipeline, window: &MembershipWindow) {
+    let key = window.key();
+    let payload = serde_json::to_string(window).unwrap_or_default();
+
+    pipe.set(&key, payload)
+        .ignore()
+        .zrem(PENDING_SET, &key)
+        .ignore()
+        .zrem(EXPIRY_SET, &key)
+        .ignore();
+
+    if window.valid_from > Utc::now() {
+        pipe.zadd(PENDING_SET, &key, window.valid_from.timestamp())
+            .ignore();
+    }
+    if let Some(valid_until) = window.valid_until {
+        pipe.zadd(EXPIRY_SET, &key, valid_until.timestamp())
+            .ignore();
+    }
+}
+
+pub fn save_window(conn: &mut redis::Connection, window: &MembershipWindow) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    queue_window(&mut pipe, window);
+    pipe.query(conn)
+}
+
+pub fn has_window(conn: &mut redis::Connection, group_id: i64, user_id: i64) -> RedisResult<bool> {
+    conn.exists(window_key(group_id, user_id))
+}
+
+pub fn delete_window(conn: &mut redis::Connection, group_id: i64, user_id: i64) -> RedisResult<()> {
+    let key = window_key(group_id, user_id);
+
+    redis::pipe()
+        .atomic()
+        .del(&key)
+        .zrem(PENDING_SET, &key)
+        .zrem(EXPIRY_SET, &key)
+        .query(conn)
+}
+
+/// Marks a pending window as applied once its membership row has been inserted.
+pub fn mark_started(conn: &mut redis::Connection, window: &MembershipWindow) -> RedisResult<()> {
+    conn.zrem(PENDING_SET, window.key())
+}
+
+fn windows_in(
+    conn: &mut redis::Connection,
+    set: &str,
+    until: DateTime<Utc>,
+) -> RedisResult<Vec<MembershipWindow>> {
+    let keys: Vec<String> = conn.zrangebyscore(set, "-inf", until.timestamp())?;
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let raw: Vec<Option<String>> = redis::cmd("MGET").arg(&keys[..]).query(conn)?;
+    Ok(raw
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| serde_json::from_str(&raw).ok())
+        .collect())
+}
+
+/// Windows whose `valid_from` has passed but whose membership has not been added yet.
+pub fn due_to_start(
+    conn: &mut redis::Connection,
+    now: DateTime<Utc>,
+) -> RedisResult<Vec<MembershipWindow>> {
+    windows_in(conn, PENDING_SET, now)
+}
+
+/// Windows ending on or before `until`.
+pub fn ending_before(
+    conn: &mut redis::Connection,
+    until: DateTime<Utc>,
+) -> RedisResult<Vec<MembershipWindow>> {
+    windows_in(conn, EXPIRY_SET, until)
+}
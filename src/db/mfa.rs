@@ -0,0 +1,145 @@
+use chrono::{DateTime, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Factors are enrolled through the IAM service, which reports them here; they live in
+// `mfa_factors:<email>` keyed by factor id. Users that require MFA live in
+// `REQUIRED_USERS_HASH` keyed by email. Apps that do have `mfa_required` set on their row.
+const REQUIRED_USERS_HASH: &str = "mfa_required:users";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MfaFactorKind {
+    Totp,
+    Webauthn,
+    Sms,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MfaFactor {
+    pub id: String,
+    pub kind: MfaFactorKind,
+    pub label: Option<String>,
+    pub enrolled_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MfaRequirement {
+    pub required_by: String,
+    pub required_at: DateTime<Utc>,
+}
+
+/// What `UserResponse` shows about a user's MFA.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MfaFlags {
+    pub enrolled: bool,
+    pub required: bool,
+}
+
+fn factors_key(email: &str) -> String {
+    format!("mfa_factors:{}", email.trim().to_lowercase())
+}
+
+fn user_field(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn factors(conn: &mut redis::Connection, email: &str) -> RedisResult<Vec<MfaFactor>> {
+    let entries: HashMap<String, String> = conn.hgetall(factors_key(email))?;
+    let mut factors: Vec<MfaFactor> = entries
+        .values()
+        .filter_map(|raw| serde_json::from_str(raw).ok())
+        .collect();
+    factors.sort_by_key(|factor| factor.enrolled_at);
+    Ok(factors)
+}
+
+pub fn enroll(conn: &mut redis::Connection, email: &str, factor: &MfaFactor) -> RedisResult<()> {
+    let payload = serde_json::to_string(factor).unwrap_or_default();
+    conn.hset(factors_key(email), &factor.id, payload)
+}
+
+/// Returns whether the factor existed.
+pub fn remove_factor(
+    conn: &mut redis::Connection,
+    email: &str,
+    factor_id: &str,
+) -> RedisResult<bool> {
+    let removed: usize = conn.hdel(factors_key(email), factor_id)?;
+    Ok(removed > 0)
+}
+
+/// Adds clearing every factor to a pipeline so it happens together with queueing the email
+/// telling the user.
+pub fn queue_reset(pipe: &mut redis::Pipeline, email: &str) {
+    pipe.del(factors_key(email)).ignore();
+}
+
+/// Enrollment and requirement flags for each of `emails`, in order.
+pub fn flags_for(conn: &mut redis::Connection, emails: &[String]) -> RedisResult<Vec<MfaFlags>> {
+    if emails.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pipe = redis::pipe();
+    for email in emails {
+        pipe.exists(factors_key(email))
+            .hexists(REQUIRED_USERS_HASH, user_field(email));
+    }
+    let answers: Vec<bool> = pipe.query(conn)?;
+
+    Ok(answers
+        .chunks(2)
+        .map(|pair| MfaFlags {
+            enrolled: pair[0],
+            required: pair[1],
+        })
+        .collect())
+}
+
+fn get_requirement(
+    conn: &mut redis::Connection,
+    hash: &str,
+    field: &str,
+) -> RedisResult<Option<MfaRequirement>> {
+    let raw: Option<String> = conn.hget(hash, field)?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Sets or clears a requirement; an existing one is kept as it was. Returns whether
+/// anything changed.
+fn set_requirement(
+    conn: &mut redis::Connection,
+    hash: &str,
+    field: &str,
+    requirement: Option<&MfaRequirement>,
+) -> RedisResult<bool> {
+    match requirement {
+        Some(requirement) => {
+            let payload = serde_json::to_string(requirement).unwrap_or_default();
+            let added: usize = conn.hset_nx(hash, field, payload)?;
+            Ok(added > 0)
+        }
+        None => {
+            let removed: usize = conn.hdel(hash, field)?;
+            Ok(removed > 0)
+        }
+    }
+}
+
+pub fn user_requirement(
+    conn: &mut redis::Connection,
+    email: &str,
+) -> RedisResult<Option<MfaRequirement>> {
+    get_requirement(conn, REQUIRED_USERS_HASH, &user_field(email))
+}
+
+pub fn set_user_requirement(
+    conn: &mut redis::Connection,
+    email: &str,
+    requirement: Option<&MfaRequirement>,
+) -> RedisResult<bool> {
+    set_requirement(conn, REQUIRED_USERS_HASH, &user_field(email), requirement)
+}
@@ -6,7 +6,25 @@ use mongodb::{Client, Database};
 use rocket::fairing::AdHoc;
 use std::env;
 
+pub mod access_requests;
+pub mod access_reviews;
+pub mod admin_roles;
+pub mod api_keys;
+pub mod audit;
+pub mod domain_rules;
+pub mod group_cache;
+pub mod groups;
+pub mod impersonations;
+pub mod invites;
+pub mod lockouts;
+pub mod memberships;
+pub mod mfa;
+pub mod outbox;
+pub mod passwords;
+pub mod rate_limit;
 pub mod redis;
+pub mod revocations;
+pub mod users;
 
 pub fn connect_mongo(mongo_uri: String, mongo_db_name: String) -> AdHoc {
     AdHoc::on_ignite("Connecting to MongoDB", |rocket| async {
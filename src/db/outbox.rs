@@ -0,0 +1,166 @@
+use chrono::{DateTime, Duration, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Outgoing emails are written here instead of being sent inline, and delivered by the
+// outbox worker. `DUE_SET` is scored by the next attempt time in milliseconds.
+const DUE_SET: &str = "outbox:due";
+const DEAD_SET: &str = "outbox:dead";
+// Finished jobs are kept around so delivery status can still be looked up
+const FINISHED_TTL_SECONDS: usize = 30 * 24 * 3600;
+
+pub const MAX_ATTEMPTS: u32 = 8;
+const BASE_BACKOFF_SECONDS: i64 = 30;
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Sent,
+    /// Gave up after `MAX_ATTEMPTS` failures
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EmailJob {
+    pub id: String,
+    pub to: String,
+    pub subject: String,
+    pub message: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl EmailJob {
+    pub fn new(id: String, to: &str, subject: String, message: String) -> Self {
+        let now = Utc::now();
+        EmailJob {
+            id,
+            to: to.to_string(),
+            subject,
+            message,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            next_attempt_at: now,
+            sent_at: None,
+        }
+    }
+
+    pub fn mark_sent(&mut self) {
+        // Delivered bodies are dropped so links in them don't outlive the email
+        self.message.clear();
+        self.attempts += 1;
+        self.status = DeliveryStatus::Sent;
+        self.sent_at = Some(Utc::now());
+        self.last_error = None;
+    }
+
+    /// Schedules the next attempt with exponential backoff, or dead-letters the job.
+    pub fn mark_failed(&mut self, error: String) {
+        self.attempts += 1;
+        self.last_error = Some(error);
+
+        if self.attempts >= MAX_ATTEMPTS {
+            self.status = DeliveryStatus::Dead;
+            return;
+        }
+
+        let backoff =
+            (BASE_BACKOFF_SECONDS << (self.attempts - 1).min(16)).min(MAX_BACKOFF_SECONDS);
+        self.next_attempt_at = Utc::now() + Duration::seconds(backoff);
+    }
+}
+
+fn job_key(job_id: &str) -> String {
+    format!("outbox:job:{}", job_id)
+}
+
+/// Adds the commands storing `job` to a pipeline, so callers can persist the email in the
+/// same MULTI as the record it belongs to.
+pub fn queue_job(pipe: &mut redis::Pipeline, job: &EmailJob) {
+    let payload = serde_json::to_string(job).unwrap_or_default();
+
+    match job.status {
+        DeliveryStatus::Pending => {
+            pipe.set(job_key(&job.id), payload)
+                .ignore()
+                .zadd(DUE_SET, &job.id, job.next_attempt_at.timestamp_millis())
+                .ignore();
+        }
+        DeliveryStatus::Sent => {
+            pipe.set_ex(job_key(&job.id), payload, FINISHED_TTL_SECONDS)
+                .ignore()
+                .zrem(DUE_SET, &job.id)
+                .ignore();
+        }
+        DeliveryStatus::Dead => {
+            pipe.set_ex(job_key(&job.id), payload, FINISHED_TTL_SECONDS)
+                .ignore()
+                .zrem(DUE_SET, &job.id)
+                .ignore()
+                .sadd(DEAD_SET, &job.id)
+                .ignore();
+        }
+    }
+}
+
+pub fn save(conn: &mut redis::Connection, job: &EmailJob) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    queue_job(&mut pipe, job);
+    pipe.query(conn)
+}
+
+pub fn get(conn: &mut redis::Connection, job_id: &str) -> RedisResult<Option<EmailJob>> {
+    let raw: Option<String> = conn.get(job_key(job_id))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Claims up to `limit` due jobs. A claimed job is pushed back by `lease_seconds`, so it is
+/// retried by another worker if this one dies before recording the outcome.
+pub fn claim_due(
+    conn: &mut redis::Connection,
+    limit: isize,
+    lease_seconds: i64,
+) -> RedisResult<Vec<EmailJob>> {
+    let now = Utc::now();
+    let due_ids: Vec<String> =
+        conn.zrangebyscore_limit(DUE_SET, "-inf", now.timestamp_millis(), 0, limit)?;
+
+    let claim = redis::Script::new(
+        r"
+        local score = redis.call('ZSCORE', KEYS[1], ARGV[1])
+        if score and tonumber(score) <= tonumber(ARGV[2]) then
+            redis.call('ZADD', KEYS[1], ARGV[3], ARGV[1])
+            return 1
+        end
+        return 0
+        ",
+    );
+
+    let lease_until = (now + Duration::seconds(lease_seconds)).timestamp_millis();
+    let mut claimed = Vec::new();
+    for job_id in due_ids {
+        let won: i32 = claim
+            .key(DUE_SET)
+            .arg(&job_id)
+            .arg(now.timestamp_millis())
+            .arg(lease_until)
+            .invoke(conn)?;
+        if won == 1 {
+            if let Some(job) = get(conn, &job_id)? {
+                claimed.push(job);
+            }
+        }
+    }
+
+    Ok(claimed)
+}
@@ -0,0 +1,107 @@
+use crate::db::redis::random_key;
+use chrono::{DateTime, Duration, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// A user has at most one outstanding reset; requesting another replaces it. Only a hash of
+// the token is kept. Accounts that must pick a new password live in `ROTATION_HASH`, keyed
+// by email, until the IAM service reports the password changed.
+const ROTATION_HASH: &str = "password_rotation";
+const RESET_TOKEN_LENGTH: usize = 40;
+pub const RESET_EXPIRY_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordReset {
+    pub email_id: String,
+    pub token_hash: String,
+    pub requested_by: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl PasswordReset {
+    /// Builds a reset and returns it with the plaintext token for the email link.
+    pub fn new(email_id: &str, requested_by: &str) -> (Self, String) {
+        let token = random_key(RESET_TOKEN_LENGTH);
+        let reset = PasswordReset {
+            email_id: email_id.to_string(),
+            token_hash: hash_token(&token),
+            requested_by: requested_by.to_string(),
+            expires_at: Utc::now() + Duration::seconds(RESET_EXPIRY_SECONDS),
+        };
+        (reset, token)
+    }
+
+    pub fn accepts(&self, token: &str) -> bool {
+        Utc::now() < self.expires_at && hash_token(token) == self.token_hash
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationReason {
+    /// An admin set a temporary password
+    TemporaryPassword,
+    /// An admin flagged the account
+    AdminRequest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordRotation {
+    pub email_id: String,
+    pub reason: RotationReason,
+    pub required_by: String,
+    pub required_at: DateTime<Utc>,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn reset_key(email: &str) -> String {
+    format!("password_reset:{}", email.trim().to_lowercase())
+}
+
+/// Adds the reset to a pipeline so it is stored together with the email carrying its link.
+pub fn queue_reset(pipe: &mut redis::Pipeline, reset: &PasswordReset) {
+    let payload = serde_json::to_string(reset).unwrap_or_default();
+    let ttl = (reset.expires_at - Utc::now()).num_seconds().max(1) as usize;
+    pipe.set_ex(reset_key(&reset.email_id), payload, ttl)
+        .ignore();
+}
+
+pub fn get_reset(conn: &mut redis::Connection, email: &str) -> RedisResult<Option<PasswordReset>> {
+    let raw: Option<String> = conn.get(reset_key(email))?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+pub fn delete_reset(conn: &mut redis::Connection, email: &str) -> RedisResult<()> {
+    conn.del(reset_key(email))
+}
+
+pub fn get_rotation(
+    conn: &mut redis::Connection,
+    email: &str,
+) -> RedisResult<Option<PasswordRotation>> {
+    let raw: Option<String> = conn.hget(ROTATION_HASH, email.trim().to_lowercase())?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+pub fn require_rotation(
+    conn: &mut redis::Connection,
+    rotation: &PasswordRotation,
+) -> RedisResult<()> {
+    let payload = serde_json::to_string(rotation).unwrap_or_default();
+    conn.hset(
+        ROTATION_HASH,
+        rotation.email_id.trim().to_lowercase(),
+        payload,
+    )
+}
+
+/// Returns whether a flag was cleared.
+pub fn clear_rotation(conn: &mut redis::Connection, email: &str) -> RedisResult<bool> {
+    let removed: usize = conn.hdel(ROTATION_HASH, email.trim().to_lowercase())?;
+    Ok(removed > 0)
+}
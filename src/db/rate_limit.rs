@@ -0,0 +1,23 @@
+use chrono::Utc;
+use r2d2_redis::redis::{self, RedisResult};
+
+/// Fixed-window counter: adds `cost` to the current window of `key` and reports whether
+/// it still fits in `limit`. Rejected attempts count too, so hammering doesn't pay off.
+pub fn consume(
+    conn: &mut redis::Connection,
+    key: &str,
+    cost: u64,
+    limit: u64,
+    window_seconds: i64,
+) -> RedisResult<bool> {
+    let window = Utc::now().timestamp() / window_seconds;
+    let window_key = format!("rate_limit:{}:{}", key, window);
+
+    let (count,): (u64,) = redis::pipe()
+        .atomic()
+        .incr(&window_key, cost)
+        .expire(&window_key, window_seconds as usize)
+        .ignore()
+        .query(conn)?;
+    Ok(count <= limit)
+}
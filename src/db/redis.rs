@@ -1,4 +1,6 @@
-use r2d2_redis::{r2d2::Pool, RedisConnectionManager};
+use r2d2_redis::{r2d2::Pool, redis, RedisConnectionManager};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use std::{ops::Deref, process::exit};
 
 // Define a type alias for your Redis connection pool
@@ -34,3 +36,29 @@ impl Deref for RedisPoolState {
         &self.0
     }
 }
+
+// Random alphanumeric string used for tokens and record ids stored in Redis
+pub fn random_key(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Takes `key` for `ttl_seconds` unless another replica already holds it. Background jobs
+/// that send email take one per pass so only one replica does the work.
+pub fn acquire_lease(
+    conn: &mut redis::Connection,
+    key: &str,
+    ttl_seconds: u64,
+) -> redis::RedisResult<bool> {
+    let acquired: Option<String> = redis::cmd("SET")
+        .arg(key)
+        .arg(random_key(16))
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl_seconds)
+        .query(conn)?;
+    Ok(acquired.is_some())
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use r2d2_redis::redis::{self, Commands, RedisResult};
+
+// Revoked sessions, kept in the Redis shared with the other services so they can enforce
+// them too:
+//   revoked_before:user:<email>   tokens for the user issued at or before this unix time
+//   revoked_before:app:<client>   tokens for the app issued at or before this unix time
+//   revoked_token:<jti>           a single token, kept until it would have expired
+// Markers have to outlive the tokens they cut off, see `revocation_marker_ttl_seconds`.
+
+fn user_marker_key(email: &str) -> String {
+    format!("revoked_before:user:{}", email.trim().to_lowercase())
+}
+
+fn app_marker_key(client_id: &str) -> String {
+    format!("revoked_before:app:{}", client_id)
+}
+
+fn token_key(jti: &str) -> String {
+    format!("revoked_token:{}", jti)
+}
+
+/// What a token has to be checked against.
+pub struct TokenIdentity<'a> {
+    pub sub: &'a str,
+    pub client_id: Option<&'a str>,
+    pub jti: Option<&'a str>,
+    /// See `issued_at`
+    pub issued_at: i64,
+}
+
+pub fn revoke_user(
+    conn: &mut redis::Connection,
+    email: &str,
+    at: DateTime<Utc>,
+    ttl_seconds: usize,
+) -> RedisResult<()> {
+    conn.set_ex(user_marker_key(email), at.timestamp(), ttl_seconds)
+}
+
+pub fn revoke_app(
+    conn: &mut redis::Connection,
+    client_id: &str,
+    at: DateTime<Utc>,
+    ttl_seconds: usize,
+) -> RedisResult<()> {
+    conn.set_ex(app_marker_key(client_id), at.timestamp(), ttl_seconds)
+}
+
+/// Denylists one token until its expiry. Already expired tokens are left alone.
+pub fn revoke_token(conn: &mut redis::Connection, jti: &str, expires_at: i64) -> RedisResult<()> {
+    let ttl = expires_at - Utc::now().timestamp();
+    if ttl <= 0 {
+        return Ok(());
+    }
+    conn.set_ex(token_key(jti), 1, ttl as usize)
+}
+
+/// When a token was issued. Tokens without `iat` are placed `lifetime_seconds` before
+/// their expiry, the lifetime access tokens are issued with.
+pub fn issued_at(iat: Option<i64>, exp: i64, lifetime_seconds: i64) -> i64 {
+    iat.unwrap_or(exp - lifetime_seconds)
+}
+
+/// Whether a `revoked_before` marker applies to a token issued at `issued_at`.
+pub fn cuts_off(revoked_before: Option<i64>, issued_at: i64) -> bool {
+    revoked_before.is_some_and(|revoked_before| issued_at <= revoked_before)
+}
+
+/// Whether the denylist or a user or app marker cuts the token off.
+pub fn is_revoked(conn: &mut redis::Connection, token: &TokenIdentity) -> RedisResult<bool> {
+    if let Some(jti) = token.jti {
+        if conn.exists(token_key(jti))? {
+            return Ok(true);
+        }
+    }
+    let user_marker: Option<i64> = conn.get(user_marker_key(token.sub))?;
+    let app_marker: Option<i64> = match token.client_id {
+        Some(client_id) => conn.get(app_marker_key(client_id))?,
+        None => None,
+    };
+
+    Ok(cuts_off(user_marker, token.issued_at) || cuts_off(app_marker, token.issued_at))
+}
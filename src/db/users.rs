@@ -0,0 +1,38 @@
+use crate::models::schema::User;
+use diesel::dsl::{exists, sql};
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Text};
+use diesel::PgConnection;
+
+pub fn find_by_email(conn: &mut PgConnection, email: &str) -> QueryResult<User> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    user.filter(email_id.eq(email)).first::<User>(conn)
+}
+
+/// Whether an account uses `email`, ignoring case since older accounts kept the email as
+/// it was typed.
+pub fn email_taken(conn: &mut PgConnection, email: &str) -> QueryResult<bool> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    diesel::select(exists(user.filter(
+        sql::<Bool>("lower(email_id) = ").bind::<Text, _>(email.to_lowercase()),
+    )))
+    .get_result(conn)
+}
+
+/// Users whose email is at `domain` or one of its subdomains. `domain` must already be
+/// normalised, so it holds no LIKE wildcards.
+pub fn with_email_domain(conn: &mut PgConnection, domain: &str) -> QueryResult<Vec<User>> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    user.filter(
+        sql::<Bool>("(lower(email_id) LIKE ")
+            .bind::<Text, _>(format!("%@{}", domain))
+            .sql(" OR lower(email_id) LIKE ")
+            .bind::<Text, _>(format!("%@%.{}", domain))
+            .sql(")"),
+    )
+    .order(email_id.asc())
+    .load(conn)
+}
@@ -0,0 +1,322 @@
+use crate::models::request::InviteRequest;
+use crate::models::schema::App;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::env;
+use tera::{Context, Tera};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// Built-in templates, named `<kind>/<locale>.<part>`. Files with the same relative name
+// under `EMAIL_TEMPLATES_DIR` take precedence, so copy can be changed without a rebuild.
+// The notification service sends a single HTML body, so there is no plain text part.
+const BUILTIN_TEMPLATES: [(&str, &str); 18] = [
+    (
+        "invite/en.subject",
+        include_str!("../templates/email/invite/en.subject"),
+    ),
+    (
+        "invite/en.html",
+        include_str!("../templates/email/invite/en.html"),
+    ),
+    (
+        "invite/es.subject",
+        include_str!("../templates/email/invite/es.subject"),
+    ),
+    (
+        "invite/es.html",
+        include_str!("../templates/email/invite/es.html"),
+    ),
+    (
+        "invite_expired/en.subject",
+        include_str!("../templates/email/invite_expired/en.subject"),
+    ),
+    (
+        "invite_expired/en.html",
+        include_str!("../templates/email/invite_expired/en.html"),
+    ),
+    (
+        "invite_accepted/en.subject",
+        include_str!("../templates/email/invite_accepted/en.subject"),
+    ),
+    (
+        "invite_accepted/en.html",
+        include_str!("../templates/email/invite_accepted/en.html"),
+    ),
+    (
+        "password_reset/en.subject",
+        include_str!("../templates/email/password_reset/en.subject"),
+    ),
+    (
+        "password_reset/en.html",
+        include_str!("../templates/email/password_reset/en.html"),
+    ),
+    (
+        "mfa_reset/en.subject",
+        include_str!("../templates/email/mfa_reset/en.subject"),
+    ),
+    (
+        "mfa_reset/en.html",
+        include_str!("../templates/email/mfa_reset/en.html"),
+    ),
+    (
+        "membership_expiring/en.subject",
+        include_str!("../templates/email/membership_expiring/en.subject"),
+    ),
+    (
+        "membership_expiring/en.html",
+        include_str!("../templates/email/membership_expiring/en.html"),
+    ),
+    (
+        "access_review_pending/en.subject",
+        include_str!("../templates/email/access_review_pending/en.subject"),
+    ),
+    (
+        "access_review_pending/en.html",
+        include_str!("../templates/email/access_review_pending/en.html"),
+    ),
+    (
+        "impersonation_started/en.subject",
+        include_str!("../templates/email/impersonation_started/en.subject"),
+    ),
+    (
+        "impersonation_started/en.html",
+        include_str!("../templates/email/impersonation_started/en.html"),
+    ),
+];
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+}
+
+pub struct InviteEmail<'a> {
+    pub first_name: &'a str,
+    pub accept_link: &'a str,
+    pub expires_in_seconds: u64,
+    pub app: Option<&'a App>,
+    /// Sent again shortly before the invite expires, with a fresh link
+    pub reminder: bool,
+}
+
+#[derive(Clone)]
+pub struct EmailTemplates(Tera);
+
+impl EmailTemplates {
+    pub fn load() -> Self {
+        let mut builtin = Tera::default();
+        builtin
+            .add_raw_templates(BUILTIN_TEMPLATES)
+            .expect("Built-in email templates must parse");
+
+        let Ok(templates_dir) = env::var("EMAIL_TEMPLATES_DIR") else {
+            return EmailTemplates(builtin);
+        };
+
+        match Tera::new(&format!("{}/**/*", templates_dir.trim_end_matches('/'))) {
+            Ok(mut overrides) => {
+                // extend() keeps templates already present, so overrides win
+                overrides
+                    .extend(&builtin)
+                    .expect("Built-in email templates must merge");
+                println!(
+                    "Loaded {} email template override(s) from {}",
+                    overrides.get_template_names().count() - BUILTIN_TEMPLATES.len(),
+                    templates_dir
+                );
+                EmailTemplates(overrides)
+            }
+            Err(err) => {
+                println!(
+                    "Ignoring email templates in {}, failed to parse: {:?}",
+                    templates_dir, err
+                );
+                EmailTemplates(builtin)
+            }
+        }
+    }
+
+    /// Picks the most specific locale that has a template: `pt-BR`, then `pt`, then English.
+    fn resolve_locale(&self, kind: &str, locale: Option<&str>) -> String {
+        let available = |candidate: &str| {
+            self.0
+                .get_template_names()
+                .any(|name| name == format!("{}/{}.html", kind, candidate))
+        };
+
+        if let Some(locale) = locale {
+            let locale = locale.trim();
+            if available(locale) {
+                return locale.to_string();
+            }
+            if let Some(language) = locale.split(['-', '_']).next() {
+                if available(language) {
+                    return language.to_string();
+                }
+            }
+        }
+
+        DEFAULT_LOCALE.to_string()
+    }
+
+    fn render(&self, kind: &str, locale: &str, context: &Context) -> tera::Result<RenderedEmail> {
+        Ok(RenderedEmail {
+            subject: self
+                .0
+                .render(&format!("{}/{}.subject", kind, locale), context)?
+                .trim()
+                .to_string(),
+            html: self
+                .0
+                .render(&format!("{}/{}.html", kind, locale), context)?,
+        })
+    }
+
+    pub fn render_invite(
+        &self,
+        locale: Option<&str>,
+        invite: &InviteEmail,
+    ) -> tera::Result<RenderedEmail> {
+        let (expiry_value, expiry_unit) = match invite.expires_in_seconds {
+            s if s % 86400 == 0 => (s / 86400, "days"),
+            s if s % 3600 == 0 => (s / 3600, "hours"),
+            s => (s / 60, "minutes"),
+        };
+
+        let mut context = Context::new();
+        context.insert("first_name", invite.first_name);
+        context.insert("accept_link", invite.accept_link);
+        context.insert("expiry_value", &expiry_value);
+        context.insert("expiry_unit", expiry_unit);
+        context.insert("reminder", &invite.reminder);
+        if let Some(app) = invite.app {
+            context.insert("app_name", &app.name);
+            if let Some(logo_url) = &app.logo_url {
+                context.insert("app_logo_url", logo_url);
+            }
+        }
+
+        let locale = self.resolve_locale("invite", locale);
+        self.render("invite", &locale, &context)
+    }
+
+    /// Tells an inviter that their invite expired without being redeemed.
+    pub fn render_invite_expired(
+        &self,
+        invitee_email: &str,
+        expired_at: DateTime<Utc>,
+    ) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("invitee_email", invitee_email);
+        context.insert(
+            "expired_at",
+            &expired_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+
+        let locale = self.resolve_locale("invite_expired", None);
+        self.render("invite_expired", &locale, &context)
+    }
+
+    /// Tells an inviter that their invite was accepted.
+    pub fn render_invite_accepted(&self, invite: &InviteRequest) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("invitee_email", &invite.email_id);
+        context.insert("first_name", &invite.first_name);
+        context.insert("last_name", &invite.last_name);
+
+        let locale = self.resolve_locale("invite_accepted", None);
+        self.render("invite_accepted", &locale, &context)
+    }
+
+    /// Sends a user the link an admin requested to reset their password.
+    pub fn render_password_reset(
+        &self,
+        reset_link: &str,
+        expires_at: DateTime<Utc>,
+    ) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("reset_link", reset_link);
+        context.insert(
+            "expires_at",
+            &expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+
+        let locale = self.resolve_locale("password_reset", None);
+        self.render("password_reset", &locale, &context)
+    }
+
+    /// Tells a user an admin removed their MFA factors.
+    pub fn render_mfa_reset(&self, email_id: &str) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("email_id", email_id);
+
+        let locale = self.resolve_locale("mfa_reset", None);
+        self.render("mfa_reset", &locale, &context)
+    }
+
+    /// Reminds a member that their time-bound membership of a group ends soon.
+    pub fn render_membership_expiring(
+        &self,
+        group_identifier: &str,
+        valid_until: DateTime<Utc>,
+    ) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("group_identifier", group_identifier);
+        context.insert(
+            "valid_until",
+            &valid_until.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+
+        let locale = self.resolve_locale("membership_expiring", None);
+        self.render("membership_expiring", &locale, &context)
+    }
+
+    /// Reminds a reviewer how many memberships of a campaign they still have to decide on.
+    pub fn render_access_review_pending(
+        &self,
+        campaign_name: &str,
+        group_identifier: &str,
+        pending: usize,
+        deadline: DateTime<Utc>,
+    ) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("campaign_name", campaign_name);
+        context.insert("group_identifier", group_identifier);
+        context.insert("pending", &pending);
+        context.insert(
+            "deadline",
+            &deadline.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+
+        let locale = self.resolve_locale("access_review_pending", None);
+        self.render("access_review_pending", &locale, &context)
+    }
+
+    /// Tells a user that an administrator started a support session as them.
+    pub fn render_impersonation_started(
+        &self,
+        admin_email: &str,
+        app_client_id: &str,
+        reason: &str,
+        started_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> tera::Result<RenderedEmail> {
+        let mut context = Context::new();
+        context.insert("admin_email", admin_email);
+        context.insert("app_client_id", app_client_id);
+        context.insert("reason", reason);
+        context.insert(
+            "started_at",
+            &started_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+        context.insert(
+            "expires_at",
+            &expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        );
+
+        let locale = self.resolve_locale("impersonation_started", None);
+        self.render("impersonation_started", &locale, &context)
+    }
+}
@@ -0,0 +1,38 @@
+use crate::middlewares::user_claims::TokenFailure;
+use crate::user_tokens::TokenRejection;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::{Request, Response};
+
+/// Says why a bearer token was refused, in the `WWW-Authenticate` header of 401 responses.
+pub struct AuthChallenge;
+
+#[rocket::async_trait]
+impl Fairing for AuthChallenge {
+    fn info(&self) -> Info {
+        Info {
+            name: "Explain rejected bearer tokens",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status() != Status::Unauthorized {
+            return;
+        }
+        let TokenFailure(Some(rejection)) = request.local_cache(|| TokenFailure(None)) else {
+            return;
+        };
+
+        // Per RFC 6750 a request without credentials gets a bare challenge
+        let challenge = if *rejection == TokenRejection::Missing {
+            "Bearer".to_string()
+        } else {
+            format!(
+                "Bearer error=\"invalid_token\", error_description=\"{}\"",
+                rejection.describe()
+            )
+        };
+        response.set_header(Header::new("WWW-Authenticate", challenge));
+    }
+}
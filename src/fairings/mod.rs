@@ -1 +1,3 @@
+pub mod auth_challenge;
 pub mod cors;
+pub mod startup_checks;
@@ -0,0 +1,24 @@
+use crate::config;
+use crate::impersonation_tokens::ImpersonationTokens;
+use crate::routes::access_reviews::ReportSigner;
+use rocket::fairing::AdHoc;
+
+/// Refuses to launch when email links can't be built or a signing secret is missing,
+/// naming what to set, instead of failing on the first request that needs them.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Links and signing secrets", |rocket| async move {
+        let checked = config::invite_accept_url()
+            .and(config::password_reset_url())
+            .and_then(|_| Ok((ReportSigner::from_env()?, ImpersonationTokens::from_env()?)));
+
+        match checked {
+            Ok((report_signer, impersonation_tokens)) => {
+                Ok(rocket.manage(report_signer).manage(impersonation_tokens))
+            }
+            Err(err) => {
+                println!("Not starting: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,137 @@
+use crate::db::impersonations::ImpersonationSession;
+use crate::models::schema::User;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Impersonation tokens carry the same fields as a regular user token, but are signed with
+// their own key and meant for the `impersonation` audience, so nothing that checks user
+// tokens takes them for the real user. Apps that support impersonation hand them to
+// `POST /impersonations/verify`, which enforces the app, the scope and ended sessions.
+
+pub const TOKEN_TYPE: &str = "impersonation";
+pub const AUDIENCE: &str = "impersonation";
+pub const READ_ONLY_SCOPE: &str = "read_only";
+pub const FULL_SCOPE: &str = "full";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub aud: String,
+    pub user_id: String,
+    pub token_type: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub middle_name: Option<String>,
+    /// The only app the token is valid for
+    pub client_id: Option<String>,
+    /// Impersonation session id
+    pub jti: String,
+    /// The admin acting as `sub`
+    pub act: Actor,
+    pub scope: String,
+}
+
+/// Why an impersonation token was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImpersonationRejection {
+    /// Not signed with the impersonation key, expired or not an impersonation token
+    Invalid,
+    /// Issued for another app
+    WrongApp,
+    /// A read-only token used for a change
+    ReadOnly,
+}
+
+/// Signs and checks impersonation tokens with `IMPERSONATION_TOKEN_SECRET`, read at
+/// startup.
+pub struct ImpersonationTokens {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl ImpersonationTokens {
+    pub fn new(secret: &[u8]) -> Self {
+        ImpersonationTokens {
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let secret = env::var("IMPERSONATION_TOKEN_SECRET")
+            .map_err(|_| "IMPERSONATION_TOKEN_SECRET must be set".to_string())?;
+        Ok(ImpersonationTokens::new(secret.as_bytes()))
+    }
+
+    pub fn issue(&self, session: &ImpersonationSession, target: &User) -> String {
+        let claims = ImpersonationClaims {
+            sub: target.email_id.clone(),
+            exp: session.expires_at.timestamp() as usize,
+            iat: session.started_at.timestamp() as usize,
+            aud: AUDIENCE.to_string(),
+            user_id: target.id.to_string(),
+            token_type: TOKEN_TYPE.to_string(),
+            first_name: target.first_name.clone(),
+            last_name: target.last_name.clone(),
+            middle_name: target.middle_name.clone(),
+            client_id: Some(session.app_client_id.clone()),
+            jti: session.id.clone(),
+            act: Actor {
+                sub: session.admin_email.clone(),
+            },
+            scope: if session.read_only {
+                READ_ONLY_SCOPE
+            } else {
+                FULL_SCOPE
+            }
+            .to_string(),
+        };
+
+        self.sign(&claims)
+    }
+
+    pub(crate) fn sign(&self, claims: &ImpersonationClaims) -> String {
+        encode(&Header::new(Algorithm::HS256), claims, &self.encoding_key)
+            .expect("impersonation claims serialize")
+    }
+
+    /// Checks a token presented to the app `client_id`. `writes` is whether the request
+    /// changes anything, which read-only tokens may not do.
+    pub fn verify(
+        &self,
+        token: &str,
+        client_id: &str,
+        writes: bool,
+    ) -> Result<ImpersonationClaims, ImpersonationRejection> {
+        let claims = self.decode(token)?;
+        if claims.client_id.as_deref() != Some(client_id) {
+            return Err(ImpersonationRejection::WrongApp);
+        }
+        if writes && claims.scope != FULL_SCOPE {
+            return Err(ImpersonationRejection::ReadOnly);
+        }
+        Ok(claims)
+    }
+
+    /// Checks that a token is a live impersonation token, whatever app it is for.
+    pub fn decode(&self, token: &str) -> Result<ImpersonationClaims, ImpersonationRejection> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_audience(&[AUDIENCE]);
+
+        let claims = decode::<ImpersonationClaims>(token, &self.decoding_key, &validation)
+            .map_err(|_| ImpersonationRejection::Invalid)?
+            .claims;
+        if claims.token_type != TOKEN_TYPE {
+            return Err(ImpersonationRejection::Invalid);
+        }
+        Ok(claims)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::db::redis::random_key;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+
+// Invite tokens only ever leave this service inside the invitation email. Redis keeps a
+// keyed hash of the token bound to the invitee's email, so reading Redis is not enough
+// to redeem an invite.
+
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+    Invalid,
+    Expired,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InviteClaims {
+    /// Invitee email
+    sub: String,
+    /// Invite id
+    jti: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Secret and format invite tokens are issued with, from `INVITE_TOKEN_SECRET` and
+/// `INVITE_TOKEN_FORMAT`.
+pub struct InviteTokens {
+    secret: String,
+    jwt: bool,
+}
+
+impl InviteTokens {
+    pub fn new(secret: &str, jwt: bool) -> Self {
+        InviteTokens {
+            secret: secret.to_string(),
+            jwt,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let secret = env::var("INVITE_TOKEN_SECRET").expect("INVITE_TOKEN_SECRET must be set");
+        let jwt =
+            env::var("INVITE_TOKEN_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("jwt"));
+        InviteTokens::new(&secret, jwt)
+    }
+
+    fn mac_for(&self, email: &str, token: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts any key size");
+        mac.update(email.trim().to_lowercase().as_bytes());
+        mac.update(b"\n");
+        mac.update(token.as_bytes());
+        mac
+    }
+
+    /// Issues a token for an invite. Opaque random tokens are the default; with
+    /// `INVITE_TOKEN_FORMAT=jwt` the token is an HS256 JWT carrying the email and expiry.
+    pub fn issue(&self, invite_id: &str, email: &str, expires_at: DateTime<Utc>) -> String {
+        if !self.jwt {
+            return random_key(30);
+        }
+
+        let claims = InviteClaims {
+            sub: email.trim().to_lowercase(),
+            jti: invite_id.to_string(),
+            iat: Utc::now().timestamp(),
+            exp: expires_at.timestamp(),
+        };
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.secret.as_bytes()),
+        )
+        .expect("invite claims serialize")
+    }
+
+    /// Keyed hash of a token, bound to the email it was issued for. Used as the Redis key.
+    pub fn hash(&self, email: &str, token: &str) -> String {
+        hex::encode(self.mac_for(email, token).finalize().into_bytes())
+    }
+
+    /// Checks a presented token against a stored hash in constant time. JWT tokens must
+    /// also carry a valid signature, an unexpired `exp` and the same email.
+    pub fn verify(&self, email: &str, token: &str, stored_hash: &str) -> Result<(), TokenError> {
+        // Opaque tokens are alphanumeric, so a dot means a JWT
+        if token.contains('.') {
+            let validation = Validation::new(Algorithm::HS256);
+            let claims = decode::<InviteClaims>(
+                token,
+                &DecodingKey::from_secret(self.secret.as_bytes()),
+                &validation,
+            )
+            .map_err(|err| match err.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => TokenError::Expired,
+                _ => TokenError::Invalid,
+            })?
+            .claims;
+
+            if claims.sub != email.trim().to_lowercase() {
+                return Err(TokenError::Invalid);
+            }
+        }
+
+        let expected = hex::decode(stored_hash).map_err(|_| TokenError::Invalid)?;
+        self.mac_for(email, token)
+            .verify_slice(&expected)
+            .map_err(|_| TokenError::Invalid)
+    }
+}
+
+// Shortcuts using the configuration from the environment
+
+pub fn issue(invite_id: &str, email: &str, expires_at: DateTime<Utc>) -> String {
+    InviteTokens::from_env().issue(invite_id, email, expires_at)
+}
+
+pub fn hash(email: &str, token: &str) -> String {
+    InviteTokens::from_env().hash(email, token)
+}
+
+pub fn verify(email: &str, token: &str, stored_hash: &str) -> Result<(), TokenError> {
+    InviteTokens::from_env().verify(email, token, stored_hash)
+}
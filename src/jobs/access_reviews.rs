@@ -0,0 +1,134 @@
+use super::{CachePool, DbPool};
+use crate::db::access_reviews::{self, CampaignStatus, ReviewCampaign, ReviewDecision};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::redis::{acquire_lease, random_key};
+use crate::emails::EmailTemplates;
+use crate::routes::groups::revoke_membership;
+use chrono::{Duration, Utc};
+use r2d2_redis::redis;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::{interval, Duration as TickDuration};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+const CHECK_INTERVAL_SECONDS: u64 = 15 * 60;
+const REMINDER_INTERVAL_HOURS: i64 = 24;
+// Held for one interval, so each pass runs on a single replica
+const LEASE_KEY: &str = "access_reviews:lease";
+
+pub async fn run(rdb: DbPool, cache_pool: CachePool, templates: EmailTemplates) {
+    let mut ticker = interval(TickDuration::from_secs(CHECK_INTERVAL_SECONDS));
+
+    loop {
+        ticker.tick().await;
+
+        let (rdb, cache_pool, templates) = (rdb.clone(), cache_pool.clone(), templates.clone());
+        match spawn_blocking(move || process_campaigns(&rdb, &cache_pool, &templates)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => println!("Access review check failed: {}", err),
+            Err(err) => println!("Access review check panicked: {:?}", err),
+        }
+    }
+}
+
+/// Closes campaigns past their deadline, revoking whatever was not reviewed, and queues
+/// reminders for reviewers of the remaining open campaigns.
+fn process_campaigns(
+    rdb: &DbPool,
+    cache_pool: &CachePool,
+    templates: &EmailTemplates,
+) -> Result<(), String> {
+    let mut cache_connection = cache_pool.get().map_err(|err| err.to_string())?;
+    if !acquire_lease(&mut cache_connection, LEASE_KEY, CHECK_INTERVAL_SECONDS)
+        .map_err(|err| err.to_string())?
+    {
+        return Ok(());
+    }
+    let mut conn = rdb.get().map_err(|err| err.to_string())?;
+    let now = Utc::now();
+
+    for mut campaign in access_reviews::open_campaigns(&mut conn).map_err(|err| err.to_string())? {
+        let items =
+            access_reviews::items(&mut conn, &campaign.id).map_err(|err| err.to_string())?;
+
+        if campaign.deadline <= now {
+            for mut item in items.into_iter().filter(|item| item.decision.is_none()) {
+                revoke_membership(
+                    &mut conn,
+                    &mut cache_connection,
+                    &item.membership_group(&campaign),
+                    item.user_id,
+                    &item.email_id,
+                    "system",
+                    json!({ "access_review": campaign.id, "auto_revoked": true }),
+                )
+                .map_err(|status| status.to_string())?;
+
+                item.decision = Some(ReviewDecision::Revoke);
+                item.decided_by = Some("system".to_string());
+                item.decided_at = Some(now);
+                item.auto_revoked = true;
+                access_reviews::save_item(&mut conn, &campaign.id, &item)
+                    .map_err(|err| err.to_string())?;
+            }
+
+            campaign.status = CampaignStatus::Completed;
+            campaign.completed_at = Some(now);
+            access_reviews::save_campaign(&mut conn, &campaign).map_err(|err| err.to_string())?;
+            continue;
+        }
+
+        let reminder_due = campaign
+            .last_reminded_at
+            .is_none_or(|at| now - at >= Duration::hours(REMINDER_INTERVAL_HOURS));
+        if !reminder_due {
+            continue;
+        }
+
+        let mut pending_by_reviewer: BTreeMap<String, usize> = BTreeMap::new();
+        for item in items.iter().filter(|item| item.decision.is_none()) {
+            for reviewer in &item.reviewers {
+                *pending_by_reviewer.entry(reviewer.clone()).or_default() += 1;
+            }
+        }
+
+        campaign.last_reminded_at = Some(now);
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (reviewer, pending) in pending_by_reviewer {
+            outbox::queue_job(
+                &mut pipe,
+                &review_reminder(templates, &campaign, &reviewer, pending)?,
+            );
+        }
+        pipe.query::<()>(&mut *cache_connection)
+            .map_err(|err| err.to_string())?;
+        access_reviews::save_campaign(&mut conn, &campaign).map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn review_reminder(
+    templates: &EmailTemplates,
+    campaign: &ReviewCampaign,
+    reviewer: &str,
+    pending: usize,
+) -> Result<EmailJob, String> {
+    let rendered = templates
+        .render_access_review_pending(
+            &campaign.name,
+            &campaign.group_identifier,
+            pending,
+            campaign.deadline,
+        )
+        .map_err(|err| format!("rendering review reminder failed: {}", err))?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        reviewer,
+        rendered.subject,
+        rendered.html,
+    ))
+}
@@ -0,0 +1,205 @@
+use super::{CachePool, DbPool};
+use crate::db::audit::{self, AuditEvent};
+use crate::db::invites::{self, InviteEvent, InviteEventKind, InviteFollowUp, PendingInviteRef};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::redis::{acquire_lease, random_key};
+use crate::emails::EmailTemplates;
+use crate::invite_tokens;
+use crate::routes::admin::{invite_target_app, render_invite_email};
+use chrono::Utc;
+use diesel::PgConnection;
+use r2d2_redis::redis;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::{interval, Duration as TickDuration};
+use serde_json::json;
+
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+// Held for one interval, so each pass runs on a single replica
+const LEASE_KEY: &str = "invite:follow_ups:lease";
+
+pub async fn run(rdb: DbPool, cache_pool: CachePool, templates: EmailTemplates) {
+    let mut ticker = interval(TickDuration::from_secs(CHECK_INTERVAL_SECONDS));
+
+    loop {
+        ticker.tick().await;
+
+        let (rdb, cache_pool, templates) = (rdb.clone(), cache_pool.clone(), templates.clone());
+        match spawn_blocking(move || process_follow_ups(&rdb, &cache_pool, &templates)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => println!("Invite follow-up failed: {}", err),
+            Err(err) => println!("Invite follow-up panicked: {:?}", err),
+        }
+    }
+}
+
+/// Reminds invitees whose invite is about to expire and tells inviters about invites that
+/// expired without being redeemed. Redeemed and replaced invites have no follow-up left.
+fn process_follow_ups(
+    rdb: &DbPool,
+    cache_pool: &CachePool,
+    templates: &EmailTemplates,
+) -> Result<(), String> {
+    let mut cache_connection = cache_pool.get().map_err(|err| err.to_string())?;
+    if !acquire_lease(&mut cache_connection, LEASE_KEY, CHECK_INTERVAL_SECONDS)
+        .map_err(|err| err.to_string())?
+    {
+        return Ok(());
+    }
+    let mut conn = rdb.get().map_err(|err| err.to_string())?;
+    let now = Utc::now();
+
+    for follow_up in
+        invites::due_follow_ups(&mut cache_connection, now).map_err(|err| err.to_string())?
+    {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        if follow_up.expires_at <= now {
+            invites::queue_cancel_follow_up(&mut pipe, &follow_up.invite_id);
+            invites::queue_event(
+                &mut pipe,
+                &follow_up.invite_id,
+                &InviteEvent::new(InviteEventKind::Expired, Some(&follow_up.invited_by)),
+            );
+            outbox::queue_job(&mut pipe, &expired_notice(templates, &follow_up)?);
+            pipe.query::<()>(&mut *cache_connection)
+                .map_err(|err| err.to_string())?;
+
+            audit::record(
+                &mut conn,
+                AuditEvent::new(
+                    "invite.expired",
+                    "system",
+                    &follow_up.email_id,
+                    json!({ "invite_id": follow_up.invite_id }),
+                ),
+            );
+            continue;
+        }
+
+        invites::watch_pending(&mut cache_connection, &follow_up.email_id)
+            .map_err(|err| err.to_string())?;
+        let queued = queue_reminder(
+            &mut conn,
+            &mut cache_connection,
+            templates,
+            &follow_up,
+            &mut pipe,
+        );
+        let committed = match queued {
+            Ok(()) => pipe
+                .query::<Option<()>>(&mut *cache_connection)
+                .map_err(|err| err.to_string())?
+                .is_some(),
+            Err(err) => {
+                let _: Result<(), _> = redis::cmd("UNWATCH").query(&mut *cache_connection);
+                return Err(err);
+            }
+        };
+        if !committed {
+            println!(
+                "Invite {} changed while sending its reminder, retrying",
+                follow_up.invite_id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the reminder for an outstanding invite to `pipe` and reschedules the follow-up for
+/// the expiry notice. Invites that are no longer outstanding lose their follow-up.
+fn queue_reminder(
+    conn: &mut PgConnection,
+    cache_connection: &mut redis::Connection,
+    templates: &EmailTemplates,
+    follow_up: &InviteFollowUp,
+    pipe: &mut redis::Pipeline,
+) -> Result<(), String> {
+    let index = invites::pending_for_email(cache_connection, &follow_up.email_id)
+        .map_err(|err| err.to_string())?
+        .filter(|index| index.invite_id == follow_up.invite_id);
+    let pending = match &index {
+        Some(index) => invites::get_pending(cache_connection, &index.token_hash)
+            .map_err(|err| err.to_string())?,
+        None => None,
+    };
+    let (Some(index), Some(mut pending)) = (index, pending) else {
+        invites::queue_cancel_follow_up(pipe, &follow_up.invite_id);
+        return Ok(());
+    };
+
+    // The original token only exists in the first email, so the reminder carries a new
+    // one. Both stay valid until the invite is redeemed.
+    let remaining = (follow_up.expires_at - Utc::now()).num_seconds().max(60) as u64;
+    let token = invite_tokens::issue(
+        &follow_up.invite_id,
+        &follow_up.email_id,
+        follow_up.expires_at,
+    );
+    pending.token_hash = invite_tokens::hash(&follow_up.email_id, &token);
+
+    let target_app = invite_target_app(conn, &pending.invite).unwrap_or(None);
+    let rendered = render_invite_email(
+        templates,
+        &pending.invite,
+        target_app.as_ref(),
+        &token,
+        remaining / 60 * 60,
+        true,
+    )
+    .map_err(|status| format!("rendering reminder failed with {}", status))?;
+
+    let mut previous_token_hashes = index.previous_token_hashes;
+    previous_token_hashes.push(index.token_hash);
+    invites::queue_pending(
+        pipe,
+        &pending,
+        &PendingInviteRef {
+            invite_id: index.invite_id,
+            token_hash: pending.token_hash.clone(),
+            expires_at: index.expires_at,
+            previous_token_hashes,
+        },
+        remaining as usize,
+    );
+    outbox::queue_job(
+        pipe,
+        &EmailJob::new(
+            random_key(16),
+            &follow_up.email_id,
+            rendered.subject,
+            rendered.html,
+        ),
+    );
+    invites::queue_event(
+        pipe,
+        &follow_up.invite_id,
+        &InviteEvent::new(InviteEventKind::Reminded, Some(&follow_up.email_id)),
+    );
+    invites::queue_follow_up(
+        pipe,
+        &InviteFollowUp {
+            remind_at: None,
+            ..follow_up.clone()
+        },
+    );
+
+    Ok(())
+}
+
+fn expired_notice(
+    templates: &EmailTemplates,
+    follow_up: &InviteFollowUp,
+) -> Result<EmailJob, String> {
+    let rendered = templates
+        .render_invite_expired(&follow_up.email_id, follow_up.expires_at)
+        .map_err(|err| format!("rendering expiry notice failed: {}", err))?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        &follow_up.invited_by,
+        rendered.subject,
+        rendered.html,
+    ))
+}
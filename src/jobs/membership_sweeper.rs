@@ -0,0 +1,134 @@
+use super::{CachePool, DbPool};
+use crate::db::audit::{self, AuditEvent};
+use crate::db::group_cache;
+use crate::db::groups;
+use crate::db::memberships::{self, MembershipWindow};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::redis::{acquire_lease, random_key};
+use crate::emails::EmailTemplates;
+use chrono::{DateTime, Duration, Utc};
+use r2d2_redis::redis;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::{interval, Duration as TickDuration};
+use serde_json::json;
+
+const SWEEP_INTERVAL_SECONDS: u64 = 60;
+// Held for one interval, so each pass runs on a single replica
+const LEASE_KEY: &str = "memberships:sweeper:lease";
+// How long before expiry members are reminded that their access ends
+const REMINDER_LEAD_HOURS: i64 = 24;
+
+pub async fn run(rdb: DbPool, cache_pool: CachePool, templates: EmailTemplates) {
+    let mut ticker = interval(TickDuration::from_secs(SWEEP_INTERVAL_SECONDS));
+
+    loop {
+        ticker.tick().await;
+
+        let (rdb, cache_pool, templates) = (rdb.clone(), cache_pool.clone(), templates.clone());
+        match spawn_blocking(move || sweep(&rdb, &cache_pool, &templates)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => println!("Membership sweep failed: {}", err),
+            Err(err) => println!("Membership sweep panicked: {:?}", err),
+        }
+    }
+}
+
+/// Applies memberships that became valid, removes expired ones and queues reminders for
+/// memberships about to expire.
+fn sweep(rdb: &DbPool, cache_pool: &CachePool, templates: &EmailTemplates) -> Result<(), String> {
+    let mut cache_connection = cache_pool.get().map_err(|err| err.to_string())?;
+    if !acquire_lease(&mut cache_connection, LEASE_KEY, SWEEP_INTERVAL_SECONDS)
+        .map_err(|err| err.to_string())?
+    {
+        return Ok(());
+    }
+    let mut conn = rdb.get().map_err(|err| err.to_string())?;
+    let now = Utc::now();
+
+    for window in
+        memberships::due_to_start(&mut cache_connection, now).map_err(|err| err.to_string())?
+    {
+        groups::add_member(&mut conn, window.group_id, window.user_id)
+            .map_err(|err| err.to_string())?;
+        memberships::mark_started(&mut cache_connection, &window).map_err(|err| err.to_string())?;
+        group_cache::invalidate_quietly(&mut cache_connection, &[window.user_id]);
+
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                "membership.started",
+                "system",
+                &window.email_id,
+                json!({ "group": window.group_identifier, "granted_by": window.granted_by }),
+            ),
+        );
+    }
+
+    for window in
+        memberships::ending_before(&mut cache_connection, now).map_err(|err| err.to_string())?
+    {
+        groups::remove_member(&mut conn, window.group_id, window.user_id)
+            .map_err(|err| err.to_string())?;
+        memberships::delete_window(&mut cache_connection, window.group_id, window.user_id)
+            .map_err(|err| err.to_string())?;
+        group_cache::invalidate_quietly(&mut cache_connection, &[window.user_id]);
+
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                "membership.expired",
+                "system",
+                &window.email_id,
+                json!({ "group": window.group_identifier, "valid_until": window.valid_until }),
+            ),
+        );
+    }
+
+    for mut window in memberships::ending_before(
+        &mut cache_connection,
+        now + Duration::hours(REMINDER_LEAD_HOURS),
+    )
+    .map_err(|err| err.to_string())?
+    {
+        if !reminder_due(&window, now) {
+            continue;
+        }
+
+        window.reminder_sent = true;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        memberships::queue_window(&mut pipe, &window);
+        outbox::queue_job(&mut pipe, &expiry_reminder(templates, &window)?);
+        pipe.query::<()>(&mut *cache_connection)
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Whether a member should now be told their membership is about to end.
+pub(crate) fn reminder_due(window: &MembershipWindow, now: DateTime<Utc>) -> bool {
+    !window.reminder_sent
+        && window
+            .valid_until
+            .is_some_and(|valid_until| valid_until <= now + Duration::hours(REMINDER_LEAD_HOURS))
+}
+
+fn expiry_reminder(
+    templates: &EmailTemplates,
+    window: &MembershipWindow,
+) -> Result<EmailJob, String> {
+    let rendered = templates
+        .render_membership_expiring(
+            &window.group_identifier,
+            window.valid_until.unwrap_or_else(Utc::now),
+        )
+        .map_err(|err| format!("rendering expiry reminder failed: {}", err))?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        &window.email_id,
+        rendered.subject,
+        rendered.html,
+    ))
+}
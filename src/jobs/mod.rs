@@ -0,0 +1,45 @@
+use crate::emails::EmailTemplates;
+use crate::resilience::Dependencies;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use r2d2_redis::RedisConnectionManager;
+use rocket::fairing::AdHoc;
+
+pub mod access_reviews;
+pub mod invite_follow_ups;
+pub mod membership_sweeper;
+pub mod outbox;
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type CachePool = r2d2_redis::r2d2::Pool<RedisConnectionManager>;
+
+// Starts the background jobs once the server is up. Jobs keep their state in Redis,
+// so they are skipped when no Redis pool is managed.
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Background jobs", |rocket| {
+        Box::pin(async move {
+            let (Some(rdb), Some(cache_pool), Some(dependencies), Some(templates)) = (
+                rocket.state::<DbPool>().cloned(),
+                rocket.state::<CachePool>().cloned(),
+                rocket.state::<Dependencies>().cloned(),
+                rocket.state::<EmailTemplates>().cloned(),
+            ) else {
+                println!("Not starting background jobs, redis is not configured");
+                return;
+            };
+
+            rocket::tokio::spawn(membership_sweeper::run(
+                rdb.clone(),
+                cache_pool.clone(),
+                templates.clone(),
+            ));
+            rocket::tokio::spawn(access_reviews::run(
+                rdb.clone(),
+                cache_pool.clone(),
+                templates.clone(),
+            ));
+            rocket::tokio::spawn(invite_follow_ups::run(rdb, cache_pool.clone(), templates));
+            rocket::tokio::spawn(outbox::run(cache_pool, dependencies.notification_service));
+        })
+    })
+}
@@ -0,0 +1,86 @@
+use super::CachePool;
+use crate::db::outbox::{self, EmailJob};
+use crate::middlewares::NotificationService_config::NotificationService_config;
+use crate::resilience::{CallError, Dependency};
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::{interval, Duration};
+use std::sync::Arc;
+use NotificationService::apis::default_api::{send_email, SendEmailParams};
+use NotificationService::models::EmailRequest;
+
+const POLL_INTERVAL_SECONDS: u64 = 5;
+const BATCH_SIZE: isize = 20;
+// Jobs claimed by a worker that dies are retried after this long
+const LEASE_SECONDS: i64 = 120;
+
+pub async fn run(cache_pool: CachePool, notification_service: Arc<Dependency>) {
+    let mut ticker = interval(Duration::from_secs(POLL_INTERVAL_SECONDS));
+
+    loop {
+        ticker.tick().await;
+
+        // Claiming while the breaker is open would only use up delivery attempts
+        if notification_service.is_open() {
+            continue;
+        }
+
+        let pool = cache_pool.clone();
+        let jobs = match spawn_blocking(move || {
+            let mut cache_connection = pool.get().map_err(|err| err.to_string())?;
+            outbox::claim_due(&mut cache_connection, BATCH_SIZE, LEASE_SECONDS)
+                .map_err(|err| err.to_string())
+        })
+        .await
+        {
+            Ok(Ok(jobs)) => jobs,
+            Ok(Err(err)) => {
+                println!("Failed to claim outbox jobs: {}", err);
+                continue;
+            }
+            Err(err) => {
+                println!("Outbox worker panicked: {:?}", err);
+                continue;
+            }
+        };
+
+        for job in jobs {
+            deliver(&cache_pool, &notification_service, job).await;
+        }
+    }
+}
+
+async fn deliver(cache_pool: &CachePool, notification_service: &Dependency, mut job: EmailJob) {
+    let configuration = NotificationService_config::from_isc_secret();
+
+    // Sending is not idempotent, so failures are retried by the outbox schedule rather
+    // than immediately
+    let sent = notification_service
+        .call(|| {
+            let email_request = EmailRequest {
+                to: job.to.clone(),
+                subject: job.subject.clone(),
+                message: job.message.clone(),
+                reply_to: None,
+            };
+            send_email(&configuration.0, SendEmailParams { email_request })
+        })
+        .await;
+
+    match sent {
+        Ok(_) => job.mark_sent(),
+        Err(CallError::Open) => job.mark_failed("notification service unavailable".to_string()),
+        Err(CallError::Timeout) => job.mark_failed("timed out".to_string()),
+        Err(CallError::Failed(err)) => job.mark_failed(format!("{:?}", err)),
+    }
+
+    let pool = cache_pool.clone();
+    let saved = spawn_blocking(move || {
+        let mut cache_connection = pool.get().map_err(|err| err.to_string())?;
+        outbox::save(&mut cache_connection, &job).map_err(|err| err.to_string())
+    })
+    .await;
+
+    if let Ok(Err(err)) = saved {
+        println!("Failed to record outbox delivery: {}", err);
+    }
+}
@@ -9,12 +9,25 @@ use rocket_okapi::openapi_get_routes;
 use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
 use rocket_prometheus::PrometheusMetrics;
 use std::env;
+mod config;
 mod db;
+mod emails;
 mod fairings;
+mod impersonation_tokens;
+mod invite_tokens;
+mod jobs;
 mod middlewares;
 mod models;
+mod permissions;
+mod policy;
+mod resilience;
 mod routes;
-use crate::routes::admin;
+mod user_tokens;
+mod validation;
+use crate::routes::{
+    access_requests, access_reviews, admin, admin_roles, api_keys, authz, domain_rules, groups,
+    impersonation, lockouts, mfa, passwords, sessions,
+};
 
 const SERVICE_PREFIX: &str = "iam-admin";
 
@@ -25,8 +38,18 @@ fn rocket() -> Rocket<Build> {
 
     let mut server = rocket::build()
         .manage(db::connect_rdb())
+        .manage(emails::EmailTemplates::load())
+        .manage(policy::PolicySet::load())
+        .manage(user_tokens::TokenVerifier::from_env())
+        .manage(resilience::Dependencies::new(prometheus.registry()))
+        .manage(db::group_cache::GroupCacheMetrics::new(
+            prometheus.registry(),
+        ))
         .attach(fairings::cors::CORS)
+        .attach(fairings::auth_challenge::AuthChallenge)
+        .attach(fairings::startup_checks::stage())
         .attach(prometheus.clone())
+        .attach(jobs::stage())
         .mount(
             format!("/{}/", SERVICE_PREFIX),
             openapi_get_routes![
@@ -35,9 +58,72 @@ fn rocket() -> Rocket<Build> {
                 admin::update_user_by_email,
                 admin::get_user_by_email,
                 admin::list_paginated_applications,
+                admin::get_app_access_report,
+                admin::export_app_access_report,
                 admin::check_group_exists,
                 admin::check_user_exists,
-                admin::create_invite
+                admin::check_groups_exist,
+                admin::check_users_exist,
+                admin::create_invite,
+                admin::preview_invite,
+                admin::get_invite_delivery,
+                admin::verify_invite,
+                admin::redeem_invite,
+                admin::list_pending_invites,
+                groups::list_child_groups,
+                groups::add_child_group,
+                groups::remove_child_group,
+                groups::get_group_members,
+                groups::add_group_member,
+                groups::remove_group_member,
+                access_requests::create_access_request,
+                access_requests::list_access_requests,
+                access_requests::approve_access_request,
+                access_requests::reject_access_request,
+                access_reviews::create_access_review,
+                access_reviews::get_access_review,
+                access_reviews::list_access_review_items,
+                access_reviews::decide_access_review_item,
+                access_reviews::get_access_review_report,
+                domain_rules::list_domain_rules,
+                domain_rules::create_domain_rule,
+                domain_rules::update_domain_rule,
+                domain_rules::delete_domain_rule,
+                domain_rules::dry_run_domain_rule,
+                domain_rules::evaluate_domain_rules,
+                domain_rules::apply_domain_rules,
+                api_keys::list_api_keys,
+                api_keys::create_api_key,
+                api_keys::rotate_api_key,
+                api_keys::revoke_api_key,
+                admin_roles::get_permission_matrix,
+                admin_roles::list_admin_roles,
+                admin_roles::assign_admin_role,
+                admin_roles::remove_admin_role,
+                authz::explain_decision,
+                impersonation::start_impersonation,
+                impersonation::list_impersonations,
+                impersonation::end_impersonation,
+                impersonation::verify_impersonation,
+                sessions::force_logout_user,
+                sessions::force_logout_app,
+                sessions::revoke_token,
+                passwords::send_password_reset,
+                passwords::set_temporary_password,
+                passwords::set_password_rotation,
+                passwords::get_password_status,
+                passwords::complete_password_reset,
+                passwords::complete_password_rotation,
+                lockouts::lock_user,
+                lockouts::unlock_user,
+                lockouts::record_login_attempt,
+                mfa::get_mfa_status,
+                mfa::set_user_mfa_requirement,
+                mfa::set_app_mfa_requirement,
+                mfa::reset_mfa,
+                mfa::record_mfa_enrollment,
+                mfa::remove_mfa_factor,
+                mfa::get_root_users_without_mfa
             ],
         )
         .mount(
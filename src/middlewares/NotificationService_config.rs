@@ -1,15 +1,32 @@
-
 use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use std::env;
 use NotificationService::apis::configuration::{ApiKey, Configuration}; // Adjust based on your crate structure
 use NotificationService::get_configuration; // Assuming get_configuration exists and returns Configuration
 
 #[derive(Debug)]
 pub struct NotificationService_config(pub Configuration); // Wrapper struct for Configuration
 
+impl NotificationService_config {
+    /// Configuration for calls made by this service itself (background jobs, invites)
+    /// rather than on behalf of the requesting user.
+    pub fn from_isc_secret() -> Self {
+        let mut configuration = get_configuration();
+
+        let token_str = env::var("ISC_SECRET").expect("ISC_SECRET must be set");
+
+        configuration.api_key = Some(ApiKey {
+            key: token_str,
+            prefix: None,
+        });
+
+        NotificationService_config(configuration)
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for NotificationService_config {
     type Error = ();
@@ -0,0 +1,98 @@
+use super::user_claims::UserClaims;
+use crate::db::{admin_roles, users};
+use crate::permissions::{AdminRole, Requirement};
+use diesel::r2d2::ConnectionManager;
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_utils::Claims;
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use r2d2_redis::r2d2::Pool;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::request::OpenApiFromRequest;
+use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::OpenApiError;
+use std::marker::PhantomData;
+
+/// Role of a signed-in user: super admin for root users, otherwise whatever was assigned.
+/// Inactive and unknown users have none.
+pub(crate) fn role_for(conn: &mut PgConnection, email: &str) -> Result<Option<AdminRole>, Status> {
+    match users::find_by_email(conn, email) {
+        Ok(record) if !record.is_active => return Ok(None),
+        Ok(record) if record.is_root => return Ok(Some(AdminRole::SuperAdmin)),
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return Ok(None),
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
+    admin_roles::get(conn, email)
+        .map(|assignment| assignment.map(|assignment| assignment.role))
+        .map_err(|_| Status::InternalServerError)
+}
+
+pub(super) fn admin_role(request: &Request<'_>, email: &str) -> Result<Option<AdminRole>, Status> {
+    let rdb = request
+        .rocket()
+        .state::<Pool<ConnectionManager<PgConnection>>>()
+        .ok_or(Status::InternalServerError)?;
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    role_for(&mut conn, email)
+}
+
+/// A signed-in admin whose role grants `R`'s permission.
+pub struct Admin<R> {
+    pub claims: Claims,
+    requirement: PhantomData<R>,
+}
+
+#[rocket::async_trait]
+impl<'r, R: Requirement> FromRequest<'r> for Admin<R> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Outcome::Success(claims) = request.guard::<UserClaims>().await else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        match admin_role(request, &claims.sub) {
+            Ok(Some(role)) if role.can(R::PERMISSION) => Outcome::Success(Admin {
+                claims: claims.0,
+                requirement: PhantomData,
+            }),
+            Ok(_) => Outcome::Error((Status::Forbidden, ())),
+            Err(status) => Outcome::Error((status, ())),
+        }
+    }
+}
+
+impl<'a, R: Requirement> OpenApiFromRequest<'a> for Admin<R> {
+    fn from_request_input(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> Result<RequestHeaderInput, OpenApiError> {
+        let security_scheme = SecurityScheme {
+            description: Some(
+                "Requires a Bearer token of an admin whose role holds the listed permission"
+                    .to_owned(),
+            ),
+            data: SecuritySchemeData::ApiKey {
+                name: "Authorization".to_owned(),
+                location: "header".to_owned(),
+            },
+            extensions: Object::default(),
+        };
+
+        let mut security_req = SecurityRequirement::new();
+        security_req.insert(
+            "AdminRoleAuth".to_owned(),
+            vec![R::PERMISSION.as_str().to_owned()],
+        );
+
+        Ok(RequestHeaderInput::Security(
+            "AdminRoleAuth".to_owned(),
+            security_scheme,
+            security_req,
+        ))
+    }
+}
@@ -1,11 +1,18 @@
+use super::user_claims::UserClaims;
 use super::IAMService_config::IAMService_config;
+use crate::db::group_cache::{self, GroupCacheMetrics, GroupListKind};
+use crate::resilience::{CallError, Dependencies};
 use ginger_shared_rs::rocket_utils::Claims;
+use r2d2_redis::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
+use rocket::tokio::task::spawn_blocking;
 use rocket_okapi::request::OpenApiFromRequest;
 use rocket_okapi::request::RequestHeaderInput;
 use rocket_okapi::OpenApiError;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use IAMService::apis::default_api::identity_get_group_memberships;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,23 +24,87 @@ impl GroupMemberships {
     }
 }
 
+/// Resolves the caller's group list, from the Redis cache when possible and otherwise
+/// from the IAM service. Works without Redis, just without caching.
+pub(super) async fn cached_group_list<F, Fut, E>(
+    request: &Request<'_>,
+    claims: &Claims,
+    kind: GroupListKind,
+    fetch: F,
+) -> Outcome<Vec<String>, ()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Vec<String>, E>>,
+{
+    let metrics = request.rocket().state::<GroupCacheMetrics>();
+    // r2d2 and the Redis client block, so they run off the async workers like the jobs do.
+    // The connection is kept for storing the list after a miss.
+    let mut cache_connection = None;
+    if let Some(cache_pool) = request.rocket().state::<Pool<RedisConnectionManager>>() {
+        let (cache_pool, user_id) = (cache_pool.clone(), claims.user_id.clone());
+        let lookup = spawn_blocking(move || {
+            let mut cache_connection = cache_pool.get().ok()?;
+            let cached = group_cache::get(&mut cache_connection, kind, &user_id)
+                .ok()
+                .flatten();
+            Some((cache_connection, cached))
+        })
+        .await;
+
+        if let Ok(Some((connection, cached))) = lookup {
+            if let Some(metrics) = metrics {
+                metrics.record(kind, cached.is_some());
+            }
+            if let Some(groups) = cached {
+                return Outcome::Success(groups);
+            }
+            cache_connection = Some(connection);
+        }
+    }
+
+    let Some(dependencies) = request.rocket().state::<Dependencies>() else {
+        return Outcome::Error((Status::InternalServerError, ()));
+    };
+
+    match dependencies.iam_service.call_idempotent(fetch).await {
+        Ok(groups) => {
+            if let Some(mut cache_connection) = cache_connection {
+                let (user_id, cached_groups) = (claims.user_id.clone(), groups.clone());
+                let stored = spawn_blocking(move || {
+                    group_cache::put(&mut cache_connection, kind, &user_id, &cached_groups)
+                })
+                .await;
+                match stored {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => println!("Failed to cache group list: {}", err),
+                    Err(err) => println!("Caching group list panicked: {:?}", err),
+                }
+            }
+            Outcome::Success(groups)
+        }
+        Err(CallError::Open) => Outcome::Error((Status::ServiceUnavailable, ())),
+        Err(CallError::Timeout) => Outcome::Error((Status::GatewayTimeout, ())),
+        Err(CallError::Failed(_)) => Outcome::Error((Status::InternalServerError, ())),
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for GroupMemberships {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let claims = request.guard::<Claims>().await;
+        let claims = request.guard::<UserClaims>().await;
 
         let iam_service_config = request.guard::<IAMService_config>().await;
 
         if let (Outcome::Success(claims), Outcome::Success(openapi_config)) =
             (claims, iam_service_config)
         {
-            // Here you would proxy to the IAM service
-            match identity_get_group_memberships(&openapi_config.0).await {
-                Ok(groups) => Outcome::Success(GroupMemberships::new(groups)),
-                Err(_) => Outcome::Error((Status::InternalServerError, ())),
-            }
+            cached_group_list(request, &claims, GroupListKind::Memberships, || {
+                identity_get_group_memberships(&openapi_config.0)
+            })
+            .await
+            .map(GroupMemberships::new)
         } else {
             Outcome::Error((Status::Unauthorized, ()))
         }
@@ -1,5 +1,7 @@
+use super::groups::cached_group_list;
+use super::user_claims::UserClaims;
 use super::IAMService_config::IAMService_config;
-use ginger_shared_rs::rocket_utils::Claims;
+use crate::db::group_cache::GroupListKind;
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket_okapi::request::OpenApiFromRequest;
@@ -22,18 +24,18 @@ impl<'r> FromRequest<'r> for GroupOwnerships {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let claims = request.guard::<Claims>().await;
+        let claims = request.guard::<UserClaims>().await;
 
         let iam_service_config = request.guard::<IAMService_config>().await;
 
         if let (Outcome::Success(claims), Outcome::Success(openapi_config)) =
             (claims, iam_service_config)
         {
-            // Here you would proxy to the IAM service
-            match identity_get_group_ownserships(&openapi_config.0).await {
-                Ok(groups) => Outcome::Success(GroupOwnerships::new(groups)),
-                Err(_) => Outcome::Error((Status::InternalServerError, ())),
-            }
+            cached_group_list(request, &claims, GroupListKind::Ownerships, || {
+                identity_get_group_ownserships(&openapi_config.0)
+            })
+            .await
+            .map(GroupOwnerships::new)
         } else {
             Outcome::Error((Status::Unauthorized, ()))
         }
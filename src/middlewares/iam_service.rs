@@ -0,0 +1,55 @@
+use crate::config;
+use ginger_shared_rs::rocket_utils::APIClaims;
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::request::OpenApiFromRequest;
+use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::OpenApiError;
+
+/// The IAM service itself, calling with an ISC token whose subject is listed in
+/// `IAM_SERVICE_SUBJECTS`. Used by the routes that report what happened at sign-in, which
+/// no other service should be able to fake.
+pub struct IamService(pub APIClaims);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IamService {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Outcome::Success(api_claims) = request.guard::<APIClaims>().await else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+        if config::internal_callers("IAM_SERVICE_SUBJECTS").contains(&api_claims.sub) {
+            Outcome::Success(IamService(api_claims))
+        } else {
+            Outcome::Error((Status::Forbidden, ()))
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for IamService {
+    fn from_request_input(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> Result<RequestHeaderInput, OpenApiError> {
+        let security_scheme = SecurityScheme {
+            description: Some("An ISC token issued to the IAM service".to_owned()),
+            data: SecuritySchemeData::ApiKey {
+                name: "X-API-Authorization".to_owned(),
+                location: "header".to_owned(),
+            },
+            extensions: Object::default(),
+        };
+
+        let mut security_req = SecurityRequirement::new();
+        security_req.insert("BearerAPIAuth".to_owned(), Vec::new());
+
+        Ok(RequestHeaderInput::Security(
+            "BearerAPIAuth".to_owned(),
+            security_scheme,
+            security_req,
+        ))
+    }
+}
@@ -0,0 +1,170 @@
+use super::admin::admin_role;
+use super::user_claims::UserClaims;
+use crate::config;
+use crate::db::api_keys;
+use crate::permissions::Requirement;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_utils::APIClaims;
+use ipnet::IpNet;
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::request::OpenApiFromRequest;
+use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::OpenApiError;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+
+/// A caller allowed to use lookups that would otherwise leak which accounts exist: another
+/// service with a signed ISC token or an API key holding `R`'s scope, or a signed-in admin
+/// whose role holds `R`'s permission. Routes returning personal data only take services
+/// named in `R::CALLERS`.
+#[derive(Debug)]
+pub struct InternalCaller<R> {
+    /// `service:<sub>`, `api_key:<id>` or `user:<sub>`, used to key rate limits
+    pub id: String,
+    pub ip: Option<IpAddr>,
+    requirement: PhantomData<R>,
+}
+
+impl<R> InternalCaller<R> {
+    fn new(id: String, ip: Option<IpAddr>) -> Self {
+        InternalCaller {
+            id,
+            ip,
+            requirement: PhantomData,
+        }
+    }
+}
+
+/// The address a request came from. Behind the ingress the peer is a proxy, so when it is
+/// trusted the client is the last `X-Forwarded-For` hop that isn't a trusted proxy itself;
+/// anything further left could have been written by the client.
+pub(crate) fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+
+    let peer = peer?;
+    if !trusted(&peer) {
+        return Some(peer);
+    }
+
+    let Some(forwarded_for) = forwarded_for else {
+        return Some(peer);
+    };
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop;
+        if !trusted(&hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// Whether a service may call routes requiring `R`, given the services named for it.
+pub(crate) fn allows_service<R: Requirement>(named: &[String], service: &str) -> bool {
+    R::CALLERS.is_none() || named.iter().any(|caller| caller == service)
+}
+
+fn named_callers<R: Requirement>() -> Vec<String> {
+    R::CALLERS.map(config::internal_callers).unwrap_or_default()
+}
+
+#[rocket::async_trait]
+impl<'r, R: Requirement> FromRequest<'r> for InternalCaller<R> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let ip = client_ip(
+            request.remote().map(|remote| remote.ip()),
+            request.headers().get_one("X-Forwarded-For"),
+            &config::trusted_proxies(),
+        );
+
+        if let Some(presented) = request.headers().get_one("X-API-Key") {
+            let Some(mut conn) = request
+                .rocket()
+                .state::<Pool<ConnectionManager<PgConnection>>>()
+                .and_then(|rdb| rdb.get().ok())
+            else {
+                return Outcome::Error((Status::InternalServerError, ()));
+            };
+
+            return match api_keys::authenticate(&mut conn, presented.trim()) {
+                Ok(Some(api_key)) => match R::SCOPE {
+                    Some(scope)
+                        if api_key.scopes.iter().any(|granted| granted == scope)
+                            && allows_service::<R>(&named_callers::<R>(), &api_key.name) =>
+                    {
+                        Outcome::Success(InternalCaller::new(format!("api_key:{}", api_key.id), ip))
+                    }
+                    _ => Outcome::Error((Status::Forbidden, ())),
+                },
+                Ok(None) => Outcome::Error((Status::Unauthorized, ())),
+                Err(_) => Outcome::Error((Status::InternalServerError, ())),
+            };
+        }
+
+        if let Outcome::Success(api_claims) = request.guard::<APIClaims>().await {
+            if !allows_service::<R>(&named_callers::<R>(), &api_claims.sub) {
+                return Outcome::Error((Status::Forbidden, ()));
+            }
+            return Outcome::Success(InternalCaller::new(
+                format!("service:{}", api_claims.sub),
+                ip,
+            ));
+        }
+
+        let Outcome::Success(claims) = request.guard::<UserClaims>().await else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+        match admin_role(request, &claims.sub) {
+            Ok(Some(role)) if role.can(R::PERMISSION) => {
+                Outcome::Success(InternalCaller::new(format!("user:{}", claims.sub), ip))
+            }
+            Ok(_) => Outcome::Error((Status::Forbidden, ())),
+            Err(status) => Outcome::Error((status, ())),
+        }
+    }
+}
+
+impl<'a, R: Requirement> OpenApiFromRequest<'a> for InternalCaller<R> {
+    fn from_request_input(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> Result<RequestHeaderInput, OpenApiError> {
+        let security_scheme = SecurityScheme {
+            description: Some(
+                "An API key with the listed scope. ISC tokens in X-API-Authorization and admin \
+                 Bearer tokens whose role holds the matching permission are accepted too"
+                    .to_owned(),
+            ),
+            data: SecuritySchemeData::ApiKey {
+                name: "X-API-Key".to_owned(),
+                location: "header".to_owned(),
+            },
+            extensions: Object::default(),
+        };
+
+        let mut security_req = SecurityRequirement::new();
+        security_req.insert(
+            "ApiKeyAuth".to_owned(),
+            R::SCOPE.map(str::to_owned).into_iter().collect(),
+        );
+
+        Ok(RequestHeaderInput::Security(
+            "ApiKeyAuth".to_owned(),
+            security_scheme,
+            security_req,
+        ))
+    }
+}
@@ -1,4 +1,8 @@
 pub mod IAMService_config;
 pub mod NotificationService_config;
+pub mod admin;
 pub mod groups;
 pub mod groups_owned;
+pub mod iam_service;
+pub mod internal_caller;
+pub mod user_claims;
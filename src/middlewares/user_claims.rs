@@ -0,0 +1,116 @@
+use crate::config;
+use crate::db::revocations::{self, TokenIdentity};
+use crate::user_tokens::{TokenRejection, TokenVerifier};
+use ginger_shared_rs::rocket_utils::Claims;
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use r2d2_redis::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::request::OpenApiFromRequest;
+use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::OpenApiError;
+use std::ops::Deref;
+
+/// Claims of a bearer token checked by the service's `TokenVerifier`, which unlike the
+/// shared `Claims` guard also accepts RS256/ES256 tokens from the configured JWKS, and
+/// against the revocation markers in Redis.
+pub struct UserClaims(pub Claims);
+
+impl Deref for UserClaims {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.0
+    }
+}
+
+/// The reason the request's bearer token was refused, read back by the `AuthChallenge`
+/// fairing to explain the 401.
+pub struct TokenFailure(pub Option<TokenRejection>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserClaims {
+    type Error = TokenRejection;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let reject = |rejection: TokenRejection| {
+            request.local_cache(|| TokenFailure(Some(rejection)));
+            Outcome::Error((Status::Unauthorized, rejection))
+        };
+
+        let values: Vec<_> = request.headers().get("Authorization").collect();
+        if values.len() != 1 {
+            return reject(TokenRejection::Missing);
+        }
+        let token = values[0].trim_start_matches("Bearer ").trim();
+
+        let Some(verifier) = request.rocket().state::<TokenVerifier>() else {
+            return Outcome::Error((Status::InternalServerError, TokenRejection::KeysUnavailable));
+        };
+        let verified = match verifier.verify(token).await {
+            Ok(verified) => verified,
+            Err(rejection) => return reject(rejection),
+        };
+
+        let Some(mut cache_connection) = request
+            .rocket()
+            .state::<Pool<RedisConnectionManager>>()
+            .and_then(|cache_pool| cache_pool.get().ok())
+        else {
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                TokenRejection::RevocationsUnavailable,
+            ));
+        };
+        let identity = TokenIdentity {
+            sub: &verified.claims.sub,
+            client_id: verified.claims.client_id.as_deref(),
+            jti: verified.jti.as_deref(),
+            issued_at: revocations::issued_at(
+                verified.iat,
+                verified.claims.exp as i64,
+                config::access_token_lifetime_seconds(),
+            ),
+        };
+        // Fails closed: a deactivated user must not get back in while Redis is down
+        match revocations::is_revoked(&mut cache_connection, &identity) {
+            Ok(false) => Outcome::Success(UserClaims(verified.claims)),
+            Ok(true) => reject(TokenRejection::Revoked),
+            Err(_) => Outcome::Error((
+                Status::ServiceUnavailable,
+                TokenRejection::RevocationsUnavailable,
+            )),
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for UserClaims {
+    fn from_request_input(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> Result<RequestHeaderInput, OpenApiError> {
+        let security_scheme = SecurityScheme {
+            description: Some(
+                "Requires a Bearer token, HS256 or RS256/ES256 signed by a key in the \
+                 configured JWKS"
+                    .to_owned(),
+            ),
+            data: SecuritySchemeData::ApiKey {
+                name: "Authorization".to_owned(),
+                location: "header".to_owned(),
+            },
+            extensions: Object::default(),
+        };
+
+        let mut security_req = SecurityRequirement::new();
+        security_req.insert("BearerAuth".to_owned(), Vec::new());
+
+        Ok(RequestHeaderInput::Security(
+            "BearerAuth".to_owned(),
+            security_scheme,
+            security_req,
+        ))
+    }
+}
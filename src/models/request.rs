@@ -1,3 +1,9 @@
+use crate::db::access_reviews::ReviewDecision;
+use crate::db::domain_rules::DomainRuleAction;
+use crate::db::mfa::MfaFactorKind;
+use crate::permissions::AdminRole;
+use crate::policy::ResourceKind;
+use chrono::{DateTime, Utc};
 use rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -17,4 +23,238 @@ pub struct InviteRequest {
     pub middle_name: Option<String>,
     pub last_name: String,
     pub is_root: bool,
+    /// How long the invite link stays valid, between 15 minutes and 7 days
+    pub expires_in_seconds: Option<u64>,
+    /// App the invitee is sent to after accepting, by `client_id`
+    pub app_client_id: Option<String>,
+    /// Language of the invitation email, e.g. `es` or `pt-BR`. Defaults to English.
+    pub locale: Option<String>,
+    /// Groups the invitee is added to when the invite is redeemed
+    #[serde(default)]
+    pub group_identifiers: Vec<String>,
+    /// Apps the invitee gets access to when the invite is redeemed, by `client_id`.
+    /// Access is granted through membership of each app's group.
+    #[serde(default)]
+    pub app_client_ids: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct UserExpansion {
+    pub groups: bool,
+    pub apps: bool,
+    pub invites: bool,
+    pub audit: bool,
+    pub lock: bool,
+}
+
+impl UserExpansion {
+    /// Parses a comma separated `expand` query value such as `groups,apps`.
+    pub fn parse(raw: Option<&str>) -> Result<Self, String> {
+        let mut expansion = UserExpansion::default();
+
+        for part in raw.unwrap_or_default().split(',').map(str::trim) {
+            match part {
+                "" => {}
+                "groups" => expansion.groups = true,
+                "apps" => expansion.apps = true,
+                "invites" => expansion.invites = true,
+                "audit" => expansion.audit = true,
+                "lock" => expansion.lock = true,
+                other => return Err(other.to_string()),
+            }
+        }
+
+        Ok(expansion)
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct NestGroupRequest {
+    pub child_identifier: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct AddGroupMemberRequest {
+    pub email_id: String,
+    /// Defaults to now. A future value schedules the membership.
+    pub valid_from: Option<DateTime<Utc>>,
+    /// Leave empty for a permanent membership
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct CreateAccessRequest {
+    pub group_identifier: String,
+    pub reason: String,
+    pub duration_hours: i64,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct CreateAccessReviewRequest {
+    pub name: String,
+    /// Review everyone with access to this app through its group
+    pub app_id: Option<i64>,
+    /// Review the members of this group; ignored when `app_id` is set
+    pub group_identifier: Option<String>,
+    /// Items still undecided at the deadline are revoked automatically
+    pub deadline: DateTime<Utc>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct AccessReviewDecisionRequest {
+    pub decision: ReviewDecision,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct RedeemInviteRequest {
+    pub token: String,
+    /// Email the invite was sent to; tokens only redeem for their own address
+    pub email_id: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct DomainRuleRequest {
+    /// Email domain without the `@`, e.g. `acme.com`
+    pub domain: String,
+    pub action: DomainRuleAction,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct EvaluateDomainRulesRequest {
+    pub email_id: String,
+    /// App the user is registering with, if any
+    pub app_client_id: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct ApplyDomainRulesRequest {
+    pub email_id: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct UserExistsBatchRequest {
+    pub email_ids: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct GroupExistsBatchRequest {
+    pub identifiers: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct CreateApiKeyRequest {
+    /// Which service the key is for, e.g. `billing-service`
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct AssignAdminRoleRequest {
+    pub role: AdminRole,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct ExplainRequest {
+    /// e.g. `user.deactivate` or `app.require_mfa`
+    pub action: String,
+    pub resource_kind: ResourceKind,
+    /// Email of a user or client id of an app
+    pub resource_id: String,
+    /// Explain for someone else instead of the caller
+    pub subject_email: Option<String>,
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct StartImpersonationRequest {
+    pub target_email: String,
+    /// The only app the token will be valid for
+    pub app_client_id: String,
+    /// Why support needs to act as the user, e.g. a ticket reference. Required.
+    pub reason: String,
+    /// Defaults to 15 minutes, at most 60
+    pub duration_minutes: Option<i64>,
+    /// Marks the token `read_only` so apps refuse changes made with it. Defaults to true.
+    #[serde(default = "default_read_only")]
+    pub read_only: bool,
+    /// Emails the user that an admin is acting as them
+    #[serde(default)]
+    pub notify_user: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct VerifyImpersonationRequest {
+    pub token: String,
+    /// The app the token was presented to
+    pub client_id: String,
+    /// Whether the request made with the token changes anything. Read-only tokens are
+    /// refused for those.
+    #[serde(default)]
+    pub writes: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct RevokeTokenRequest {
+    /// The bearer token to revoke. It must carry a `jti`.
+    pub token: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SetTemporaryPasswordRequest {
+    /// Generated and returned once when left out
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct PasswordRotationRequest {
+    pub required: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct CompletePasswordResetRequest {
+    pub email_id: String,
+    /// Token from the reset link
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct PasswordChangedRequest {
+    pub email_id: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct LockUserRequest {
+    pub reason: String,
+    /// Lifts the lock automatically at this time. Locked until unlocked when left out.
+    pub unlock_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct LoginAttemptRequest {
+    pub email_id: String,
+    pub succeeded: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct MfaRequirementRequest {
+    pub required: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Serialize)]
+pub struct MfaEnrollmentRequest {
+    pub email_id: String,
+    pub kind: MfaFactorKind,
+    /// Shown to admins, e.g. the authenticator or device name
+    pub label: Option<String>,
 }
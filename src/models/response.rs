@@ -1,5 +1,17 @@
+use crate::db::access_reviews::{ReviewCampaign, ReviewItem};
+use crate::db::api_keys::ApiKey;
+use crate::db::audit::AuditEvent;
+use crate::db::groups::GroupRef;
+use crate::db::impersonations::ImpersonationSession;
+use crate::db::invites::InviteHistoryEntry;
+use crate::db::lockouts::AccountLock;
+use crate::db::mfa::{MfaFactor, MfaRequirement};
+use crate::db::outbox::{DeliveryStatus, EmailJob};
+use crate::db::passwords::PasswordRotation;
+use crate::models::request::InviteRequest;
 use crate::models::schema::App;
 use crate::models::schema::User;
+use crate::permissions::Permission;
 use chrono::{DateTime, NaiveDate, Utc};
 use rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -12,6 +24,10 @@ pub struct UserResponse {
     pub email_id: String,
     pub is_root: bool,
     pub is_active: bool,
+    /// Whether the user has at least one MFA factor enrolled
+    pub mfa_enrolled: bool,
+    /// Whether MFA is required for the user in every app
+    pub mfa_required: bool,
 }
 
 impl From<User> for UserResponse {
@@ -23,10 +39,30 @@ impl From<User> for UserResponse {
             email_id: user.email_id,
             is_root: user.is_root,
             is_active: user.is_active,
+            mfa_enrolled: false,
+            mfa_required: false,
         }
     }
 }
 
+#[derive(Serialize, JsonSchema)]
+pub struct UserDetailResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<GroupRef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owned_groups: Option<Vec<GroupRef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apps: Option<Vec<AppResponse>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invites: Option<Vec<InviteHistoryEntry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit: Option<Vec<AuditEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_status: Option<AccountLockStatus>,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema)]
 pub struct AppResponse {
     pub client_id: String,
@@ -37,6 +73,8 @@ pub struct AppResponse {
     pub tnc_link: Option<String>,
     pub allow_registration: bool,
     pub id: i64,
+    /// Whether signing in to the app needs a second factor
+    pub mfa_required: bool,
 }
 
 impl From<App> for AppResponse {
@@ -50,6 +88,279 @@ impl From<App> for AppResponse {
             tnc_link: app.tnc_link,
             allow_registration: app.allow_registration,
             id: app.id,
+            mfa_required: app.mfa_required,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AppAccessEntry {
+    pub email_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub is_root: bool,
+    /// Every reason this user can sign in to the app, e.g. `member of group <identifier>`
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct GroupMemberEntry {
+    pub email_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// Group the membership was granted on; differs from the requested group for inherited members
+    pub via_group: String,
+    pub depth: i32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct GroupMembersResponse {
+    pub direct: Vec<GroupMemberEntry>,
+    pub inherited: Vec<GroupMemberEntry>,
+}
+
+/// Splits members by depth into direct and inherited, each ordered by email.
+impl FromIterator<GroupMemberEntry> for GroupMembersResponse {
+    fn from_iter<I: IntoIterator<Item = GroupMemberEntry>>(entries: I) -> Self {
+        let (mut direct, mut inherited): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|entry| entry.depth == 0);
+        direct.sort_by(|a, b| a.email_id.cmp(&b.email_id));
+        inherited.sort_by(|a, b| a.email_id.cmp(&b.email_id));
+
+        GroupMembersResponse { direct, inherited }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AccessReviewSummary {
+    #[serde(flatten)]
+    pub campaign: ReviewCampaign,
+    pub total_items: usize,
+    pub approved: usize,
+    pub revoked: usize,
+    pub pending: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AccessReviewReport {
+    pub campaign: ReviewCampaign,
+    pub items: Vec<ReviewItem>,
+    pub generated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SignedAccessReviewReport {
+    pub report: AccessReviewReport,
+    pub algorithm: String,
+    /// Hex encoded HMAC of the JSON serialised `report`
+    pub signature: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct InviteResponse {
+    pub invite_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub delivery: DeliveryStatus,
+    /// Outstanding invite invalidated by a forced re-issue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaced_invite_id: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct InviteErrorResponse {
+    /// Stable identifier of the failure, e.g. `user_exists` or `invite_pending`
+    pub code: String,
+    pub message: String,
+    /// The conflicting invite, for `invite_pending`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct InviteDeliveryResponse {
+    pub invite_id: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl From<EmailJob> for InviteDeliveryResponse {
+    fn from(job: EmailJob) -> Self {
+        InviteDeliveryResponse {
+            invite_id: job.id,
+            status: job.status,
+            attempts: job.attempts,
+            last_error: job.last_error,
+            next_attempt_at: job.next_attempt_at,
+            sent_at: job.sent_at,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct RedeemedInviteResponse {
+    pub invite_id: String,
+    pub invited_by: String,
+    /// Groups the invitee was added to on redemption
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub granted_groups: Vec<String>,
+    #[serde(flatten)]
+    pub invite: InviteRequest,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PendingInviteEntry {
+    pub invite_id: String,
+    pub email_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub is_root: bool,
+    pub invited_by: String,
+    pub group_identifiers: Vec<String>,
+    pub app_client_ids: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DomainRuleImpact {
+    pub email_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// What the rule would change for this user
+    pub effect: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DomainRuleDryRunResponse {
+    pub total_count: usize,
+    pub affected: Vec<DomainRuleImpact>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AppliedDomainRulesResponse {
+    pub email_id: String,
+    /// Groups the user was added to
+    pub granted_groups: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    /// Until when the secret before the last rotation is still accepted
+    pub previous_valid_until: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(api_key: ApiKey) -> Self {
+        ApiKeyResponse {
+            id: api_key.id,
+            name: api_key.name,
+            scopes: api_key.scopes,
+            created_by: api_key.created_by,
+            created_at: api_key.created_at,
+            rotated_at: api_key.rotated_at,
+            previous_valid_until: api_key.previous_valid_until,
+            revoked_at: api_key.revoked_at,
         }
     }
 }
+
+#[derive(Serialize, JsonSchema)]
+pub struct ApiKeySecretResponse {
+    #[serde(flatten)]
+    pub key: ApiKeyResponse,
+    /// Sent as the `X-API-Key` header. Only shown once, it can't be recovered later.
+    pub api_key: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PermissionMatrixEntry {
+    pub permission: Permission,
+    /// Roles holding the permission
+    pub roles: Vec<&'static str>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ImpersonationTokenResponse {
+    #[serde(flatten)]
+    pub session: ImpersonationSession,
+    /// Bearer token for the target app. Its `token_type` and `aud` are `impersonation`
+    /// and `act.sub` names the admin. The app checks it with `POST /impersonations/verify`.
+    pub token: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SessionRevocationResponse {
+    /// Email of the user, client id of the app, or `jti` of the token
+    pub revoked: String,
+    /// Tokens issued at or before this time no longer work
+    pub issued_before: Option<DateTime<Utc>>,
+    /// For a single token, when it would have expired anyway
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PasswordResetResponse {
+    pub email_id: String,
+    pub expires_at: DateTime<Utc>,
+    /// Outbox job delivering the reset email
+    pub email_job_id: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TemporaryPasswordResponse {
+    pub email_id: String,
+    /// Only set when the password was generated. Shown once.
+    pub temporary_password: Option<String>,
+    pub must_rotate: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PasswordStatusResponse {
+    pub email_id: String,
+    /// The user has to choose a new password at next login
+    pub must_rotate: bool,
+    pub rotation: Option<PasswordRotation>,
+    /// Expiry of an outstanding reset link
+    pub reset_pending_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AccountLockStatus {
+    pub email_id: String,
+    pub locked: bool,
+    pub lock: Option<AccountLock>,
+    /// Failed logins in a row, counted within `FAILED_LOGIN_WINDOW_SECONDS` of each other
+    pub failed_logins: u32,
+    pub last_failed_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MfaStatusResponse {
+    pub email_id: String,
+    pub enrolled: bool,
+    pub factors: Vec<MfaFactor>,
+    /// Whether the login being checked needs a second factor
+    pub required: bool,
+    pub user_requirement: Option<MfaRequirement>,
+    /// Whether the app given as `client_id` requires MFA
+    pub app_requires_mfa: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MfaResetResponse {
+    pub email_id: String,
+    pub factors_removed: usize,
+    /// Outbox job telling the user, when any factor was removed
+    pub email_job_id: Option<String>,
+}
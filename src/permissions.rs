@@ -0,0 +1,165 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Root users are always super admins. Everyone else needs a role assigned through
+// `/admin-roles` to use the admin routes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// Read-only access to users, groups, apps, invites and reports
+    Viewer,
+    /// Helpdesk: a viewer who can also send and resend invites and edit non-root users
+    Operator,
+    SuperAdmin,
+}
+
+// Declares `Permission` along with its wire names and the full list, so the matrix can't
+// miss a permission added later.
+macro_rules! permissions {
+    ($($(#[$meta:meta])* $variant:ident => $name:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+        pub enum Permission {
+            $($(#[$meta])* #[serde(rename = $name)] $variant,)*
+        }
+
+        impl Permission {
+            pub const ALL: &'static [Permission] = &[$(Permission::$variant,)*];
+
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(Permission::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+permissions! {
+    ViewUsers => "view_users",
+    EditUsers => "edit_users",
+    /// Making a user root or taking root away
+    GrantRoot => "grant_root",
+    ViewGroups => "view_groups",
+    /// Nesting groups, which passes the parent's access to the child's members
+    EditGroups => "edit_groups",
+    ViewApps => "view_apps",
+    EditApps => "edit_apps",
+    ViewAccessReports => "view_access_reports",
+    ViewInvites => "view_invites",
+    SendInvites => "send_invites",
+    ManageRoles => "manage_roles",
+}
+
+const VIEWER: &[Permission] = &[
+    Permission::ViewUsers,
+    Permission::ViewGroups,
+    Permission::ViewApps,
+    Permission::ViewAccessReports,
+    Permission::ViewInvites,
+];
+
+const OPERATOR: &[Permission] = &[
+    Permission::ViewUsers,
+    Permission::ViewGroups,
+    Permission::ViewApps,
+    Permission::ViewAccessReports,
+    Permission::ViewInvites,
+    Permission::SendInvites,
+    Permission::EditUsers,
+];
+
+const SUPER_ADMIN: &[Permission] = &[
+    Permission::ViewUsers,
+    Permission::ViewGroups,
+    Permission::ViewApps,
+    Permission::ViewAccessReports,
+    Permission::ViewInvites,
+    Permission::SendInvites,
+    Permission::EditUsers,
+    Permission::GrantRoot,
+    Permission::EditGroups,
+    Permission::EditApps,
+    Permission::ManageRoles,
+];
+
+impl AdminRole {
+    pub const ALL: [AdminRole; 3] = [
+        AdminRole::Viewer,
+        AdminRole::Operator,
+        AdminRole::SuperAdmin,
+    ];
+
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            AdminRole::Viewer => VIEWER,
+            AdminRole::Operator => OPERATOR,
+            AdminRole::SuperAdmin => SUPER_ADMIN,
+        }
+    }
+
+    pub fn can(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdminRole::Viewer => "viewer",
+            AdminRole::Operator => "operator",
+            AdminRole::SuperAdmin => "super_admin",
+        }
+    }
+}
+
+impl Permission {
+    /// Roles holding the permission.
+    pub fn roles(self) -> Vec<&'static str> {
+        AdminRole::ALL
+            .into_iter()
+            .filter(|role| role.can(self))
+            .map(AdminRole::as_str)
+            .collect()
+    }
+}
+
+/// What a route requires, used as the type parameter of the `Admin` and `InternalCaller`
+/// guards so the requirement shows up in the route signature and the OpenAPI docs.
+pub trait Requirement: Send + Sync + 'static {
+    const PERMISSION: Permission;
+    /// API key scope accepted instead, for routes other services call
+    const SCOPE: Option<&'static str> = None;
+    /// Variable listing the services allowed to call with the scope, for routes that return
+    /// personal data. Other services are refused even with a valid token or key.
+    const CALLERS: Option<&'static str> = None;
+}
+
+macro_rules! requirements {
+    ($($name:ident => $permission:ident $(, scope $scope:literal)? $(, callers $callers:literal)?;)*) => {
+        $(
+            pub struct $name;
+
+            impl Requirement for $name {
+                const PERMISSION: Permission = Permission::$permission;
+                $(const SCOPE: Option<&'static str> = Some($scope);)?
+                $(const CALLERS: Option<&'static str> = Some($callers);)?
+            }
+        )*
+    };
+}
+
+pub mod require {
+    use super::{Permission, Requirement};
+
+    requirements! {
+        ReadUsers => ViewUsers, scope "users:read", callers "USERS_READ_CALLERS";
+        UserExists => ViewUsers, scope "users:exists";
+        ViewUsers => ViewUsers;
+        ReadGroups => ViewGroups, scope "groups:read", callers "GROUPS_READ_CALLERS";
+        GroupExists => ViewGroups, scope "groups:exists";
+        ViewApps => ViewApps;
+        ViewAccessReports => ViewAccessReports;
+        ViewInvites => ViewInvites;
+        SendInvites => SendInvites;
+        ManageRoles => ManageRoles;
+    }
+}
@@ -0,0 +1,225 @@
+use crate::permissions::{AdminRole, Permission};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+// Fine-grained authorization on top of admin roles. Policies match actions such as
+// `user.deactivate` and hold when all of their conditions do. A matching deny wins over
+// any allow, and nothing is allowed unless a policy allows it.
+
+const BUILTIN_POLICIES: &str = include_str!("../policies/default.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "if", rename_all = "snake_case")]
+pub enum Condition {
+    SubjectHasPermission {
+        permission: Permission,
+    },
+    /// The subject owns one of the resource's groups: a group the user belongs to, or the
+    /// app's group
+    SubjectOwnsResourceGroup,
+    SubjectIsResource,
+    ResourceIsRoot,
+    Not {
+        condition: Box<Condition>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Policy {
+    pub id: String,
+    pub description: String,
+    pub effect: Effect,
+    /// Exact actions, `user.*` for every action on a kind, or `*`
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub when: Vec<Condition>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    policies: Vec<Policy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    User,
+    App,
+}
+
+/// Who is asking.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Subject {
+    pub email_id: String,
+    pub role: Option<AdminRole>,
+    pub owned_groups: Vec<String>,
+}
+
+/// What they are asking about.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    /// Email of a user or client id of an app
+    pub id: String,
+    pub is_root: bool,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ConditionTrace {
+    pub condition: Condition,
+    pub holds: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PolicyTrace {
+    pub policy_id: String,
+    pub effect: Effect,
+    /// Whether the policy covers the action at all
+    pub applies: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<ConditionTrace>,
+    pub matched: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Decision {
+    pub action: String,
+    pub allowed: bool,
+    /// The deny or allow policy that settled it, none when nothing allowed the action
+    pub decided_by: Option<String>,
+    pub reason: String,
+    pub trace: Vec<PolicyTrace>,
+}
+
+impl Condition {
+    fn holds(&self, subject: &Subject, resource: &Resource) -> bool {
+        match self {
+            Condition::SubjectHasPermission { permission } => {
+                subject.role.is_some_and(|role| role.can(*permission))
+            }
+            Condition::SubjectOwnsResourceGroup => resource
+                .groups
+                .iter()
+                .any(|group| subject.owned_groups.contains(group)),
+            Condition::SubjectIsResource => {
+                resource.kind == ResourceKind::User && resource.id == subject.email_id
+            }
+            Condition::ResourceIsRoot => resource.is_root,
+            Condition::Not { condition } => !condition.holds(subject, resource),
+        }
+    }
+}
+
+impl Policy {
+    fn covers(&self, action: &str) -> bool {
+        self.actions
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => action.starts_with(prefix),
+                None => pattern == action,
+            })
+    }
+}
+
+pub struct PolicySet(Vec<Policy>);
+
+impl PolicySet {
+    /// Built-in policies, replaced by the file at `AUTHZ_POLICY_FILE` when it is set.
+    pub fn load() -> Self {
+        let builtin = Self::parse(BUILTIN_POLICIES).expect("Built-in policies must parse");
+
+        let Ok(path) = env::var("AUTHZ_POLICY_FILE") else {
+            return builtin;
+        };
+
+        match fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|raw| Self::parse(&raw))
+        {
+            Ok(policies) => {
+                println!(
+                    "Loaded {} authorization policies from {}",
+                    policies.0.len(),
+                    path
+                );
+                policies
+            }
+            // Falling back would silently drop deny rules someone relied on
+            Err(err) => panic!(
+                "Failed to load authorization policies from {}: {}",
+                path, err
+            ),
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let file: PolicyFile = serde_json::from_str(raw).map_err(|err| err.to_string())?;
+        Ok(PolicySet(file.policies))
+    }
+
+    pub fn evaluate(&self, subject: &Subject, action: &str, resource: &Resource) -> Decision {
+        let trace: Vec<PolicyTrace> = self
+            .0
+            .iter()
+            .map(|policy| {
+                let applies = policy.covers(action);
+                let conditions: Vec<ConditionTrace> = if applies {
+                    policy
+                        .when
+                        .iter()
+                        .map(|condition| ConditionTrace {
+                            condition: condition.clone(),
+                            holds: condition.holds(subject, resource),
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                PolicyTrace {
+                    policy_id: policy.id.clone(),
+                    effect: policy.effect,
+                    applies,
+                    matched: applies && conditions.iter().all(|condition| condition.holds),
+                    conditions,
+                }
+            })
+            .collect();
+
+        let matched = |effect: Effect| {
+            self.0
+                .iter()
+                .zip(&trace)
+                .find(|(policy, policy_trace)| policy_trace.matched && policy.effect == effect)
+                .map(|(policy, _)| policy)
+        };
+
+        let (allowed, decided_by, reason) = if let Some(policy) = matched(Effect::Deny) {
+            (false, Some(policy.id.clone()), policy.description.clone())
+        } else if let Some(policy) = matched(Effect::Allow) {
+            (true, Some(policy.id.clone()), policy.description.clone())
+        } else {
+            (
+                false,
+                None,
+                format!("No policy allows {} for {}", action, subject.email_id),
+            )
+        };
+
+        Decision {
+            action: action.to_string(),
+            allowed,
+            decided_by,
+            reason,
+            trace,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use rocket::tokio::time::{sleep, timeout};
+use rocket_prometheus::prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Consecutive failures that open a breaker, and how long it stays open before a probe
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_SECONDS: u64 = 30;
+const RETRY_BACKOFF_MILLIS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    /// A single probe call is let through to decide whether to close again
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn gauge_value(self) -> i64 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CallError<E> {
+    /// The breaker is open and the call was not attempted
+    Open,
+    Timeout,
+    Failed(E),
+}
+
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+}
+
+/// Timeout, retry and circuit breaker settings for one downstream service.
+pub struct Dependency {
+    name: &'static str,
+    timeout: Duration,
+    retries: u32,
+    failure_threshold: u32,
+    open_for: Duration,
+    breaker: Mutex<Breaker>,
+    metrics: Metrics,
+}
+
+impl Dependency {
+    fn new(
+        name: &'static str,
+        env_prefix: &str,
+        default_timeout_ms: u64,
+        metrics: Metrics,
+    ) -> Self {
+        let setting = |key: &str, default: u64| -> u64 {
+            env::var(format!("{}_{}", env_prefix, key))
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+
+        let dependency = Dependency {
+            name,
+            timeout: Duration::from_millis(setting("TIMEOUT_MS", default_timeout_ms)),
+            retries: setting("RETRIES", 2) as u32,
+            failure_threshold: setting("BREAKER_FAILURES", DEFAULT_FAILURE_THRESHOLD as u64).max(1)
+                as u32,
+            open_for: Duration::from_secs(setting("BREAKER_OPEN_SECONDS", DEFAULT_OPEN_SECONDS)),
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_in_flight: false,
+            }),
+            metrics,
+        };
+        dependency.publish(BreakerState::Closed);
+        dependency
+    }
+
+    /// Whether calls are currently being rejected without being attempted.
+    pub fn is_open(&self) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        breaker.state == BreakerState::Open
+            && breaker
+                .opened_at
+                .is_some_and(|opened_at| opened_at.elapsed() < self.open_for)
+    }
+
+    /// Calls a downstream endpoint that is safe to repeat, retrying timeouts and failures.
+    pub async fn call_idempotent<T, E, F, Fut>(&self, call: F) -> Result<T, CallError<E>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.call(&call).await {
+                Err(CallError::Open) => return Err(CallError::Open),
+                Err(_) if attempt < self.retries => {
+                    attempt += 1;
+                    sleep(Duration::from_millis(RETRY_BACKOFF_MILLIS * attempt as u64)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Calls a downstream endpoint once, within the timeout and through the breaker.
+    pub async fn call<T, E, F, Fut>(&self, call: F) -> Result<T, CallError<E>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !self.try_acquire() {
+            self.metrics
+                .calls
+                .with_label_values(&[self.name, "rejected"])
+                .inc();
+            return Err(CallError::Open);
+        }
+
+        let result = match timeout(self.timeout, call()).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(err)) => Err(CallError::Failed(err)),
+            Err(_) => Err(CallError::Timeout),
+        };
+
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(CallError::Timeout) => "timeout",
+            Err(_) => "failure",
+        };
+        self.metrics
+            .calls
+            .with_label_values(&[self.name, outcome])
+            .inc();
+        self.record(result.is_ok());
+
+        result
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut breaker = self.breaker.lock().unwrap();
+        match breaker.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                let elapsed = breaker
+                    .opened_at
+                    .is_none_or(|opened_at| opened_at.elapsed() >= self.open_for);
+                if !elapsed {
+                    return false;
+                }
+                breaker.state = BreakerState::HalfOpen;
+                breaker.opened_at = Some(Instant::now());
+                breaker.probe_in_flight = true;
+                self.publish(BreakerState::HalfOpen);
+                true
+            }
+            BreakerState::HalfOpen => {
+                // A probe whose caller went away never reports back, so it is replaced
+                // once it has had longer than the timeout to finish
+                let stale = breaker
+                    .opened_at
+                    .is_none_or(|probed_at| probed_at.elapsed() > self.timeout);
+                if breaker.probe_in_flight && !stale {
+                    return false;
+                }
+                breaker.opened_at = Some(Instant::now());
+                breaker.probe_in_flight = true;
+                true
+            }
+        }
+    }
+
+    fn record(&self, succeeded: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.probe_in_flight = false;
+
+        let next = if succeeded {
+            breaker.consecutive_failures = 0;
+            BreakerState::Closed
+        } else {
+            breaker.consecutive_failures += 1;
+            if breaker.state == BreakerState::HalfOpen
+                || breaker.consecutive_failures >= self.failure_threshold
+            {
+                breaker.opened_at = Some(Instant::now());
+                BreakerState::Open
+            } else {
+                breaker.state
+            }
+        };
+
+        if next != breaker.state {
+            println!(
+                "Circuit breaker for {} moved from {:?} to {:?}",
+                self.name, breaker.state, next
+            );
+            breaker.state = next;
+            self.publish(next);
+        }
+    }
+
+    fn publish(&self, state: BreakerState) {
+        self.metrics
+            .state
+            .with_label_values(&[self.name])
+            .set(state.gauge_value());
+    }
+}
+
+#[derive(Clone)]
+struct Metrics {
+    state: IntGaugeVec,
+    calls: IntCounterVec,
+}
+
+/// Downstream services this service calls, shared through managed state.
+#[derive(Clone)]
+pub struct Dependencies {
+    pub iam_service: Arc<Dependency>,
+    pub notification_service: Arc<Dependency>,
+}
+
+impl Dependencies {
+    /// Builds the dependencies and registers their metrics on `registry`.
+    pub fn new(registry: &Registry) -> Self {
+        let state = IntGaugeVec::new(
+            Opts::new(
+                "dependency_circuit_breaker_state",
+                "Circuit breaker state per dependency (0 closed, 1 half-open, 2 open)",
+            ),
+            &["dependency"],
+        )
+        .expect("valid breaker state metric");
+        let calls = IntCounterVec::new(
+            Opts::new(
+                "dependency_calls_total",
+                "Calls to downstream dependencies by outcome",
+            ),
+            &["dependency", "outcome"],
+        )
+        .expect("valid dependency calls metric");
+
+        if let Err(err) = registry.register(Box::new(state.clone())) {
+            println!("Failed to register breaker state metric: {}", err);
+        }
+        if let Err(err) = registry.register(Box::new(calls.clone())) {
+            println!("Failed to register dependency calls metric: {}", err);
+        }
+
+        let metrics = Metrics { state, calls };
+        Dependencies {
+            iam_service: Arc::new(Dependency::new(
+                "iam_service",
+                "IAM_SERVICE",
+                2000,
+                metrics.clone(),
+            )),
+            notification_service: Arc::new(Dependency::new(
+                "notification_service",
+                "NOTIFICATION_SERVICE",
+                5000,
+                metrics,
+            )),
+        }
+    }
+}
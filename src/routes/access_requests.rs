@@ -0,0 +1,201 @@
+use crate::db::access_requests::{self, AccessRequest, AccessRequestStatus};
+use crate::db::audit::{self, AuditEvent};
+use crate::db::redis::random_key;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::CreateAccessRequest;
+use crate::routes::groups::{find_group, find_user, grant_membership};
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_utils::Claims;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+// Upper bound for just-in-time elevation
+const MAX_ACCESS_DURATION_HOURS: i64 = 7 * 24;
+
+/// Requests temporary membership of a group for the calling user.
+#[openapi]
+#[post("/access-requests", format = "json", data = "<access_request>")]
+pub fn create_access_request(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    access_request: Json<CreateAccessRequest>,
+) -> Result<Json<AccessRequest>, Status> {
+    if access_request.duration_hours <= 0
+        || access_request.duration_hours > MAX_ACCESS_DURATION_HOURS
+        || access_request.reason.trim().is_empty()
+    {
+        return Err(Status::BadRequest);
+    }
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let group = find_group(&mut conn, &access_request.group_identifier)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let request = AccessRequest {
+        id: random_key(16),
+        requester: claims.sub.clone(),
+        group_identifier: group.identifier,
+        reason: access_request.reason.trim().to_string(),
+        duration_hours: access_request.duration_hours,
+        status: AccessRequestStatus::Pending,
+        created_at: Utc::now(),
+        decided_by: None,
+        decided_at: None,
+    };
+
+    access_requests::save(&mut cache_connection, &request)
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "access_request.created",
+            &claims.sub,
+            &claims.sub,
+            json!({ "request_id": request.id, "group": request.group_identifier }),
+        ),
+    );
+
+    Ok(Json(request))
+}
+
+fn can_decide(
+    conn: &mut PgConnection,
+    claims: &Claims,
+    ownerships: &GroupOwnerships,
+    request: &AccessRequest,
+) -> Result<bool, Status> {
+    if ownerships.0.contains(&request.group_identifier) {
+        return Ok(true);
+    }
+    Ok(find_user(conn, &claims.sub)?.is_root)
+}
+
+/// Pending requests the caller can decide: all of them for root users, otherwise those
+/// for groups the caller owns.
+#[openapi]
+#[get("/access-requests")]
+pub fn list_access_requests(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+) -> Result<Json<Vec<AccessRequest>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let is_root = find_user(&mut conn, &claims.sub)?.is_root;
+
+    let pending =
+        access_requests::pending(&mut cache_connection).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        pending
+            .into_iter()
+            .filter(|request| is_root || ownerships.0.contains(&request.group_identifier))
+            .collect(),
+    ))
+}
+
+fn decide(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    request_id: &str,
+    approve: bool,
+) -> Result<Json<AccessRequest>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let mut request = access_requests::get(&mut cache_connection, request_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if request.status != AccessRequestStatus::Pending {
+        return Err(Status::Conflict);
+    }
+    if !can_decide(&mut conn, &claims, &ownerships, &request)? {
+        return Err(Status::Forbidden);
+    }
+    // Nobody approves their own elevation
+    if request.requester == claims.sub {
+        return Err(Status::Forbidden);
+    }
+
+    let now = Utc::now();
+    if approve {
+        let group = find_group(&mut conn, &request.group_identifier)?;
+        let member = find_user(&mut conn, &request.requester)?;
+
+        grant_membership(
+            &mut conn,
+            &mut cache_connection,
+            &group,
+            &member,
+            now,
+            Some(now + Duration::hours(request.duration_hours)),
+            &claims.sub,
+        )?;
+    }
+
+    request.status = if approve {
+        AccessRequestStatus::Approved
+    } else {
+        AccessRequestStatus::Rejected
+    };
+    request.decided_by = Some(claims.sub.clone());
+    request.decided_at = Some(now);
+
+    access_requests::save(&mut cache_connection, &request)
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            if approve {
+                "access_request.approved"
+            } else {
+                "access_request.rejected"
+            },
+            &claims.sub,
+            &request.requester,
+            json!({ "request_id": request.id, "group": request.group_identifier }),
+        ),
+    );
+
+    Ok(Json(request))
+}
+
+#[openapi]
+#[post("/access-requests/<request_id>/approve")]
+pub fn approve_access_request(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    request_id: String,
+) -> Result<Json<AccessRequest>, Status> {
+    decide(claims, ownerships, rdb, cache_pool, &request_id, true)
+}
+
+#[openapi]
+#[post("/access-requests/<request_id>/reject")]
+pub fn reject_access_request(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    request_id: String,
+) -> Result<Json<AccessRequest>, Status> {
+    decide(claims, ownerships, rdb, cache_pool, &request_id, false)
+}
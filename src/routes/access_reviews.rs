@@ -0,0 +1,399 @@
+use crate::db::access_reviews::{self, CampaignStatus, ReviewCampaign, ReviewDecision, ReviewItem};
+use crate::db::audit::{self, AuditEvent};
+use crate::db::groups::{self, GroupRef};
+use crate::db::redis::random_key;
+use crate::middlewares::admin::role_for;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{AccessReviewDecisionRequest, CreateAccessReviewRequest};
+use crate::models::response::{AccessReviewReport, AccessReviewSummary, SignedAccessReviewReport};
+use crate::models::schema::{App, User};
+use crate::permissions::{AdminRole, Permission};
+use crate::routes::groups::{authorize_group_change, find_group, find_user, revoke_membership};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use hmac::{Hmac, Mac};
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+
+fn resolve_scope(
+    conn: &mut PgConnection,
+    review_request: &CreateAccessReviewRequest,
+) -> Result<GroupRef, Status> {
+    use crate::models::schema::schema::app::dsl::*;
+    use crate::models::schema::schema::group::dsl as group_dsl;
+
+    match (review_request.app_id, &review_request.group_identifier) {
+        (Some(app_pk), _) => {
+            let target_app = match app.filter(id.eq(app_pk)).first::<App>(conn) {
+                Ok(target_app) => target_app,
+                Err(diesel::result::Error::NotFound) => return Err(Status::NotFound),
+                Err(_) => return Err(Status::InternalServerError),
+            };
+            // Apps without a group are open to everyone, there is no membership to review
+            let app_group_id = target_app.group_id.ok_or(Status::UnprocessableEntity)?;
+            let group_identifier: String = group_dsl::group
+                .filter(group_dsl::id.eq(app_group_id))
+                .select(group_dsl::identifier)
+                .first(conn)
+                .map_err(|_| Status::InternalServerError)?;
+            Ok(GroupRef {
+                id: app_group_id,
+                identifier: group_identifier,
+            })
+        }
+        (None, Some(group_identifier)) => find_group(conn, group_identifier),
+        (None, None) => Err(Status::BadRequest),
+    }
+}
+
+fn load_campaign(conn: &mut PgConnection, campaign_id: &str) -> Result<ReviewCampaign, Status> {
+    access_reviews::get_campaign(conn, campaign_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)
+}
+
+/// Whether the caller may see a campaign: its reviewers, owners of the reviewed group and
+/// admins with `view_access_reports`.
+pub(crate) fn can_view_campaign(
+    role: Option<AdminRole>,
+    owned_groups: &[String],
+    caller: &str,
+    campaign: &ReviewCampaign,
+    items: &[ReviewItem],
+) -> bool {
+    role.is_some_and(|role| role.can(Permission::ViewAccessReports))
+        || owned_groups.contains(&campaign.group_identifier)
+        || items
+            .iter()
+            .any(|item| item.reviewers.iter().any(|reviewer| reviewer == caller))
+}
+
+/// Loads a campaign with its items, refusing callers who may not see it.
+fn load_visible_campaign(
+    conn: &mut PgConnection,
+    claims: &UserClaims,
+    ownerships: &GroupOwnerships,
+    campaign_id: &str,
+) -> Result<(ReviewCampaign, Vec<ReviewItem>), Status> {
+    let campaign = load_campaign(conn, campaign_id)?;
+    let items =
+        access_reviews::items(conn, campaign_id).map_err(|_| Status::InternalServerError)?;
+    let role = role_for(conn, &claims.sub)?;
+    if !can_view_campaign(role, &ownerships.0, &claims.sub, &campaign, &items) {
+        return Err(Status::Forbidden);
+    }
+    Ok((campaign, items))
+}
+
+fn summarize(campaign: ReviewCampaign, items: &[ReviewItem]) -> AccessReviewSummary {
+    let count = |decision: Option<ReviewDecision>| {
+        items
+            .iter()
+            .filter(|item| item.decision == decision)
+            .count()
+    };
+
+    AccessReviewSummary {
+        campaign,
+        total_items: items.len(),
+        approved: count(Some(ReviewDecision::Approve)),
+        revoked: count(Some(ReviewDecision::Revoke)),
+        pending: count(None),
+    }
+}
+
+/// Starts a review of every member of a group, or of the group gating an app, including
+/// members inherited from nested groups. Each membership is assigned to the group's owners;
+/// if the group has no owners the creator of the campaign reviews it. Needs ownership of
+/// the group or an admin role with `edit_users`, since undecided items are revoked at the
+/// deadline.
+#[openapi]
+#[post("/access-reviews", format = "json", data = "<review_request>")]
+pub fn create_access_review(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    review_request: Json<CreateAccessReviewRequest>,
+) -> Result<Json<AccessReviewSummary>, Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    if review_request.deadline <= Utc::now() || review_request.name.trim().is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let group = resolve_scope(&mut conn, &review_request)?;
+    authorize_group_change(
+        role_for(&mut conn, &claims.sub)?,
+        ownerships.0.contains(&group.identifier),
+        Permission::EditUsers,
+    )?;
+
+    let owner_ids =
+        groups::owner_ids(&mut conn, group.id).map_err(|_| Status::InternalServerError)?;
+    let mut reviewers: Vec<String> = user
+        .filter(id.eq_any(owner_ids))
+        .select(email_id)
+        .load(&mut conn)
+        .map_err(|_| Status::InternalServerError)?;
+    if reviewers.is_empty() {
+        reviewers.push(claims.sub.clone());
+    }
+
+    let members =
+        groups::effective_members(&mut conn, group.id).map_err(|_| Status::InternalServerError)?;
+    let mut records: HashMap<i64, User> = user
+        .filter(
+            id.eq_any(
+                members
+                    .iter()
+                    .map(|member| member.user_id)
+                    .collect::<Vec<i64>>(),
+            ),
+        )
+        .load::<User>(&mut conn)
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect();
+
+    let items: Vec<ReviewItem> = members
+        .into_iter()
+        .filter_map(|member| {
+            let record = records.remove(&member.user_id)?;
+            Some(ReviewItem {
+                id: random_key(12),
+                user_id: record.id,
+                email_id: record.email_id,
+                reviewers: reviewers.clone(),
+                decision: None,
+                comment: None,
+                decided_by: None,
+                decided_at: None,
+                auto_revoked: false,
+                via_group: (member.depth > 0).then_some(GroupRef {
+                    id: member.via_group_id,
+                    identifier: member.via_group,
+                }),
+            })
+        })
+        .collect();
+
+    let campaign = ReviewCampaign {
+        id: random_key(16),
+        name: review_request.name.trim().to_string(),
+        app_id: review_request.app_id,
+        group_id: group.id,
+        group_identifier: group.identifier,
+        created_by: claims.sub.clone(),
+        created_at: Utc::now(),
+        deadline: review_request.deadline,
+        status: CampaignStatus::Open,
+        completed_at: None,
+        last_reminded_at: None,
+    };
+
+    access_reviews::create(&mut conn, &campaign, &items)
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "access_review.created",
+            &claims.sub,
+            &campaign.group_identifier,
+            json!({ "campaign_id": campaign.id, "items": items.len() }),
+        ),
+    );
+
+    Ok(Json(summarize(campaign, &items)))
+}
+
+/// Progress of a campaign, for its reviewers, owners of the reviewed group and admins with
+/// `view_access_reports`.
+#[openapi]
+#[get("/access-reviews/<campaign_id>")]
+pub fn get_access_review(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    campaign_id: String,
+) -> Result<Json<AccessReviewSummary>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let (campaign, items) = load_visible_campaign(&mut conn, &claims, &ownerships, &campaign_id)?;
+
+    Ok(Json(summarize(campaign, &items)))
+}
+
+/// Lists the items of a campaign, with the same rights as viewing it. With `mine=true` only
+/// items assigned to the caller.
+#[openapi]
+#[get("/access-reviews/<campaign_id>/items?<mine>")]
+pub fn list_access_review_items(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    campaign_id: String,
+    mine: Option<bool>,
+) -> Result<Json<Vec<ReviewItem>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let (_, items) = load_visible_campaign(&mut conn, &claims, &ownerships, &campaign_id)?;
+
+    Ok(Json(if mine.unwrap_or(false) {
+        items
+            .into_iter()
+            .filter(|item| item.reviewers.contains(&claims.sub))
+            .collect()
+    } else {
+        items
+    }))
+}
+
+/// Records a reviewer's decision. Revoking removes the membership immediately; for an
+/// inherited member that is their membership of the nested group they inherit through.
+#[openapi]
+#[post(
+    "/access-reviews/<campaign_id>/items/<item_id>",
+    format = "json",
+    data = "<decision_request>"
+)]
+pub fn decide_access_review_item(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    campaign_id: String,
+    item_id: String,
+    decision_request: Json<AccessReviewDecisionRequest>,
+) -> Result<Json<ReviewItem>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let mut campaign = load_campaign(&mut conn, &campaign_id)?;
+    if campaign.status != CampaignStatus::Open {
+        return Err(Status::Conflict);
+    }
+
+    let mut item = access_reviews::get_item(&mut conn, &campaign_id, &item_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    if item.decision.is_some() {
+        return Err(Status::Conflict);
+    }
+    if !item.reviewers.contains(&claims.sub) && !find_user(&mut conn, &claims.sub)?.is_root {
+        return Err(Status::Forbidden);
+    }
+
+    if decision_request.decision == ReviewDecision::Revoke {
+        revoke_membership(
+            &mut conn,
+            &mut cache_connection,
+            &item.membership_group(&campaign),
+            item.user_id,
+            &item.email_id,
+            &claims.sub,
+            json!({ "access_review": campaign.id }),
+        )?;
+    }
+
+    item.decision = Some(decision_request.decision);
+    item.comment = decision_request.comment.clone();
+    item.decided_by = Some(claims.sub.clone());
+    item.decided_at = Some(Utc::now());
+
+    access_reviews::save_item(&mut conn, &campaign_id, &item)
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "access_review.decided",
+            &claims.sub,
+            &item.email_id,
+            json!({ "campaign_id": campaign.id, "decision": item.decision }),
+        ),
+    );
+
+    let items =
+        access_reviews::items(&mut conn, &campaign_id).map_err(|_| Status::InternalServerError)?;
+    if items.iter().all(|item| item.decision.is_some()) {
+        campaign.status = CampaignStatus::Completed;
+        campaign.completed_at = Some(Utc::now());
+        access_reviews::save_campaign(&mut conn, &campaign)
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    Ok(Json(item))
+}
+
+/// Signs access review reports with `ACCESS_REVIEW_SIGNING_SECRET`, read at startup.
+pub struct ReportSigner {
+    secret: Vec<u8>,
+}
+
+impl ReportSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        ReportSigner {
+            secret: secret.to_vec(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let secret = env::var("ACCESS_REVIEW_SIGNING_SECRET")
+            .map_err(|_| "ACCESS_REVIEW_SIGNING_SECRET must be set".to_string())?;
+        Ok(ReportSigner::new(secret.as_bytes()))
+    }
+
+    /// Hex encoded HMAC-SHA256 of `payload`.
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(payload);
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Completion report for auditors, signed so it can be verified after export. Returns 409
+/// while the campaign is still open. Available to whoever may view the campaign.
+#[openapi]
+#[get("/access-reviews/<campaign_id>/report")]
+pub fn get_access_review_report(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    signer: &State<ReportSigner>,
+    campaign_id: String,
+) -> Result<Json<SignedAccessReviewReport>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let (campaign, items) = load_visible_campaign(&mut conn, &claims, &ownerships, &campaign_id)?;
+    if campaign.status != CampaignStatus::Completed {
+        return Err(Status::Conflict);
+    }
+
+    let report = AccessReviewReport {
+        campaign,
+        items,
+        generated_at: Utc::now(),
+    };
+
+    let payload = serde_json::to_vec(&report).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(SignedAccessReviewReport {
+        report,
+        algorithm: "HMAC-SHA256".to_string(),
+        signature: signer.sign(&payload),
+    }))
+}
@@ -1,32 +1,65 @@
-use crate::models::request::{InviteRequest, UpdateUserRequest};
-use crate::models::response::{AppResponse, UserResponse};
+use crate::config;
+use crate::db::audit::{self, AuditEvent};
+use crate::db::domain_rules::{self, DomainRuleEvaluation};
+use crate::db::group_cache;
+use crate::db::groups::{self, GroupRef};
+use crate::db::invites::{
+    self, InviteEvent, InviteEventKind, InviteFollowUp, InviteHistoryEntry, PendingInvite,
+    PendingInviteRef,
+};
+use crate::db::lockouts;
+use crate::db::outbox::{self, EmailJob};
+use crate::db::rate_limit;
+use crate::db::redis::random_key;
+use crate::db::revocations;
+use crate::db::users;
+use crate::emails::{EmailTemplates, InviteEmail, RenderedEmail};
+use crate::invite_tokens::{self, TokenError};
+use crate::middlewares::admin::{role_for, Admin};
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::internal_caller::InternalCaller;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{
+    GroupExistsBatchRequest, InviteRequest, RedeemInviteRequest, UpdateUserRequest,
+    UserExistsBatchRequest, UserExpansion,
+};
+use crate::models::response::{
+    AppAccessEntry, AppResponse, InviteDeliveryResponse, InviteErrorResponse, InviteResponse,
+    PendingInviteEntry, RedeemedInviteResponse, UserDetailResponse, UserResponse,
+};
 use crate::models::schema::{App, User};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::permissions::require::{
+    GroupExists, ReadUsers, SendInvites, UserExists, ViewAccessReports, ViewApps, ViewInvites,
+};
+use crate::permissions::{AdminRole, Permission};
+use crate::policy::PolicySet;
+use crate::routes::authz::{authorize, subject_for, user_resource};
+use crate::routes::lockouts::lock_status_for;
+use crate::routes::mfa;
+use crate::validation;
 use chrono::{Duration, Utc};
 use diesel::dsl::exists;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::{insert_into, PgConnection, RunQueryDsl};
 use diesel::{prelude::*, update};
 use ginger_shared_rs::rocket_models::MessageResponse;
-use ginger_shared_rs::rocket_utils::{APIClaims, Claims};
+use ginger_shared_rs::rocket_utils::APIClaims;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use r2d2_redis::redis::Commands;
+use r2d2_redis::redis;
 use r2d2_redis::RedisConnectionManager;
-use rand::distributions::Alphanumeric;
-use rand::Rng;
-use rocket::http::Status;
+use rocket::futures::stream::{BoxStream, StreamExt};
+use rocket::http::{ContentType, RawStr, Status};
 use rocket::response::status;
+use rocket::response::stream::{stream, TextStream};
 use rocket::serde::json::Json;
+use rocket::tokio::task::spawn_blocking;
 use rocket::{post, State};
 use rocket_okapi::openapi;
 use schemars::JsonSchema;
 use serde::Serialize;
 use serde_json::{json, Value};
-use std::env;
-use NotificationService::apis::configuration::ApiKey as NotificationApiKey;
-use NotificationService::apis::default_api::{send_email, SendEmailParams};
-use NotificationService::get_configuration as get_notification_service_configuration;
-use NotificationService::models::EmailRequest;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 #[derive(Serialize, JsonSchema)]
 pub struct PaginatedResponse<T> {
@@ -34,13 +67,15 @@ pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
 }
 #[openapi]
-#[get("/users?<page>&<page_size>&<search>")]
+#[get("/users?<page>&<page_size>&<search>&<locked>")]
 pub fn get_paginated_users(
-    _claims: Claims,
+    _caller: InternalCaller<ReadUsers>,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
     page: Option<usize>,
     page_size: Option<usize>,
     search: Option<String>,
+    locked: Option<bool>,
 ) -> Result<Json<PaginatedResponse<UserResponse>>, rocket::http::Status> {
     use crate::models::schema::schema::user::dsl::*;
 
@@ -58,45 +93,57 @@ pub fn get_paginated_users(
     let offset = (page - 1) * page_size;
     let like_pattern = search.as_deref().map(|s| format!("%{}%", s));
 
-    // Total count query
-    let total_count: i64 = match like_pattern {
-        Some(ref pattern) => user
-            .filter(
+    // Only the lock filter needs Redis; without it MFA flags are just left out
+    let mut cache_connection = cache_pool.get().ok();
+
+    // Locks live in Redis, so the filter narrows the query to the locked emails
+    let locked_emails = match locked {
+        Some(_) => {
+            let cache_connection = cache_connection
+                .as_mut()
+                .ok_or(rocket::http::Status::ServiceUnavailable)?;
+            lockouts::locked_emails(cache_connection)
+                .map_err(|_| rocket::http::Status::InternalServerError)?
+        }
+        None => Vec::new(),
+    };
+
+    let filtered = || {
+        let mut query = user.into_boxed();
+        if let Some(ref pattern) = like_pattern {
+            query = query.filter(
                 first_name
                     .ilike(pattern)
                     .or(last_name.ilike(pattern))
                     .or(middle_name.ilike(pattern))
                     .or(email_id.ilike(pattern)),
-            )
-            .count()
-            .get_result(&mut conn),
-        None => user.count().get_result(&mut conn),
-    }
-    .map_err(|_| rocket::http::Status::InternalServerError)?;
+            );
+        }
+        match locked {
+            Some(true) => query.filter(email_id.eq_any(&locked_emails)),
+            Some(false) => query.filter(email_id.ne_all(&locked_emails)),
+            None => query,
+        }
+    };
+
+    // Total count query
+    let total_count: i64 = filtered()
+        .count()
+        .get_result(&mut conn)
+        .map_err(|_| rocket::http::Status::InternalServerError)?;
 
     // Paginated results query
-    let results = match like_pattern {
-        Some(ref pattern) => user
-            .filter(
-                first_name
-                    .ilike(pattern)
-                    .or(last_name.ilike(pattern))
-                    .or(middle_name.ilike(pattern))
-                    .or(email_id.ilike(pattern)),
-            )
-            .order_by(created_at.desc())
-            .limit(page_size as i64)
-            .offset(offset as i64)
-            .load::<User>(&mut conn),
-        None => user
-            .order_by(created_at.desc())
-            .limit(page_size as i64)
-            .offset(offset as i64)
-            .load::<User>(&mut conn),
-    }
-    .map_err(|_| rocket::http::Status::InternalServerError)?;
+    let results = filtered()
+        .order_by(created_at.desc())
+        .limit(page_size as i64)
+        .offset(offset as i64)
+        .load::<User>(&mut conn)
+        .map_err(|_| rocket::http::Status::InternalServerError)?;
 
-    let response: Vec<UserResponse> = results.into_iter().map(UserResponse::from).collect();
+    let mut response: Vec<UserResponse> = results.into_iter().map(UserResponse::from).collect();
+    if let Some(cache_connection) = cache_connection.as_mut() {
+        response = mfa::with_mfa_flags(cache_connection, response)?;
+    }
 
     Ok(Json(PaginatedResponse {
         total_count: total_count as usize,
@@ -104,34 +151,156 @@ pub fn get_paginated_users(
     }))
 }
 
+/// Returns a single user. Pass `expand` with any of `groups`, `apps`, `invites`, `audit`
+/// and `lock` (comma separated) to include the related records.
 #[openapi]
-#[get("/user?<email>")]
+#[get("/user?<email>&<expand>")]
 pub fn get_user_by_email(
-    _claims: Claims,
+    _caller: InternalCaller<ReadUsers>,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
     email: String,
-) -> Result<Json<UserResponse>, rocket::http::Status> {
+    expand: Option<String>,
+) -> Result<Json<UserDetailResponse>, rocket::http::Status> {
     use crate::models::schema::schema::user::dsl::*;
 
+    let expansion =
+        UserExpansion::parse(expand.as_deref()).map_err(|_| rocket::http::Status::BadRequest)?;
+
     let mut conn = rdb
         .get()
         .map_err(|_| rocket::http::Status::InternalServerError)?;
 
-    match user
+    let user_record = match user
         .filter(email_id.eq(email.clone()))
         .first::<User>(&mut conn)
     {
-        Ok(user_record) => Ok(Json(UserResponse::from(user_record))),
-        Err(diesel::result::Error::NotFound) => Err(rocket::http::Status::NotFound),
-        Err(_) => Err(rocket::http::Status::InternalServerError),
+        Ok(user_record) => user_record,
+        Err(diesel::result::Error::NotFound) => return Err(rocket::http::Status::NotFound),
+        Err(_) => return Err(rocket::http::Status::InternalServerError),
+    };
+
+    let user_pk = user_record.id;
+    let user_email = user_record.email_id.clone();
+    let user_is_root = user_record.is_root;
+
+    // Only the expansions kept in Redis need it. Without it the user is still returned,
+    // with the MFA flags left unset.
+    let mut cache_connection = cache_pool.get().ok();
+    let mut user_response = UserResponse::from(user_record);
+    if let Some(cache_connection) = cache_connection.as_mut() {
+        user_response = mfa::with_mfa_flags(cache_connection, vec![user_response])?.remove(0);
     }
+
+    let mut response = UserDetailResponse {
+        user: user_response,
+        groups: None,
+        owned_groups: None,
+        apps: None,
+        invites: None,
+        audit: None,
+        lock_status: None,
+    };
+
+    if expansion.groups || expansion.apps {
+        let member_of = groups::groups_for_user(&mut conn, user_pk)
+            .map_err(|_| rocket::http::Status::InternalServerError)?;
+        let owner_of = groups::groups_owned_by_user(&mut conn, user_pk)
+            .map_err(|_| rocket::http::Status::InternalServerError)?;
+
+        if expansion.apps {
+            use crate::models::schema::schema::app::dsl as app_dsl;
+
+            let inherited = groups::effective_groups_for_user(&mut conn, user_pk)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+            let group_ids: Vec<i64> = inherited
+                .iter()
+                .chain(owner_of.iter())
+                .map(|g| g.id)
+                .collect();
+            let reachable = if user_is_root {
+                app_dsl::app
+                    .order_by(app_dsl::name.asc())
+                    .load::<App>(&mut conn)
+            } else {
+                app_dsl::app
+                    .filter(
+                        app_dsl::group_id
+                            .is_null()
+                            .or(app_dsl::group_id.eq_any(group_ids)),
+                    )
+                    .order_by(app_dsl::name.asc())
+                    .load::<App>(&mut conn)
+            }
+            .map_err(|_| rocket::http::Status::InternalServerError)?;
+
+            response.apps = Some(reachable.into_iter().map(AppResponse::from).collect());
+        }
+
+        if expansion.groups {
+            response.groups = Some(member_of);
+            response.owned_groups = Some(owner_of);
+        }
+    }
+
+    if expansion.audit {
+        response.audit = Some(
+            audit::recent_events_for(&mut conn, &user_email, 20)
+                .map_err(|_| rocket::http::Status::InternalServerError)?,
+        );
+    }
+
+    if expansion.invites || expansion.lock {
+        let cache_connection = cache_connection
+            .as_mut()
+            .ok_or(rocket::http::Status::ServiceUnavailable)?;
+
+        if expansion.invites {
+            response.invites = Some(
+                invites::history_for(cache_connection, &user_email)
+                    .map_err(|_| rocket::http::Status::InternalServerError)?,
+            );
+        }
+
+        if expansion.lock {
+            response.lock_status = Some(lock_status_for(cache_connection, user_email)?);
+        }
+    }
+
+    Ok(Json(response))
+}
+
+/// The policy actions an update amounts to.
+fn user_update_actions(current: &User, update_request: &UpdateUserRequest) -> Vec<&'static str> {
+    let mut actions = Vec::new();
+    if current.is_root != update_request.is_root {
+        actions.push("user.grant_root");
+    }
+    match (current.is_active, update_request.is_active) {
+        (true, false) => actions.push("user.deactivate"),
+        (false, true) => actions.push("user.activate"),
+        _ => {}
+    }
+    if current.first_name != update_request.first_name
+        || current.middle_name != update_request.middle_name
+        || current.last_name != update_request.last_name
+        || actions.is_empty()
+    {
+        actions.push("user.update");
+    }
+    actions
 }
 
+/// Updates a user. Every part of the change, e.g. `user.deactivate` or `user.grant_root`,
+/// must be allowed by the authorization policies.
 #[openapi]
 #[put("/user/<email>", format = "json", data = "<update_request>")]
 pub fn update_user_by_email(
-    _claims: Claims,
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
     email: String,
     update_request: Json<UpdateUserRequest>,
 ) -> Result<Json<UserResponse>, rocket::http::Status> {
@@ -141,6 +310,41 @@ pub fn update_user_by_email(
         .get()
         .map_err(|_| rocket::http::Status::InternalServerError)?;
 
+    let current = match user.filter(email_id.eq(&email)).first::<User>(&mut conn) {
+        Ok(current) => current,
+        Err(diesel::result::Error::NotFound) => return Err(rocket::http::Status::NotFound),
+        Err(_) => return Err(rocket::http::Status::InternalServerError),
+    };
+
+    let mut cache_connection = cache_pool
+        .get()
+        .map_err(|_| rocket::http::Status::ServiceUnavailable)?;
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(&mut conn, &current)?;
+    let actions = user_update_actions(&current, &update_request);
+    for action in &actions {
+        authorize(policies, &mut conn, &subject, action, &resource)?;
+    }
+
+    // Deactivated users and demoted roots lose the sessions they hold. Revoking before the
+    // update means a Redis failure leaves the user unchanged rather than half cut off.
+    let revocation_reason = if actions.contains(&"user.deactivate") {
+        Some("deactivated")
+    } else if current.is_root && !update_request.is_root {
+        Some("root_removed")
+    } else {
+        None
+    };
+    if revocation_reason.is_some() {
+        revocations::revoke_user(
+            &mut cache_connection,
+            &current.email_id,
+            Utc::now(),
+            config::revocation_marker_ttl_seconds(),
+        )
+        .map_err(|_| rocket::http::Status::ServiceUnavailable)?;
+    }
+
     // Build the update query
     match diesel::update(user.filter(email_id.eq(email.clone())))
         .set((
@@ -152,7 +356,33 @@ pub fn update_user_by_email(
         ))
         .get_result::<User>(&mut conn)
     {
-        Ok(updated_user) => Ok(Json(UserResponse::from(updated_user))),
+        Ok(updated_user) => {
+            audit::record(
+                &mut conn,
+                AuditEvent::new(
+                    "user.updated",
+                    &claims.sub,
+                    &updated_user.email_id,
+                    json!(&*update_request),
+                ),
+            );
+            if let Some(reason) = revocation_reason {
+                audit::record(
+                    &mut conn,
+                    AuditEvent::new(
+                        "sessions.revoked",
+                        &claims.sub,
+                        &updated_user.email_id,
+                        json!({ "scope": "user", "reason": reason }),
+                    ),
+                );
+            }
+            mfa::with_mfa_flags(
+                &mut cache_connection,
+                vec![UserResponse::from(updated_user)],
+            )
+            .map(|mut updated| Json(updated.remove(0)))
+        }
         Err(diesel::result::Error::NotFound) => Err(rocket::http::Status::NotFound),
         Err(_) => Err(rocket::http::Status::InternalServerError),
     }
@@ -160,7 +390,7 @@ pub fn update_user_by_email(
 #[openapi]
 #[get("/applications?<page>&<page_size>&<search>")]
 pub fn list_paginated_applications(
-    _claims: Claims,
+    _admin: Admin<ViewApps>,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
     page: Option<usize>,
     page_size: Option<usize>,
@@ -208,22 +438,329 @@ pub fn list_paginated_applications(
     }
     .map_err(|_| rocket::http::Status::InternalServerError)?;
 
-    let response: Vec<AppResponse> = results.into_iter().map(AppResponse::from).collect();
+    Ok(Json(PaginatedResponse {
+        total_count: total_count as usize,
+        data: results.into_iter().map(AppResponse::from).collect(),
+    }))
+}
+
+/// How each user reaches the app, keyed by user id. Deactivated users are still in here;
+/// they are left out when the rows are loaded.
+fn app_access_paths(
+    conn: &mut PgConnection,
+    app_pk: i64,
+) -> Result<BTreeMap<i64, Vec<String>>, rocket::http::Status> {
+    use crate::models::schema::schema::app::dsl as app_dsl;
+    use crate::models::schema::schema::group::dsl as group_dsl;
+    use crate::models::schema::schema::user::dsl::*;
+
+    let target_app = match app_dsl::app
+        .filter(app_dsl::id.eq(app_pk))
+        .first::<App>(conn)
+    {
+        Ok(target_app) => target_app,
+        Err(diesel::result::Error::NotFound) => return Err(rocket::http::Status::NotFound),
+        Err(_) => return Err(rocket::http::Status::InternalServerError),
+    };
+
+    let mut paths: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+
+    let root_ids: Vec<i64> = user
+        .filter(is_root.eq(true))
+        .select(id)
+        .load(conn)
+        .map_err(|_| rocket::http::Status::InternalServerError)?;
+    for root_id in root_ids {
+        paths
+            .entry(root_id)
+            .or_default()
+            .push("root user".to_string());
+    }
+
+    match target_app.group_id {
+        Some(app_group_id) => {
+            let group_identifier: String = group_dsl::group
+                .filter(group_dsl::id.eq(app_group_id))
+                .select(group_dsl::identifier)
+                .first(conn)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+
+            let members = groups::effective_members(conn, app_group_id)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+            for member in members {
+                let path = if member.depth == 0 {
+                    format!("member of group {}", group_identifier)
+                } else {
+                    format!(
+                        "member of group {} nested in {}",
+                        member.via_group, group_identifier
+                    )
+                };
+                paths.entry(member.user_id).or_default().push(path);
+            }
+
+            let owner_ids = groups::owner_ids(conn, app_group_id)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+            for owner_id in owner_ids {
+                paths
+                    .entry(owner_id)
+                    .or_default()
+                    .push(format!("owner of group {}", group_identifier));
+            }
+        }
+        None => {
+            let all_ids: Vec<i64> = user
+                .select(id)
+                .load(conn)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+            for user_id in all_ids {
+                paths
+                    .entry(user_id)
+                    .or_default()
+                    .push("app is not restricted to a group".to_string());
+            }
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Number of active users in `paths`.
+fn app_access_count(
+    conn: &mut PgConnection,
+    paths: &BTreeMap<i64, Vec<String>>,
+) -> Result<i64, rocket::http::Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    user.filter(id.eq_any(paths.keys().copied().collect::<Vec<i64>>()))
+        .filter(is_active.eq(true))
+        .count()
+        .get_result(conn)
+        .map_err(|_| rocket::http::Status::InternalServerError)
+}
+
+/// A page of the active users in `paths`, ordered by email.
+fn app_access_page(
+    conn: &mut PgConnection,
+    paths: &BTreeMap<i64, Vec<String>>,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<AppAccessEntry>, rocket::http::Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    // Deactivated users cannot sign in regardless of how access was granted
+    let users = user
+        .filter(id.eq_any(paths.keys().copied().collect::<Vec<i64>>()))
+        .filter(is_active.eq(true))
+        .order_by(email_id.asc())
+        .offset(offset)
+        .limit(limit)
+        .load::<User>(conn)
+        .map_err(|_| rocket::http::Status::InternalServerError)?;
+
+    Ok(users
+        .into_iter()
+        .map(|user_record| AppAccessEntry {
+            paths: paths.get(&user_record.id).cloned().unwrap_or_default(),
+            email_id: user_record.email_id,
+            first_name: user_record.first_name,
+            last_name: user_record.last_name,
+            is_root: user_record.is_root,
+        })
+        .collect())
+}
+
+pub(crate) fn effective_app_access(
+    conn: &mut PgConnection,
+    app_pk: i64,
+) -> Result<Vec<AppAccessEntry>, rocket::http::Status> {
+    let paths = app_access_paths(conn, app_pk)?;
+    app_access_page(conn, &paths, 0, i64::MAX)
+}
+
+/// Lists every active user who can sign in to the app, with the path(s) that grant access.
+#[openapi]
+#[get("/applications/<app_id>/access?<page>&<page_size>")]
+pub fn get_app_access_report(
+    _admin: Admin<ViewAccessReports>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    app_id: i64,
+    page: Option<usize>,
+    page_size: Option<usize>,
+) -> Result<Json<PaginatedResponse<AppAccessEntry>>, rocket::http::Status> {
+    let mut conn = rdb
+        .get()
+        .map_err(|_| rocket::http::Status::InternalServerError)?;
+
+    let page = page.unwrap_or(1);
+    let page_size = page_size.unwrap_or(10);
+
+    if page == 0 || page_size == 0 {
+        return Err(rocket::http::Status::BadRequest);
+    }
+
+    let paths = app_access_paths(&mut conn, app_id)?;
+    let total_count = app_access_count(&mut conn, &paths)?;
+    let data = app_access_page(
+        &mut conn,
+        &paths,
+        ((page - 1) * page_size) as i64,
+        page_size as i64,
+    )?;
 
     Ok(Json(PaginatedResponse {
         total_count: total_count as usize,
-        data: response,
+        data,
     }))
 }
 
+// Rows loaded per query while exporting the access report
+const ACCESS_EXPORT_BATCH: i64 = 500;
+
+/// Same report as `get_app_access_report`, unpaginated, as CSV for access reviews. Rows are
+/// loaded and sent in batches, so large apps don't have to fit in memory. A database error
+/// after the first batch ends the file early.
+#[openapi]
+#[get("/applications/<app_id>/access.csv")]
+pub async fn export_app_access_report(
+    _admin: Admin<ViewAccessReports>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    app_id: i64,
+) -> Result<(ContentType, TextStream<BoxStream<'static, String>>), rocket::http::Status> {
+    let rdb = rdb.inner().clone();
+
+    let pool = rdb.clone();
+    let (paths, first_batch) = spawn_blocking(move || {
+        let mut conn = pool
+            .get()
+            .map_err(|_| rocket::http::Status::InternalServerError)?;
+        let paths = app_access_paths(&mut conn, app_id)?;
+        let first_batch = app_access_page(&mut conn, &paths, 0, ACCESS_EXPORT_BATCH)?;
+        Ok::<_, rocket::http::Status>((paths, first_batch))
+    })
+    .await
+    .map_err(|_| rocket::http::Status::InternalServerError)??;
+    let paths = Arc::new(paths);
+
+    Ok((
+        ContentType::CSV,
+        TextStream::from(
+            stream! {
+                yield "email_id,first_name,last_name,is_root,paths\n".to_string();
+
+                let mut batch = first_batch;
+                let mut offset = 0;
+                loop {
+                    let last = (batch.len() as i64) < ACCESS_EXPORT_BATCH;
+                    for entry in &batch {
+                        yield csv_row(entry);
+                    }
+                    if last {
+                        break;
+                    }
+
+                    offset += ACCESS_EXPORT_BATCH;
+                    let pool = rdb.clone();
+                    let paths = paths.clone();
+                    batch = match spawn_blocking(move || {
+                        let mut conn = pool
+                            .get()
+                            .map_err(|_| rocket::http::Status::InternalServerError)?;
+                        app_access_page(&mut conn, &paths, offset, ACCESS_EXPORT_BATCH)
+                    })
+                    .await
+                    {
+                        Ok(Ok(batch)) => batch,
+                        _ => break,
+                    };
+                }
+            }
+            .boxed(),
+        ),
+    ))
+}
+
+fn csv_row(entry: &AppAccessEntry) -> String {
+    let row = [
+        csv_field(&entry.email_id),
+        csv_field(entry.first_name.as_deref().unwrap_or_default()),
+        csv_field(entry.last_name.as_deref().unwrap_or_default()),
+        entry.is_root.to_string(),
+        csv_field(&entry.paths.join("; ")),
+    ];
+    format!("{}\n", row.join(","))
+}
+
+/// Quotes a CSV value where needed. Values a spreadsheet would run as a formula get a
+/// leading `'` so they are shown as text.
+pub(crate) fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+// Batch lookups are charged per item against the rate limits
+const MAX_EXISTENCE_BATCH: usize = 100;
+const EXISTENCE_WINDOW_SECONDS: i64 = 60;
+
+/// Charges `cost` lookups to the caller and to their IP. Existence checks reveal which
+/// accounts exist, so they are rate limited even for authenticated callers.
+fn charge_existence_checks<R>(
+    cache_pool: &Pool<RedisConnectionManager>,
+    caller: &InternalCaller<R>,
+    cost: usize,
+) -> Result<(), Status> {
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let mut buckets = vec![(
+        format!("existence:{}", caller.id),
+        config::existence_check_caller_limit(),
+    )];
+    if let Some(ip) = caller.ip {
+        buckets.push((
+            format!("existence:ip:{}", ip),
+            config::existence_check_ip_limit(),
+        ));
+    }
+
+    for (key, limit) in buckets {
+        let allowed = rate_limit::consume(
+            &mut cache_connection,
+            &key,
+            cost as u64,
+            limit,
+            EXISTENCE_WINDOW_SECONDS,
+        )
+        .map_err(|_| Status::ServiceUnavailable)?;
+        if !allowed {
+            return Err(Status::TooManyRequests);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a group exists. Only other services and signed-in admins may ask, API keys
+/// need the `groups:exists` scope.
 #[openapi]
 #[get("/group-exists/<uuid>")]
 pub fn check_group_exists(
+    caller: InternalCaller<GroupExists>,
     uuid: String,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
 ) -> Result<Json<bool>, rocket::http::Status> {
     use crate::models::schema::schema::group::dsl::*;
 
+    charge_existence_checks(cache_pool, &caller, 1)?;
+
     // Attempt to get a database connection
     let mut conn = rdb
         .get()
@@ -236,14 +773,20 @@ pub fn check_group_exists(
     }
 }
 
+/// Whether a user exists. Only other services and signed-in admins may ask, API keys need
+/// the `users:exists` scope.
 #[openapi]
 #[get("/user-exists/<email>")]
 pub fn check_user_exists(
+    caller: InternalCaller<UserExists>,
     email: String,
     rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
 ) -> Result<Json<bool>, rocket::http::Status> {
     use crate::models::schema::schema::user::dsl::*;
 
+    charge_existence_checks(cache_pool, &caller, 1)?;
+
     // Attempt to get a database connection
     let mut conn = rdb
         .get()
@@ -256,59 +799,750 @@ pub fn check_user_exists(
     }
 }
 
+/// Checks up to 100 group identifiers at once, mapping each to whether it exists.
 #[openapi]
-#[post("/create-invite", data = "<invite_request>")]
-pub async fn create_invite(
+#[post("/group-exists", format = "json", data = "<batch_request>")]
+pub fn check_groups_exist(
+    caller: InternalCaller<GroupExists>,
+    batch_request: Json<GroupExistsBatchRequest>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+) -> Result<Json<BTreeMap<String, bool>>, Status> {
+    use crate::models::schema::schema::group::dsl::*;
+
+    if batch_request.identifiers.len() > MAX_EXISTENCE_BATCH {
+        return Err(Status::BadRequest);
+    }
+    charge_existence_checks(cache_pool, &caller, batch_request.identifiers.len())?;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let found: Vec<String> = group
+        .filter(identifier.eq_any(&batch_request.identifiers))
+        .select(identifier)
+        .load(&mut conn)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        batch_request
+            .identifiers
+            .iter()
+            .map(|requested| (requested.clone(), found.contains(requested)))
+            .collect(),
+    ))
+}
+
+/// Checks up to 100 emails at once, mapping each to whether a user has it.
+#[openapi]
+#[post("/user-exists", format = "json", data = "<batch_request>")]
+pub fn check_users_exist(
+    caller: InternalCaller<UserExists>,
+    batch_request: Json<UserExistsBatchRequest>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+) -> Result<Json<BTreeMap<String, bool>>, Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    if batch_request.email_ids.len() > MAX_EXISTENCE_BATCH {
+        return Err(Status::BadRequest);
+    }
+    charge_existence_checks(cache_pool, &caller, batch_request.email_ids.len())?;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let found: Vec<String> = user
+        .filter(email_id.eq_any(&batch_request.email_ids))
+        .select(email_id)
+        .load(&mut conn)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        batch_request
+            .email_ids
+            .iter()
+            .map(|requested| (requested.clone(), found.contains(requested)))
+            .collect(),
+    ))
+}
+
+pub(crate) fn invite_target_app(
+    conn: &mut PgConnection,
+    invite_request: &InviteRequest,
+) -> Result<Option<App>, Status> {
+    let Some(target_client_id) = &invite_request.app_client_id else {
+        return Ok(None);
+    };
+
+    use crate::models::schema::schema::app::dsl::*;
+
+    match app
+        .filter(client_id.eq(target_client_id))
+        .filter(disabled.eq(false))
+        .first::<App>(conn)
+    {
+        Ok(target_app) => Ok(Some(target_app)),
+        Err(diesel::result::Error::NotFound) => Err(Status::UnprocessableEntity),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Root invites make the invitee root on redemption, so they need `grant_root` like
+/// promoting an existing user does.
+pub(crate) fn check_invite_role(role: Option<AdminRole>, is_root: bool) -> Result<(), Status> {
+    if is_root && !role.is_some_and(|role| role.can(Permission::GrantRoot)) {
+        return Err(Status::Forbidden);
+    }
+    Ok(())
+}
+
+fn invite_expiry(invite_request: &InviteRequest) -> Result<u64, Status> {
+    let expiration = invite_request
+        .expires_in_seconds
+        .unwrap_or_else(config::default_invite_expiry_seconds);
+    if !(config::MIN_INVITE_EXPIRY_SECONDS..=config::MAX_INVITE_EXPIRY_SECONDS)
+        .contains(&expiration)
+    {
+        return Err(Status::BadRequest);
+    }
+    Ok(expiration)
+}
+
+/// The acceptance page redirects to the app's login when a client_id is present.
+pub(crate) fn invite_accept_link(base_url: &str, token: &str, client_id: Option<&str>) -> String {
+    match client_id {
+        Some(client_id) => format!(
+            "{}/{}?client_id={}",
+            base_url,
+            token,
+            RawStr::new(client_id).percent_encode()
+        ),
+        None => format!("{}/{}", base_url, token),
+    }
+}
+
+pub(crate) fn render_invite_email(
+    templates: &EmailTemplates,
+    invite_request: &InviteRequest,
+    target_app: Option<&App>,
+    token: &str,
+    expiration: u64,
+    reminder: bool,
+) -> Result<RenderedEmail, Status> {
+    let accept_base = config::invite_accept_url().map_err(|err| {
+        println!("Failed to build invite link: {}", err);
+        Status::InternalServerError
+    })?;
+    let accept_link = invite_accept_link(
+        &accept_base,
+        token,
+        target_app.map(|target_app| target_app.client_id.as_str()),
+    );
+
+    templates
+        .render_invite(
+            invite_request.locale.as_deref(),
+            &InviteEmail {
+                first_name: &invite_request.first_name,
+                accept_link: &accept_link,
+                expires_in_seconds: expiration,
+                app: target_app,
+                reminder,
+            },
+        )
+        .map_err(|err| {
+            println!("Failed to render invite email: {:?}", err);
+            Status::InternalServerError
+        })
+}
+
+/// Renders the invitation email an invite request would send, without creating the invite.
+#[openapi]
+#[post("/invite-preview", data = "<invite_request>")]
+pub fn preview_invite(
+    _admin: Admin<SendInvites>,
     invite_request: Json<InviteRequest>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    templates: &State<EmailTemplates>,
+) -> Result<Json<RenderedEmail>, Status> {
+    let expiration = invite_expiry(&invite_request)?;
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target_app = invite_target_app(&mut conn, &invite_request)?;
+
+    render_invite_email(
+        templates,
+        &invite_request,
+        target_app.as_ref(),
+        "preview-token",
+        expiration,
+        false,
+    )
+    .map(Json)
+}
+
+/// Groups an invite grants on redemption: the listed groups plus the group of each listed
+/// app. Apps without a group are open to every user and need no grant.
+struct InviteGrants {
+    groups: Vec<GroupRef>,
+    unknown_groups: Vec<String>,
+    unknown_apps: Vec<String>,
+}
+
+fn resolve_invite_grants(
+    conn: &mut PgConnection,
+    invite_request: &InviteRequest,
+) -> QueryResult<InviteGrants> {
+    use crate::models::schema::schema::app::dsl as app_dsl;
+
+    let mut granted = groups::groups_by_identifiers(conn, &invite_request.group_identifiers)?;
+    let unknown_groups = invite_request
+        .group_identifiers
+        .iter()
+        .filter(|identifier| !granted.iter().any(|g| &g.identifier == *identifier))
+        .cloned()
+        .collect();
+
+    let apps = app_dsl::app
+        .filter(app_dsl::client_id.eq_any(&invite_request.app_client_ids))
+        .filter(app_dsl::disabled.eq(false))
+        .load::<App>(conn)?;
+    let unknown_apps = invite_request
+        .app_client_ids
+        .iter()
+        .filter(|requested| !apps.iter().any(|a| &a.client_id == *requested))
+        .cloned()
+        .collect();
+
+    let mut app_group_ids: Vec<i64> = apps
+        .iter()
+        .filter_map(|a| a.group_id)
+        .filter(|group_id| !granted.iter().any(|g| g.id == *group_id))
+        .collect();
+    app_group_ids.sort_unstable();
+    app_group_ids.dedup();
+    granted.extend(groups::groups_by_ids(conn, &app_group_ids)?);
+
+    Ok(InviteGrants {
+        groups: granted,
+        unknown_groups,
+        unknown_apps,
+    })
+}
+
+/// Domain rules for an invitee. The invite's target app is the app they register with.
+fn invite_domain_rules(
+    conn: &mut PgConnection,
+    invite_request: &InviteRequest,
+) -> Result<DomainRuleEvaluation, Status> {
+    let rules = domain_rules::list(conn).map_err(|_| Status::InternalServerError)?;
+    Ok(domain_rules::evaluate(
+        &rules,
+        &invite_request.email_id,
+        invite_request.app_client_id.as_deref(),
+    ))
+}
+
+fn invite_error(
+    status: Status,
+    code: &str,
+    message: &str,
+) -> status::Custom<Json<InviteErrorResponse>> {
+    status::Custom(
+        status,
+        Json(InviteErrorResponse {
+            code: code.to_string(),
+            message: message.to_string(),
+            invite_id: None,
+            expires_at: None,
+        }),
+    )
+}
+
+fn invite_failure(status: Status) -> status::Custom<Json<InviteErrorResponse>> {
+    invite_error(
+        status,
+        "internal_error",
+        status.reason().unwrap_or("The invite could not be created"),
+    )
+}
+
+/// Creates an invite and queues its email. The token, the history entry and the email are
+/// written in one Redis transaction; the outbox worker delivers the email with retries.
+///
+/// Inviting a root user needs the `grant_root` permission. Invites for existing accounts
+/// are rejected. An email with an outstanding invite gets a
+/// 409 naming that invite, unless `force=true` is passed to replace it with a new one.
+#[openapi]
+#[post("/create-invite?<force>", data = "<invite_request>")]
+pub fn create_invite(
+    admin: Admin<SendInvites>,
+    invite_request: Json<InviteRequest>,
+    force: Option<bool>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
     cache_pool: &State<Pool<RedisConnectionManager>>,
-) -> Result<(), Status> {
+    templates: &State<EmailTemplates>,
+) -> Result<Json<InviteResponse>, status::Custom<Json<InviteErrorResponse>>> {
+    let claims = admin.claims;
+    let mut invite_request = invite_request.into_inner();
+
+    invite_request.email_id =
+        validation::normalize_email(&invite_request.email_id).map_err(|_| {
+            invite_error(
+                Status::UnprocessableEntity,
+                "invalid_email",
+                "email_id is not a valid email address",
+            )
+        })?;
+    validation::check_invite_domain(
+        &invite_request.email_id,
+        &config::invite_allowed_domains(),
+        &config::invite_denied_domains(),
+    )
+    .map_err(|_| {
+        invite_error(
+            Status::UnprocessableEntity,
+            "domain_not_allowed",
+            "Invites cannot be sent to this email domain",
+        )
+    })?;
+
+    let expiration = invite_expiry(&invite_request).map_err(|_| {
+        invite_error(
+            Status::BadRequest,
+            "invalid_expiry",
+            "expires_in_seconds must be between 15 minutes and 7 days",
+        )
+    })?;
+    let mut conn = rdb
+        .get()
+        .map_err(|_| invite_failure(Status::InternalServerError))?;
+    let role = role_for(&mut conn, &claims.sub).map_err(invite_failure)?;
+    check_invite_role(role, invite_request.is_root).map_err(|status| {
+        invite_error(
+            status,
+            "root_invite_not_allowed",
+            "Only admins who can grant root may invite root users",
+        )
+    })?;
+    let target_app = invite_target_app(&mut conn, &invite_request).map_err(|status| {
+        if status == Status::UnprocessableEntity {
+            invite_error(
+                status,
+                "unknown_app",
+                "app_client_id does not match an enabled application",
+            )
+        } else {
+            invite_failure(status)
+        }
+    })?;
+
+    if users::email_taken(&mut conn, &invite_request.email_id)
+        .map_err(|_| invite_failure(Status::InternalServerError))?
+    {
+        return Err(invite_error(
+            Status::Conflict,
+            "user_exists",
+            "A user with this email already exists",
+        ));
+    }
+
+    for requested in [
+        &mut invite_request.group_identifiers,
+        &mut invite_request.app_client_ids,
+    ] {
+        requested.sort();
+        requested.dedup();
+    }
+    let grants = resolve_invite_grants(&mut conn, &invite_request)
+        .map_err(|_| invite_failure(Status::InternalServerError))?;
+    if !grants.unknown_groups.is_empty() {
+        return Err(invite_error(
+            Status::UnprocessableEntity,
+            "unknown_group",
+            &format!("Unknown groups: {}", grants.unknown_groups.join(", ")),
+        ));
+    }
+    if !grants.unknown_apps.is_empty() {
+        return Err(invite_error(
+            Status::UnprocessableEntity,
+            "unknown_app",
+            &format!(
+                "Unknown or disabled apps: {}",
+                grants.unknown_apps.join(", ")
+            ),
+        ));
+    }
+
+    let mut cache_connection = cache_pool
+        .get()
+        .map_err(|_| invite_failure(Status::ServiceUnavailable))?;
+
+    if !invite_domain_rules(&mut conn, &invite_request)
+        .map_err(invite_failure)?
+        .registration_allowed
+    {
+        return Err(invite_error(
+            Status::UnprocessableEntity,
+            "domain_not_allowed",
+            "The app only accepts registrations from other email domains",
+        ));
+    }
+
+    let replaced = invites::pending_for_email(&mut cache_connection, &invite_request.email_id)
+        .map_err(|_| invite_failure(Status::InternalServerError))?;
+    if let (Some(existing), false) = (&replaced, force.unwrap_or(false)) {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(InviteErrorResponse {
+                code: "invite_pending".to_string(),
+                message: "An invite for this email is still pending, pass force=true to replace it"
+                    .to_string(),
+                invite_id: Some(existing.invite_id.clone()),
+                expires_at: Some(existing.expires_at),
+            }),
+        ));
+    }
+
+    let invite_id = random_key(16);
+    let created_at = Utc::now();
+    let expires_at = created_at + Duration::seconds(expiration as i64);
+
+    // Only a keyed hash of the token is stored, the token itself goes out in the email
+    let token = invite_tokens::issue(&invite_id, &invite_request.email_id, expires_at);
+    let pending = PendingInvite {
+        invite_id: invite_id.clone(),
+        token_hash: invite_tokens::hash(&invite_request.email_id, &token),
+        invited_by: claims.sub.clone(),
+        invite: invite_request,
+    };
+    let invite_request = &pending.invite;
+
+    let rendered = render_invite_email(
+        templates,
+        invite_request,
+        target_app.as_ref(),
+        &token,
+        expiration,
+        false,
+    )
+    .map_err(invite_failure)?;
+
+    // The notification service takes a single body, so the HTML alternative is sent
+    let email_job = EmailJob::new(
+        invite_id.clone(),
+        &invite_request.email_id,
+        rendered.subject,
+        rendered.html,
+    );
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if let Some(existing) = &replaced {
+        invites::queue_revoke_pending(&mut pipe, &pending.invite.email_id, existing);
+        invites::queue_event(
+            &mut pipe,
+            &existing.invite_id,
+            &InviteEvent::new(InviteEventKind::Replaced, None),
+        );
+    }
+    invites::queue_pending(
+        &mut pipe,
+        &pending,
+        &PendingInviteRef {
+            invite_id: invite_id.clone(),
+            token_hash: pending.token_hash.clone(),
+            expires_at,
+            previous_token_hashes: Vec::new(),
+        },
+        expiration as usize,
+    );
+    invites::queue_follow_up(
+        &mut pipe,
+        &InviteFollowUp {
+            invite_id: invite_id.clone(),
+            email_id: invite_request.email_id.clone(),
+            invited_by: claims.sub.clone(),
+            expires_at,
+            remind_at: config::invite_reminder_lead_seconds(expiration)
+                .map(|lead| expires_at - Duration::seconds(lead as i64)),
+        },
+    );
+    invites::queue_history(
+        &mut pipe,
+        &InviteHistoryEntry {
+            invite_id: invite_id.clone(),
+            email_id: invite_request.email_id.clone(),
+            invited_by: claims.sub.clone(),
+            is_root: invite_request.is_root,
+            app_client_id: invite_request.app_client_id.clone(),
+            group_identifiers: invite_request.group_identifiers.clone(),
+            app_client_ids: invite_request.app_client_ids.clone(),
+            created_at,
+            expires_at,
+            events: Vec::new(),
+        },
+    );
+    outbox::queue_job(&mut pipe, &email_job);
+    pipe.query::<()>(&mut *cache_connection)
+        .map_err(|_| invite_failure(Status::InternalServerError))?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "invite.created",
+            &claims.sub,
+            &invite_request.email_id,
+            json!({
+                "invite_id": invite_id,
+                "is_root": invite_request.is_root,
+                "app_client_id": invite_request.app_client_id,
+                "group_identifiers": invite_request.group_identifiers,
+                "app_client_ids": invite_request.app_client_ids,
+                "expires_in_seconds": expiration,
+                "replaces": replaced.as_ref().map(|existing| &existing.invite_id),
+            }),
+        ),
+    );
+
+    Ok(Json(InviteResponse {
+        invite_id,
+        expires_at,
+        delivery: email_job.status,
+        replaced_invite_id: replaced.map(|existing| existing.invite_id),
+    }))
+}
+
+/// Reports whether the email for an invite has been delivered yet.
+#[openapi]
+#[get("/invites/<invite_id>/delivery")]
+pub fn get_invite_delivery(
+    _admin: Admin<ViewInvites>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    invite_id: String,
+) -> Result<Json<InviteDeliveryResponse>, Status> {
     let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
 
-    // Generate a unique token
-    let token: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(30)
-        .map(char::from)
-        .collect();
+    match outbox::get(&mut cache_connection, &invite_id) {
+        Ok(Some(job)) => Ok(Json(job.into())),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+fn find_pending_invite(
+    cache_connection: &mut redis::Connection,
+    redeem_request: &RedeemInviteRequest,
+) -> Result<PendingInvite, Status> {
+    let token_hash = invite_tokens::hash(&redeem_request.email_id, &redeem_request.token);
+    let pending = invites::get_pending(cache_connection, &token_hash)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    match invite_tokens::verify(
+        &redeem_request.email_id,
+        &redeem_request.token,
+        &pending.token_hash,
+    ) {
+        Ok(()) => Ok(pending),
+        Err(TokenError::Expired) => Err(Status::Gone),
+        Err(TokenError::Invalid) => Err(Status::NotFound),
+    }
+}
+
+/// Checks an invite token without redeeming it, so the acceptance page can show the invite
+/// and create the account from it.
+#[openapi]
+#[post("/invites/verify", format = "json", data = "<redeem_request>")]
+pub fn verify_invite(
+    _api_claims: APIClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    redeem_request: Json<RedeemInviteRequest>,
+) -> Result<Json<RedeemedInviteResponse>, Status> {
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let pending = find_pending_invite(&mut cache_connection, &redeem_request)?;
+    // Rules may have changed since the invite was sent
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    if !invite_domain_rules(&mut conn, &pending.invite)?.registration_allowed {
+        return Err(Status::Forbidden);
+    }
+
+    Ok(Json(RedeemedInviteResponse {
+        invite_id: pending.invite_id,
+        invited_by: pending.invited_by,
+        granted_groups: Vec::new(),
+        invite: pending.invite,
+    }))
+}
+
+/// Grants an invite and then consumes it. The invite is only consumed once the grants are
+/// committed, so a failed grant leaves it redeemable, and a failed consume can be retried
+/// because granting again changes nothing. When two redemptions race, both grant the same
+/// memberships but only the one that consumes the invite succeeds.
+pub(crate) fn redeem_once<G, C>(grant: G, consume: C) -> Result<(), Status>
+where
+    G: FnOnce() -> QueryResult<()>,
+    C: FnOnce() -> redis::RedisResult<bool>,
+{
+    grant().map_err(|err| {
+        println!("Failed to grant invite: {}", err);
+        Status::InternalServerError
+    })?;
 
-    // Serialize the invite data as JSON
-    let invite_data =
-        serde_json::to_string(&*invite_request).map_err(|_| Status::InternalServerError)?;
+    match consume() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Status::NotFound),
+        Err(err) => {
+            println!("Failed to consume invite: {}", err);
+            Err(Status::ServiceUnavailable)
+        }
+    }
+}
 
-    // Save the invite data in Redis with a token as the key
-    let expiration = 3600; // 1 hour in seconds
-    cache_connection
-        .set_ex(&token, invite_data, expiration)
+/// Redeems an invite once the invitee's account exists. The token must match the email it
+/// was issued for and can only be redeemed once. The invite's groups and app access, plus
+/// the groups of matching domain rules, are granted in one transaction before the invite
+/// is consumed.
+#[openapi]
+#[post("/invites/redeem", format = "json", data = "<redeem_request>")]
+pub fn redeem_invite(
+    api_claims: APIClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    templates: &State<EmailTemplates>,
+    redeem_request: Json<RedeemInviteRequest>,
+) -> Result<Json<RedeemedInviteResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let pending = find_pending_invite(&mut cache_connection, &redeem_request)?;
+    let member = match users::find_by_email(&mut conn, &pending.invite.email_id) {
+        Ok(member) => member,
+        // The account is created from the verified invite before redeeming it
+        Err(diesel::result::Error::NotFound) => return Err(Status::Conflict),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let rule_evaluation = invite_domain_rules(&mut conn, &pending.invite)?;
+    if !rule_evaluation.registration_allowed {
+        return Err(Status::Forbidden);
+    }
+
+    let mut grants = resolve_invite_grants(&mut conn, &pending.invite)
         .map_err(|_| Status::InternalServerError)?;
+    for group in groups::groups_by_identifiers(&mut conn, &rule_evaluation.group_identifiers)
+        .map_err(|_| Status::InternalServerError)?
+    {
+        if !grants.groups.iter().any(|granted| granted.id == group.id) {
+            grants.groups.push(group);
+        }
+    }
 
-    // Configure the email sending service
-    let mut configuration = get_notification_service_configuration();
+    redeem_once(
+        || {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                for group in &grants.groups {
+                    groups::add_member(conn, group.id, member.id)?;
+                }
+                Ok(())
+            })
+        },
+        || invites::consume_pending(&mut cache_connection, &pending),
+    )?;
 
-    let token_str = env::var("ISC_SECRET").expect("ISC_SECRET must be set");
+    let granted_groups: Vec<String> = grants
+        .groups
+        .iter()
+        .map(|group| group.identifier.clone())
+        .collect();
+    group_cache::invalidate_quietly(&mut cache_connection, &[member.id]);
 
-    configuration.api_key = Some(NotificationApiKey {
-        key: token_str,
-        prefix: None,
-    });
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    invites::queue_event(
+        &mut pipe,
+        &pending.invite_id,
+        &InviteEvent::new(InviteEventKind::Redeemed, Some(&pending.invited_by)),
+    );
+    match templates.render_invite_accepted(&pending.invite) {
+        Ok(rendered) => outbox::queue_job(
+            &mut pipe,
+            &EmailJob::new(
+                random_key(16),
+                &pending.invited_by,
+                rendered.subject,
+                rendered.html,
+            ),
+        ),
+        Err(err) => println!("Failed to render acceptance notice: {}", err),
+    }
+    if let Err(err) = pipe.query::<()>(&mut *cache_connection) {
+        println!(
+            "Failed to record redemption of invite {}: {}",
+            pending.invite_id, err
+        );
+    }
 
-    // Send the invite email
-    let email_body = format!(
-        "Hello {first_name},\n\nYou have been invited to join our platform. Use the following link to accept the invite: \
-        https://iam-staging.gingersociety/#/accept-invite/{token}\n\nThe link expires in 1 hour.",
-        first_name = invite_request.first_name,
-        token = token
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "invite.redeemed",
+            &api_claims.sub,
+            &pending.invite.email_id,
+            json!({
+                "invite_id": pending.invite_id,
+                "granted_groups": granted_groups,
+                "skipped_groups": grants.unknown_groups,
+                "skipped_apps": grants.unknown_apps,
+                "domain_rules": rule_evaluation.matched_rule_ids,
+            }),
+        ),
     );
 
-    let email_request = EmailRequest {
-        to: invite_request.email_id.clone(),
-        subject: "You're Invited!".to_string(),
-        message: email_body,
-        reply_to: None,
-    };
+    Ok(Json(RedeemedInviteResponse {
+        invite_id: pending.invite_id,
+        invited_by: pending.invited_by,
+        granted_groups,
+        invite: pending.invite,
+    }))
+}
 
-    send_email(&configuration, SendEmailParams { email_request })
-        .await
-        .map_err(|_| Status::ServiceUnavailable)?;
+/// Lists outstanding invites, soonest to expire first.
+#[openapi]
+#[get("/invites?<page>&<page_size>")]
+pub fn list_pending_invites(
+    _admin: Admin<ViewInvites>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    page: Option<usize>,
+    page_size: Option<usize>,
+) -> Result<Json<PaginatedResponse<PendingInviteEntry>>, Status> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(10);
+    if page_size == 0 {
+        return Err(Status::BadRequest);
+    }
 
-    Ok(())
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let (total_count, pending) = invites::list_pending(
+        &mut cache_connection,
+        ((page - 1) * page_size) as isize,
+        page_size as isize,
+    )
+    .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(PaginatedResponse {
+        total_count,
+        data: pending
+            .into_iter()
+            .map(|(index, pending)| PendingInviteEntry {
+                invite_id: pending.invite_id,
+                email_id: pending.invite.email_id,
+                first_name: pending.invite.first_name,
+                last_name: pending.invite.last_name,
+                is_root: pending.invite.is_root,
+                invited_by: pending.invited_by,
+                group_identifiers: pending.invite.group_identifiers,
+                app_client_ids: pending.invite.app_client_ids,
+                expires_at: index.expires_at,
+            })
+            .collect(),
+    }))
 }
@@ -0,0 +1,120 @@
+use crate::db::admin_roles::{self, RoleAssignment};
+use crate::db::audit::{self, AuditEvent};
+use crate::middlewares::admin::Admin;
+use crate::models::request::AssignAdminRoleRequest;
+use crate::models::response::PermissionMatrixEntry;
+use crate::permissions::require::{ManageRoles, ViewUsers};
+use crate::permissions::Permission;
+use crate::routes::groups::find_user;
+use chrono::Utc;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_models::MessageResponse;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+/// Which roles hold each permission. Root users are always super admins.
+#[openapi]
+#[get("/admin-roles/permissions")]
+pub fn get_permission_matrix(_admin: Admin<ViewUsers>) -> Json<Vec<PermissionMatrixEntry>> {
+    Json(
+        Permission::ALL
+            .iter()
+            .map(|&permission| PermissionMatrixEntry {
+                permission,
+                roles: permission.roles(),
+            })
+            .collect(),
+    )
+}
+
+/// Lists assigned admin roles. Root users don't need an assignment and aren't listed.
+#[openapi]
+#[get("/admin-roles")]
+pub fn list_admin_roles(
+    _admin: Admin<ManageRoles>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<Json<Vec<RoleAssignment>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    admin_roles::list(&mut conn)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Gives a user an admin role, replacing any role they had. Admins can't change their own.
+#[openapi]
+#[put("/admin-roles/<email>", format = "json", data = "<role_request>")]
+pub fn assign_admin_role(
+    admin: Admin<ManageRoles>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    email: &str,
+    role_request: Json<AssignAdminRoleRequest>,
+) -> Result<Json<RoleAssignment>, Status> {
+    if email == admin.claims.sub {
+        return Err(Status::Forbidden);
+    }
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let member = find_user(&mut conn, email)?;
+
+    let assignment = RoleAssignment {
+        email_id: member.email_id,
+        role: role_request.role,
+        granted_by: admin.claims.sub.clone(),
+        granted_at: Utc::now(),
+    };
+
+    let previous = admin_roles::get(&mut conn, &assignment.email_id)
+        .map_err(|_| Status::InternalServerError)?;
+    admin_roles::save(&mut conn, &assignment).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "admin_role.assigned",
+            &admin.claims.sub,
+            &assignment.email_id,
+            json!({
+                "role": assignment.role,
+                "previous_role": previous.map(|previous| previous.role),
+            }),
+        ),
+    );
+
+    Ok(Json(assignment))
+}
+
+#[openapi]
+#[delete("/admin-roles/<email>")]
+pub fn remove_admin_role(
+    admin: Admin<ManageRoles>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    email: &str,
+) -> Result<Json<MessageResponse>, Status> {
+    if email == admin.claims.sub {
+        return Err(Status::Forbidden);
+    }
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let previous = admin_roles::get(&mut conn, email)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    admin_roles::remove(&mut conn, email).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "admin_role.removed",
+            &admin.claims.sub,
+            email,
+            json!({ "previous_role": previous.role }),
+        ),
+    );
+
+    Ok(Json(MessageResponse {
+        message: "Admin role removed".to_string(),
+    }))
+}
@@ -0,0 +1,158 @@
+use crate::db::api_keys::{self, ApiKey, KNOWN_SCOPES};
+use crate::db::audit::{self, AuditEvent};
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::CreateApiKeyRequest;
+use crate::models::response::{ApiKeyResponse, ApiKeySecretResponse};
+use crate::routes::groups::require_root;
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+const DEFAULT_ROTATION_GRACE_SECONDS: i64 = 3600;
+const MAX_ROTATION_GRACE_SECONDS: i64 = 7 * 24 * 3600;
+
+fn load_key(conn: &mut PgConnection, key_id: &str) -> Result<ApiKey, Status> {
+    api_keys::get(conn, key_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)
+}
+
+/// Lists every API key, revoked ones included. Secrets are never returned.
+#[openapi]
+#[get("/api-keys")]
+pub fn list_api_keys(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<Json<Vec<ApiKeyResponse>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let keys = api_keys::list(&mut conn).map_err(|_| Status::InternalServerError)?;
+    Ok(Json(keys.into_iter().map(ApiKeyResponse::from).collect()))
+}
+
+/// Creates a scoped key for another service. The key is only returned in this response.
+#[openapi]
+#[post("/api-keys", format = "json", data = "<key_request>")]
+pub fn create_api_key(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    key_request: Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiKeySecretResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let name = key_request.name.trim();
+    if name.is_empty() || key_request.scopes.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    if key_request
+        .scopes
+        .iter()
+        .any(|scope| !KNOWN_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(Status::UnprocessableEntity);
+    }
+    let mut scopes = key_request.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let (api_key, secret) = api_keys::generate(name, scopes, &claims.sub);
+
+    api_keys::save(&mut conn, &api_key).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "api_key.created",
+            &claims.sub,
+            &api_key.name,
+            json!({ "key_id": api_key.id, "scopes": api_key.scopes }),
+        ),
+    );
+
+    Ok(Json(ApiKeySecretResponse {
+        key: api_key.into(),
+        api_key: secret,
+    }))
+}
+
+/// Issues a new secret for a key. The previous secret keeps working for `grace_seconds`,
+/// an hour by default, so the calling service can be redeployed with the new one.
+#[openapi]
+#[post("/api-keys/<key_id>/rotate?<grace_seconds>")]
+pub fn rotate_api_key(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    key_id: &str,
+    grace_seconds: Option<i64>,
+) -> Result<Json<ApiKeySecretResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let grace_seconds = grace_seconds.unwrap_or(DEFAULT_ROTATION_GRACE_SECONDS);
+    if !(0..=MAX_ROTATION_GRACE_SECONDS).contains(&grace_seconds) {
+        return Err(Status::BadRequest);
+    }
+
+    let mut api_key = load_key(&mut conn, key_id)?;
+    if !api_key.is_active() {
+        return Err(Status::Conflict);
+    }
+
+    let secret = api_key.rotate(Duration::seconds(grace_seconds));
+    api_keys::save(&mut conn, &api_key).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "api_key.rotated",
+            &claims.sub,
+            &api_key.name,
+            json!({ "key_id": api_key.id, "grace_seconds": grace_seconds }),
+        ),
+    );
+
+    Ok(Json(ApiKeySecretResponse {
+        key: api_key.into(),
+        api_key: secret,
+    }))
+}
+
+/// Revokes a key, including any secret still in its rotation grace period. The record is
+/// kept so audits can still resolve the key id.
+#[openapi]
+#[delete("/api-keys/<key_id>")]
+pub fn revoke_api_key(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    key_id: &str,
+) -> Result<Json<ApiKeyResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let mut api_key = load_key(&mut conn, key_id)?;
+    if !api_key.is_active() {
+        return Err(Status::Conflict);
+    }
+
+    api_key.revoked_at = Some(Utc::now());
+    api_keys::save(&mut conn, &api_key).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "api_key.revoked",
+            &claims.sub,
+            &api_key.name,
+            json!({ "key_id": api_key.id }),
+        ),
+    );
+
+    Ok(Json(api_key.into()))
+}
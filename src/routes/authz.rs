@@ -0,0 +1,148 @@
+use crate::db::audit::{self, AuditEvent};
+use crate::db::groups;
+use crate::middlewares::admin::role_for;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::ExplainRequest;
+use crate::models::schema::{App, User};
+use crate::permissions::Permission;
+use crate::policy::{Decision, PolicySet, Resource, ResourceKind, Subject};
+use crate::routes::groups::find_user;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+pub(crate) fn subject_for(
+    conn: &mut PgConnection,
+    email: &str,
+    owned_groups: Vec<String>,
+) -> Result<Subject, Status> {
+    Ok(Subject {
+        email_id: email.to_string(),
+        role: role_for(conn, email)?,
+        owned_groups,
+    })
+}
+
+pub(crate) fn user_resource(conn: &mut PgConnection, target: &User) -> Result<Resource, Status> {
+    let member_of = groups::effective_groups_for_user(conn, target.id)
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(Resource {
+        kind: ResourceKind::User,
+        id: target.email_id.clone(),
+        is_root: target.is_root,
+        groups: member_of
+            .into_iter()
+            .map(|group| group.identifier)
+            .collect(),
+    })
+}
+
+pub(crate) fn app_resource(
+    conn: &mut PgConnection,
+    app_client_id: &str,
+) -> Result<Resource, Status> {
+    use crate::models::schema::schema::app::dsl::*;
+
+    let target = match app.filter(client_id.eq(app_client_id)).first::<App>(conn) {
+        Ok(target) => target,
+        Err(diesel::result::Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let app_group = match target.group_id {
+        Some(app_group_id) => {
+            groups::groups_by_ids(conn, &[app_group_id]).map_err(|_| Status::InternalServerError)?
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Resource {
+        kind: ResourceKind::App,
+        id: target.client_id,
+        is_root: false,
+        groups: app_group
+            .into_iter()
+            .map(|group| group.identifier)
+            .collect(),
+    })
+}
+
+/// Checks an action against the policies, recording denials in the audit trail.
+pub(crate) fn authorize(
+    policies: &PolicySet,
+    conn: &mut PgConnection,
+    subject: &Subject,
+    action: &str,
+    resource: &Resource,
+) -> Result<(), Status> {
+    let decision = policies.evaluate(subject, action, resource);
+    if decision.allowed {
+        return Ok(());
+    }
+
+    audit::record(
+        conn,
+        AuditEvent::new(
+            "authz.denied",
+            &subject.email_id,
+            &resource.id,
+            json!({ "action": action, "decided_by": decision.decided_by }),
+        ),
+    );
+    Err(Status::Forbidden)
+}
+
+/// Evaluates the authorization policies for an action and says which policy decided it
+/// and how every policy's conditions came out. Explaining another subject's access needs
+/// the `manage_roles` permission.
+#[openapi]
+#[post("/authz/explain", format = "json", data = "<explain_request>")]
+pub fn explain_decision(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    policies: &State<PolicySet>,
+    explain_request: Json<ExplainRequest>,
+) -> Result<Json<Decision>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let caller = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let subject = match &explain_request.subject_email {
+        Some(email) if *email != claims.sub => {
+            if !caller
+                .role
+                .is_some_and(|role| role.can(Permission::ManageRoles))
+            {
+                return Err(Status::Forbidden);
+            }
+            let other = find_user(&mut conn, email)?;
+            let owned = groups::groups_owned_by_user(&mut conn, other.id)
+                .map_err(|_| Status::InternalServerError)?;
+            subject_for(
+                &mut conn,
+                &other.email_id,
+                owned.into_iter().map(|group| group.identifier).collect(),
+            )?
+        }
+        _ => caller,
+    };
+
+    let resource = match explain_request.resource_kind {
+        ResourceKind::User => {
+            let target = find_user(&mut conn, &explain_request.resource_id)?;
+            user_resource(&mut conn, &target)?
+        }
+        ResourceKind::App => app_resource(&mut conn, &explain_request.resource_id)?,
+    };
+
+    Ok(Json(policies.evaluate(
+        &subject,
+        &explain_request.action,
+        &resource,
+    )))
+}
@@ -0,0 +1,327 @@
+use crate::db::audit::{self, AuditEvent};
+use crate::db::domain_rules::{self, DomainRule, DomainRuleAction, DomainRuleEvaluation};
+use crate::db::groups;
+use crate::db::redis::random_key;
+use crate::db::users;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{
+    ApplyDomainRulesRequest, DomainRuleRequest, EvaluateDomainRulesRequest,
+};
+use crate::models::response::{
+    AppliedDomainRulesResponse, DomainRuleDryRunResponse, DomainRuleImpact,
+};
+use crate::models::schema::App;
+use crate::routes::admin::effective_app_access;
+use crate::routes::groups::{find_group, find_user, grant_membership, require_root};
+use crate::validation::{self, domain_matches};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_models::MessageResponse;
+use ginger_shared_rs::rocket_utils::APIClaims;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+pub(crate) fn find_app(conn: &mut PgConnection, app_client_id: &str) -> Result<App, Status> {
+    use crate::models::schema::schema::app::dsl::*;
+
+    match app.filter(client_id.eq(app_client_id)).first::<App>(conn) {
+        Ok(record) => Ok(record),
+        Err(diesel::result::Error::NotFound) => Err(Status::UnprocessableEntity),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/// Normalises the domain and checks that the group or app the rule points at exists.
+fn validate_rule(
+    conn: &mut PgConnection,
+    rule_request: &DomainRuleRequest,
+) -> Result<String, Status> {
+    let domain =
+        validation::normalize_domain(&rule_request.domain).map_err(|_| Status::BadRequest)?;
+
+    match &rule_request.action {
+        DomainRuleAction::JoinGroup { group_identifier } => {
+            find_group(conn, group_identifier).map_err(|status| {
+                if status == Status::NotFound {
+                    Status::UnprocessableEntity
+                } else {
+                    status
+                }
+            })?;
+        }
+        DomainRuleAction::RestrictRegistration { app_client_id } => {
+            find_app(conn, app_client_id)?;
+        }
+    }
+
+    Ok(domain)
+}
+
+/// Lists every email domain rule, oldest first.
+#[openapi]
+#[get("/domain-rules")]
+pub fn list_domain_rules(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<Json<Vec<DomainRule>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let rules = domain_rules::list(&mut conn).map_err(|_| Status::InternalServerError)?;
+    Ok(Json(rules))
+}
+
+/// Adds a rule that puts users of an email domain into a group, or limits registration for
+/// an app to certain domains. Only root users manage rules.
+#[openapi]
+#[post("/domain-rules", format = "json", data = "<rule_request>")]
+pub fn create_domain_rule(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    rule_request: Json<DomainRuleRequest>,
+) -> Result<Json<DomainRule>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+    let domain = validate_rule(&mut conn, &rule_request)?;
+
+    let now = Utc::now();
+    let rule = DomainRule {
+        id: random_key(16),
+        domain,
+        action: rule_request.action.clone(),
+        enabled: rule_request.enabled,
+        created_by: claims.sub.clone(),
+        created_at: now,
+        updated_at: now,
+    };
+
+    domain_rules::save(&mut conn, &rule).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "domain_rule.created",
+            &claims.sub,
+            &rule.domain,
+            json!({ "rule_id": rule.id, "action": rule.action, "enabled": rule.enabled }),
+        ),
+    );
+
+    Ok(Json(rule))
+}
+
+#[openapi]
+#[put("/domain-rules/<rule_id>", format = "json", data = "<rule_request>")]
+pub fn update_domain_rule(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    rule_id: &str,
+    rule_request: Json<DomainRuleRequest>,
+) -> Result<Json<DomainRule>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+    let domain = validate_rule(&mut conn, &rule_request)?;
+
+    let mut rule = domain_rules::get(&mut conn, rule_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    rule.domain = domain;
+    rule.action = rule_request.action.clone();
+    rule.enabled = rule_request.enabled;
+    rule.updated_at = Utc::now();
+    domain_rules::save(&mut conn, &rule).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "domain_rule.updated",
+            &claims.sub,
+            &rule.domain,
+            json!({ "rule_id": rule.id, "action": rule.action, "enabled": rule.enabled }),
+        ),
+    );
+
+    Ok(Json(rule))
+}
+
+/// Deletes a rule. Group memberships it already granted are kept.
+#[openapi]
+#[delete("/domain-rules/<rule_id>")]
+pub fn delete_domain_rule(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    rule_id: &str,
+) -> Result<Json<MessageResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let rule = domain_rules::get(&mut conn, rule_id)
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    domain_rules::delete(&mut conn, rule_id).map_err(|_| Status::InternalServerError)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "domain_rule.deleted",
+            &claims.sub,
+            &rule.domain,
+            json!({ "rule_id": rule.id, "action": rule.action }),
+        ),
+    );
+
+    Ok(Json(MessageResponse {
+        message: "Domain rule deleted".to_string(),
+    }))
+}
+
+/// Shows which existing users a rule would affect without saving it: users it would add
+/// to its group, or users of its app whose domain it would not let register.
+#[openapi]
+#[post("/domain-rules/dry-run", format = "json", data = "<rule_request>")]
+pub fn dry_run_domain_rule(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    rule_request: Json<DomainRuleRequest>,
+) -> Result<Json<DomainRuleDryRunResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+    let domain = validate_rule(&mut conn, &rule_request)?;
+
+    let affected = match &rule_request.action {
+        DomainRuleAction::JoinGroup { group_identifier } => {
+            let group = find_group(&mut conn, group_identifier)?;
+            let members: Vec<i64> = groups::effective_members(&mut conn, group.id)
+                .map_err(|_| Status::InternalServerError)?
+                .into_iter()
+                .map(|member| member.user_id)
+                .collect();
+
+            users::with_email_domain(&mut conn, &domain)
+                .map_err(|_| Status::InternalServerError)?
+                .into_iter()
+                .filter(|candidate| !members.contains(&candidate.id))
+                .map(|candidate| DomainRuleImpact {
+                    email_id: candidate.email_id,
+                    first_name: candidate.first_name,
+                    last_name: candidate.last_name,
+                    effect: format!("joins group {}", group.identifier),
+                })
+                .collect::<Vec<_>>()
+        }
+        DomainRuleAction::RestrictRegistration { app_client_id } => {
+            let target_app = find_app(&mut conn, app_client_id)?;
+
+            // The rule widens whatever the app's existing restrictions already allow
+            let mut allowed_domains: Vec<String> = domain_rules::list(&mut conn)
+                .map_err(|_| Status::InternalServerError)?
+                .into_iter()
+                .filter(|rule| rule.enabled)
+                .filter_map(|rule| match rule.action {
+                    DomainRuleAction::RestrictRegistration {
+                        app_client_id: restricted_app,
+                    } if &restricted_app == app_client_id => Some(rule.domain),
+                    _ => None,
+                })
+                .collect();
+            allowed_domains.push(domain);
+
+            effective_app_access(&mut conn, target_app.id)?
+                .into_iter()
+                .filter(|entry| {
+                    let user_domain = validation::email_domain(&entry.email_id).to_lowercase();
+                    !allowed_domains
+                        .iter()
+                        .any(|allowed| domain_matches(&user_domain, allowed))
+                })
+                .map(|entry| DomainRuleImpact {
+                    email_id: entry.email_id,
+                    first_name: entry.first_name,
+                    last_name: entry.last_name,
+                    effect: format!("could not register with {}", target_app.client_id),
+                })
+                .collect()
+        }
+    };
+
+    Ok(Json(DomainRuleDryRunResponse {
+        total_count: affected.len(),
+        affected,
+    }))
+}
+
+/// Evaluates the rules for a user about to be created, so the registering service can
+/// refuse emails an app does not accept. Apps that don't allow registration refuse
+/// everyone.
+#[openapi]
+#[post("/domain-rules/evaluate", format = "json", data = "<evaluate_request>")]
+pub fn evaluate_domain_rules(
+    _api_claims: APIClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    evaluate_request: Json<EvaluateDomainRulesRequest>,
+) -> Result<Json<DomainRuleEvaluation>, Status> {
+    let email = validation::normalize_email(&evaluate_request.email_id)
+        .map_err(|_| Status::UnprocessableEntity)?;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let rules = domain_rules::list(&mut conn).map_err(|_| Status::InternalServerError)?;
+    let mut evaluation =
+        domain_rules::evaluate(&rules, &email, evaluate_request.app_client_id.as_deref());
+
+    if let Some(app_client_id) = &evaluate_request.app_client_id {
+        let target_app = find_app(&mut conn, app_client_id).map_err(|status| {
+            if status == Status::UnprocessableEntity {
+                Status::NotFound
+            } else {
+                status
+            }
+        })?;
+        evaluation.registration_allowed &= target_app.allow_registration && !target_app.disabled;
+    }
+
+    Ok(Json(evaluation))
+}
+
+/// Adds a newly created user to the groups their email domain's rules point at.
+#[openapi]
+#[post("/domain-rules/apply", format = "json", data = "<apply_request>")]
+pub fn apply_domain_rules(
+    api_claims: APIClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    apply_request: Json<ApplyDomainRulesRequest>,
+) -> Result<Json<AppliedDomainRulesResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let member = find_user(&mut conn, &apply_request.email_id)?;
+    let rules = domain_rules::list(&mut conn).map_err(|_| Status::InternalServerError)?;
+    let evaluation = domain_rules::evaluate(&rules, &member.email_id, None);
+
+    // Rules can outlive their group, those are skipped
+    let granted = groups::groups_by_identifiers(&mut conn, &evaluation.group_identifiers)
+        .map_err(|_| Status::InternalServerError)?;
+    for group in &granted {
+        grant_membership(
+            &mut conn,
+            &mut cache_connection,
+            group,
+            &member,
+            Utc::now(),
+            None,
+            &api_claims.sub,
+        )?;
+    }
+
+    Ok(Json(AppliedDomainRulesResponse {
+        email_id: member.email_id,
+        granted_groups: granted.into_iter().map(|group| group.identifier).collect(),
+    }))
+}
@@ -0,0 +1,435 @@
+use crate::db::audit::{self, AuditEvent};
+use crate::db::group_cache;
+use crate::db::groups::{self, GroupRef, NestingError};
+use crate::db::memberships::{self, MembershipWindow};
+use crate::db::users;
+use crate::middlewares::admin::role_for;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::internal_caller::InternalCaller;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{AddGroupMemberRequest, NestGroupRequest};
+use crate::models::response::{GroupMemberEntry, GroupMembersResponse};
+use crate::models::schema::User;
+use crate::permissions::require::ReadGroups;
+use crate::permissions::{AdminRole, Permission};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_utils::Claims;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub(crate) fn find_group(conn: &mut PgConnection, identifier: &str) -> Result<GroupRef, Status> {
+    match groups::group_by_identifier(conn, identifier) {
+        Ok(group) => Ok(group),
+        Err(diesel::result::Error::NotFound) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+pub(crate) fn find_user(conn: &mut PgConnection, email: &str) -> Result<User, Status> {
+    match users::find_by_email(conn, email) {
+        Ok(record) => Ok(record),
+        Err(diesel::result::Error::NotFound) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+pub(crate) fn require_root(conn: &mut PgConnection, claims: &Claims) -> Result<(), Status> {
+    if find_user(conn, &claims.sub)?.is_root {
+        Ok(())
+    } else {
+        Err(Status::Forbidden)
+    }
+}
+
+/// Checks the caller may change a group: admins whose role holds `permission`, which root
+/// users always do, or an owner of the group when `owns_group` is set.
+pub(crate) fn authorize_group_change(
+    role: Option<AdminRole>,
+    owns_group: bool,
+    permission: Permission,
+) -> Result<(), Status> {
+    if owns_group || role.is_some_and(|role| role.can(permission)) {
+        Ok(())
+    } else {
+        Err(Status::Forbidden)
+    }
+}
+
+/// How a grant changes a user's membership of a group.
+#[derive(Debug, PartialEq)]
+pub(crate) enum GrantPlan {
+    /// Add the membership and drop any window, so it never expires.
+    Permanent,
+    /// Record a window for the sweeper, adding the membership now if it has started.
+    TimeBound { add_now: bool },
+    /// The user is already a permanent member. A window would make the sweeper remove
+    /// that membership, so the grant leaves it as it is.
+    KeepPermanent,
+}
+
+pub(crate) fn plan_grant(
+    is_member: bool,
+    has_window: bool,
+    valid_from: DateTime<Utc>,
+    valid_until: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> GrantPlan {
+    if valid_from <= now && valid_until.is_none() {
+        GrantPlan::Permanent
+    } else if is_member && !has_window {
+        GrantPlan::KeepPermanent
+    } else {
+        GrantPlan::TimeBound {
+            add_now: valid_from <= now,
+        }
+    }
+}
+
+/// Adds a user to a group, optionally only for a time window. Memberships starting in the
+/// future are inserted by the membership sweeper once `valid_from` passes, and memberships
+/// with `valid_until` are removed by it when they expire. A time-bound grant to a user who
+/// is already a permanent member keeps the permanent membership.
+pub(crate) fn grant_membership(
+    conn: &mut PgConnection,
+    cache_connection: &mut redis::Connection,
+    group: &GroupRef,
+    member: &User,
+    valid_from: DateTime<Utc>,
+    valid_until: Option<DateTime<Utc>>,
+    granted_by: &str,
+) -> Result<(), Status> {
+    let is_member = groups::is_direct_member(conn, group.id, member.id)
+        .map_err(|_| Status::InternalServerError)?;
+    let has_window = memberships::has_window(cache_connection, group.id, member.id)
+        .map_err(|_| Status::InternalServerError)?;
+    let plan = plan_grant(is_member, has_window, valid_from, valid_until, Utc::now());
+
+    match plan {
+        GrantPlan::Permanent => {
+            groups::add_member(conn, group.id, member.id)
+                .map_err(|_| Status::InternalServerError)?;
+            // A permanent grant replaces any earlier time-bound one
+            memberships::delete_window(cache_connection, group.id, member.id)
+                .map_err(|_| Status::InternalServerError)?;
+        }
+        GrantPlan::TimeBound { add_now } => {
+            if add_now {
+                groups::add_member(conn, group.id, member.id)
+                    .map_err(|_| Status::InternalServerError)?;
+            }
+            memberships::save_window(
+                cache_connection,
+                &MembershipWindow {
+                    group_id: group.id,
+                    group_identifier: group.identifier.clone(),
+                    user_id: member.id,
+                    email_id: member.email_id.clone(),
+                    valid_from,
+                    valid_until,
+                    granted_by: granted_by.to_string(),
+                    reminder_sent: false,
+                },
+            )
+            .map_err(|_| Status::InternalServerError)?;
+        }
+        GrantPlan::KeepPermanent => {}
+    }
+
+    group_cache::invalidate_quietly(cache_connection, &[member.id]);
+
+    audit::record(
+        conn,
+        AuditEvent::new(
+            "membership.granted",
+            granted_by,
+            &member.email_id,
+            json!({
+                "group": group.identifier,
+                "valid_from": valid_from,
+                "valid_until": valid_until,
+                "kept_permanent": plan == GrantPlan::KeepPermanent,
+            }),
+        ),
+    );
+
+    Ok(())
+}
+
+/// Removes a direct membership together with any validity window recorded for it.
+pub(crate) fn revoke_membership(
+    conn: &mut PgConnection,
+    cache_connection: &mut redis::Connection,
+    group: &GroupRef,
+    user_id: i64,
+    email_id: &str,
+    revoked_by: &str,
+    mut details: Value,
+) -> Result<(), Status> {
+    groups::remove_member(conn, group.id, user_id).map_err(|_| Status::InternalServerError)?;
+    memberships::delete_window(cache_connection, group.id, user_id)
+        .map_err(|_| Status::InternalServerError)?;
+
+    group_cache::invalidate_quietly(cache_connection, &[user_id]);
+
+    details["group"] = json!(group.identifier);
+    audit::record(
+        conn,
+        AuditEvent::new("membership.revoked", revoked_by, email_id, details),
+    );
+
+    Ok(())
+}
+
+/// Nesting changes alter the inherited memberships of everyone in the child group and
+/// the groups below it.
+fn invalidate_subtree(
+    conn: &mut PgConnection,
+    cache_connection: &mut redis::Connection,
+    group_id: i64,
+) {
+    match groups::effective_members(conn, group_id) {
+        Ok(members) => {
+            let user_ids: Vec<i64> = members.iter().map(|member| member.user_id).collect();
+            group_cache::invalidate_quietly(cache_connection, &user_ids);
+        }
+        Err(err) => println!("Failed to resolve members to invalidate: {}", err),
+    }
+}
+
+/// Adds a member to a group. Needs an admin role with `edit_users` or ownership of the
+/// group.
+#[openapi]
+#[post(
+    "/groups/<identifier>/members",
+    format = "json",
+    data = "<member_request>"
+)]
+pub fn add_group_member(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    identifier: String,
+    member_request: Json<AddGroupMemberRequest>,
+) -> Result<Status, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let valid_from = member_request.valid_from.unwrap_or_else(Utc::now);
+    if let Some(valid_until) = member_request.valid_until {
+        if valid_until <= valid_from || valid_until <= Utc::now() {
+            return Err(Status::BadRequest);
+        }
+    }
+
+    let group = find_group(&mut conn, &identifier)?;
+    authorize_group_change(
+        role_for(&mut conn, &claims.sub)?,
+        ownerships.0.contains(&group.identifier),
+        Permission::EditUsers,
+    )?;
+    let member = find_user(&mut conn, &member_request.email_id)?;
+
+    grant_membership(
+        &mut conn,
+        &mut cache_connection,
+        &group,
+        &member,
+        valid_from,
+        member_request.valid_until,
+        &claims.sub,
+    )?;
+
+    Ok(Status::Created)
+}
+
+/// Removes a direct member from a group, with the same rights as adding one.
+#[openapi]
+#[delete("/groups/<identifier>/members/<email>")]
+pub fn remove_group_member(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    identifier: String,
+    email: String,
+) -> Result<Status, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let group = find_group(&mut conn, &identifier)?;
+    authorize_group_change(
+        role_for(&mut conn, &claims.sub)?,
+        ownerships.0.contains(&group.identifier),
+        Permission::EditUsers,
+    )?;
+    let member = find_user(&mut conn, &email)?;
+
+    revoke_membership(
+        &mut conn,
+        &mut cache_connection,
+        &group,
+        member.id,
+        &member.email_id,
+        &claims.sub,
+        json!({}),
+    )?;
+
+    Ok(Status::NoContent)
+}
+
+#[openapi]
+#[get("/groups/<identifier>/children")]
+pub fn list_child_groups(
+    _caller: InternalCaller<ReadGroups>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    identifier: String,
+) -> Result<Json<Vec<GroupRef>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let parent = find_group(&mut conn, &identifier)?;
+
+    groups::child_groups(&mut conn, parent.id)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Nests a group under another. Members of the child become inherited members of the
+/// parent and every group above it. Returns 409 if the edge would create a cycle. Needs an
+/// admin role with `edit_groups`; owning either group is not enough.
+#[openapi]
+#[post(
+    "/groups/<identifier>/children",
+    format = "json",
+    data = "<nest_request>"
+)]
+pub fn add_child_group(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    identifier: String,
+    nest_request: Json<NestGroupRequest>,
+) -> Result<Status, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    authorize_group_change(
+        role_for(&mut conn, &claims.sub)?,
+        false,
+        Permission::EditGroups,
+    )?;
+
+    let parent = find_group(&mut conn, &identifier)?;
+    let child = find_group(&mut conn, &nest_request.child_identifier)?;
+
+    match groups::add_child_group(&mut conn, parent.id, child.id) {
+        Ok(()) => {}
+        Err(NestingError::Cycle) => return Err(Status::Conflict),
+        Err(NestingError::Database(_)) => return Err(Status::InternalServerError),
+    }
+
+    if let Ok(mut cache_connection) = cache_pool.get() {
+        invalidate_subtree(&mut conn, &mut cache_connection, child.id);
+    }
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "group.child_added",
+            &claims.sub,
+            &parent.identifier,
+            json!({ "child": child.identifier }),
+        ),
+    );
+
+    Ok(Status::Created)
+}
+
+/// Removes a nesting edge, with the same rights as adding one.
+#[openapi]
+#[delete("/groups/<identifier>/children/<child_identifier>")]
+pub fn remove_child_group(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    identifier: String,
+    child_identifier: String,
+) -> Result<Status, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    authorize_group_change(
+        role_for(&mut conn, &claims.sub)?,
+        false,
+        Permission::EditGroups,
+    )?;
+
+    let parent = find_group(&mut conn, &identifier)?;
+    let child = find_group(&mut conn, &child_identifier)?;
+
+    let removed = groups::remove_child_group(&mut conn, parent.id, child.id)
+        .map_err(|_| Status::InternalServerError)?;
+    if removed == 0 {
+        return Err(Status::NotFound);
+    }
+
+    if let Ok(mut cache_connection) = cache_pool.get() {
+        invalidate_subtree(&mut conn, &mut cache_connection, child.id);
+    }
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "group.child_removed",
+            &claims.sub,
+            &parent.identifier,
+            json!({ "child": child.identifier }),
+        ),
+    );
+
+    Ok(Status::NoContent)
+}
+
+/// Lists direct members of a group and the members inherited from nested groups.
+#[openapi]
+#[get("/groups/<identifier>/members")]
+pub fn get_group_members(
+    _caller: InternalCaller<ReadGroups>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    identifier: String,
+) -> Result<Json<GroupMembersResponse>, Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let group = find_group(&mut conn, &identifier)?;
+    let members =
+        groups::effective_members(&mut conn, group.id).map_err(|_| Status::InternalServerError)?;
+
+    let mut users: HashMap<i64, User> = user
+        .filter(id.eq_any(members.iter().map(|m| m.user_id).collect::<Vec<i64>>()))
+        .load::<User>(&mut conn)
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect();
+
+    let response = members
+        .into_iter()
+        .filter_map(|member| {
+            let record = users.remove(&member.user_id)?;
+            Some(GroupMemberEntry {
+                email_id: record.email_id,
+                first_name: record.first_name,
+                last_name: record.last_name,
+                via_group: member.via_group,
+                depth: member.depth,
+            })
+        })
+        .collect();
+
+    Ok(Json(response))
+}
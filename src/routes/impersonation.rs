@@ -0,0 +1,252 @@
+use crate::db::audit::{self, AuditEvent};
+use crate::db::impersonations::{self, ImpersonationSession};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::redis::random_key;
+use crate::db::revocations::{self, TokenIdentity};
+use crate::emails::EmailTemplates;
+use crate::impersonation_tokens::{ImpersonationRejection, ImpersonationTokens};
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{StartImpersonationRequest, VerifyImpersonationRequest};
+use crate::models::response::{ImpersonationTokenResponse, SessionRevocationResponse};
+use crate::routes::admin::effective_app_access;
+use crate::routes::domain_rules::find_app;
+use crate::routes::groups::{find_user, require_root};
+use chrono::Duration;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use ginger_shared_rs::rocket_utils::APIClaims;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+const DEFAULT_DURATION_MINUTES: i64 = 15;
+const MAX_DURATION_MINUTES: i64 = 60;
+
+fn impersonation_notice(
+    templates: &EmailTemplates,
+    session: &ImpersonationSession,
+) -> Result<EmailJob, Status> {
+    let rendered = templates
+        .render_impersonation_started(
+            &session.admin_email,
+            &session.app_client_id,
+            &session.reason,
+            session.started_at,
+            session.expires_at,
+        )
+        .map_err(|err| {
+            println!("Failed to render impersonation notice: {}", err);
+            Status::InternalServerError
+        })?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        &session.target_email,
+        rendered.subject,
+        rendered.html,
+    ))
+}
+
+/// Issues a short-lived token that lets a root admin see an app as another user. Root
+/// users can't be impersonated, a reason is required, and the token only works in the
+/// given app. Every session is audited and can optionally be announced to the user.
+#[openapi]
+#[post("/impersonations", format = "json", data = "<impersonation_request>")]
+pub fn start_impersonation(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    tokens: &State<ImpersonationTokens>,
+    templates: &State<EmailTemplates>,
+    impersonation_request: Json<StartImpersonationRequest>,
+) -> Result<Json<ImpersonationTokenResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let reason = impersonation_request.reason.trim();
+    let duration_minutes = impersonation_request
+        .duration_minutes
+        .unwrap_or(DEFAULT_DURATION_MINUTES);
+    if reason.is_empty() || !(1..=MAX_DURATION_MINUTES).contains(&duration_minutes) {
+        return Err(Status::BadRequest);
+    }
+
+    let target = find_user(&mut conn, &impersonation_request.target_email)?;
+    if target.is_root || target.email_id == claims.sub {
+        return Err(Status::Forbidden);
+    }
+    if !target.is_active {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let target_app = find_app(&mut conn, &impersonation_request.app_client_id)?;
+    if target_app.disabled {
+        return Err(Status::UnprocessableEntity);
+    }
+    // Seeing what the user sees only makes sense where they can sign in themselves
+    let can_sign_in = effective_app_access(&mut conn, target_app.id)?
+        .iter()
+        .any(|entry| entry.email_id == target.email_id);
+    if !can_sign_in {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut session = ImpersonationSession::new(
+        &claims.sub,
+        &target.email_id,
+        &target_app.client_id,
+        reason,
+        impersonation_request.read_only,
+        Duration::minutes(duration_minutes),
+    );
+    session.user_notified = impersonation_request.notify_user;
+    let token = tokens.issue(&session, &target);
+
+    let notice = if session.user_notified {
+        Some(impersonation_notice(templates, &session)?)
+    } else {
+        None
+    };
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    impersonations::queue_session(&mut pipe, &session);
+    if let Some(notice) = &notice {
+        outbox::queue_job(&mut pipe, notice);
+    }
+    // No token without a stored session: it is what the audit trail points at
+    pipe.query::<()>(&mut *cache_connection)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "impersonation.started",
+            &claims.sub,
+            &target.email_id,
+            json!({
+                "impersonation_id": session.id,
+                "app_client_id": session.app_client_id,
+                "reason": session.reason,
+                "read_only": session.read_only,
+                "expires_at": session.expires_at,
+                "user_notified": session.user_notified,
+            }),
+        ),
+    );
+
+    Ok(Json(ImpersonationTokenResponse { session, token }))
+}
+
+/// Lists impersonation sessions whose token is still valid. Tokens are never shown again.
+#[openapi]
+#[get("/impersonations")]
+pub fn list_impersonations(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+) -> Result<Json<Vec<ImpersonationSession>>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    impersonations::list_active(&mut cache_connection)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Ends a session early. Its token stops working in every app checking the shared
+/// revocation list or verifying it here.
+#[openapi]
+#[delete("/impersonations/<session_id>")]
+pub fn end_impersonation(
+    claims: UserClaims,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    session_id: &str,
+) -> Result<Json<SessionRevocationResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    require_root(&mut conn, &claims)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let session = impersonations::get(&mut cache_connection, session_id)
+        .map_err(|_| Status::ServiceUnavailable)?
+        .ok_or(Status::NotFound)?;
+
+    // Denylisted first, so a failure leaves the session listed rather than silently live
+    revocations::revoke_token(
+        &mut cache_connection,
+        &session.id,
+        session.expires_at.timestamp(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+    impersonations::end(&mut cache_connection, &session.id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "impersonation.ended",
+            &claims.sub,
+            &session.target_email,
+            json!({
+                "impersonation_id": session.id,
+                "app_client_id": session.app_client_id,
+                "started_by": session.admin_email,
+            }),
+        ),
+    );
+
+    Ok(Json(SessionRevocationResponse {
+        revoked: session.id,
+        issued_before: None,
+        expires_at: Some(session.expires_at),
+    }))
+}
+
+/// For apps supporting impersonation: checks a token presented to `client_id` and returns
+/// its session. Tokens for other apps, read-only tokens used for changes and tokens of
+/// ended or revoked sessions are refused.
+#[openapi]
+#[post("/impersonations/verify", format = "json", data = "<verify_request>")]
+pub fn verify_impersonation(
+    _api_claims: APIClaims,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    tokens: &State<ImpersonationTokens>,
+    verify_request: Json<VerifyImpersonationRequest>,
+) -> Result<Json<ImpersonationSession>, Status> {
+    let claims = tokens
+        .verify(
+            verify_request.token.trim(),
+            &verify_request.client_id,
+            verify_request.writes,
+        )
+        .map_err(|rejection| match rejection {
+            ImpersonationRejection::Invalid => Status::Unauthorized,
+            ImpersonationRejection::WrongApp | ImpersonationRejection::ReadOnly => {
+                Status::Forbidden
+            }
+        })?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let identity = TokenIdentity {
+        sub: &claims.sub,
+        client_id: claims.client_id.as_deref(),
+        jti: Some(&claims.jti),
+        issued_at: claims.iat as i64,
+    };
+    if revocations::is_revoked(&mut cache_connection, &identity)
+        .map_err(|_| Status::ServiceUnavailable)?
+    {
+        return Err(Status::Unauthorized);
+    }
+    impersonations::get(&mut cache_connection, &claims.jti)
+        .map_err(|_| Status::ServiceUnavailable)?
+        .map(Json)
+        .ok_or(Status::Unauthorized)
+}
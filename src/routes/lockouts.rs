@@ -0,0 +1,197 @@
+use crate::config;
+use crate::db::audit::{self, AuditEvent};
+use crate::db::lockouts::{self, AccountLock};
+use crate::db::revocations;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::iam_service::IamService;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{LockUserRequest, LoginAttemptRequest};
+use crate::models::response::AccountLockStatus;
+use crate::policy::PolicySet;
+use crate::routes::authz::{authorize, subject_for, user_resource};
+use crate::routes::groups::find_user;
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+pub(crate) fn lock_status_for(
+    cache_connection: &mut redis::Connection,
+    email_id: String,
+) -> Result<AccountLockStatus, Status> {
+    let lock = lockouts::active_lock(cache_connection, &email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    let failed_logins = lockouts::failed_logins(cache_connection, &email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    Ok(AccountLockStatus {
+        email_id,
+        locked: lock.is_some(),
+        lock,
+        failed_logins: failed_logins.count,
+        last_failed_login_at: failed_logins.last_failed_at,
+    })
+}
+
+/// Locks an account and logs it out everywhere. The lock stays until it is lifted, or until
+/// `unlock_at` when given. Needs `user.lock`.
+#[openapi]
+#[put("/user/<email>/lock", format = "json", data = "<lock_request>")]
+pub fn lock_user(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+    lock_request: Json<LockUserRequest>,
+) -> Result<Json<AccountLockStatus>, Status> {
+    let reason = lock_request.reason.trim();
+    if reason.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let now = Utc::now();
+    if lock_request
+        .unlock_at
+        .is_some_and(|unlock_at| unlock_at <= now)
+    {
+        return Err(Status::BadRequest);
+    }
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = find_user(&mut conn, email)?;
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(&mut conn, &target)?;
+    authorize(policies, &mut conn, &subject, "user.lock", &resource)?;
+
+    let account_lock = AccountLock {
+        email_id: target.email_id.clone(),
+        reason: reason.to_string(),
+        locked_by: claims.sub.clone(),
+        locked_at: now,
+        unlock_at: lock_request.unlock_at,
+    };
+    lockouts::lock(&mut cache_connection, &account_lock).map_err(|_| Status::ServiceUnavailable)?;
+    revocations::revoke_user(
+        &mut cache_connection,
+        &target.email_id,
+        now,
+        config::revocation_marker_ttl_seconds(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "user.locked",
+            &claims.sub,
+            &target.email_id,
+            json!({ "reason": account_lock.reason, "unlock_at": account_lock.unlock_at }),
+        ),
+    );
+
+    lock_status_for(&mut cache_connection, target.email_id).map(Json)
+}
+
+/// Lifts a lock and clears the user's failed login count. Needs `user.unlock`.
+#[openapi]
+#[delete("/user/<email>/lock")]
+pub fn unlock_user(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+) -> Result<Json<AccountLockStatus>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = find_user(&mut conn, email)?;
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(&mut conn, &target)?;
+    authorize(policies, &mut conn, &subject, "user.unlock", &resource)?;
+
+    let unlocked = lockouts::unlock(&mut cache_connection, &target.email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    if unlocked {
+        audit::record(
+            &mut conn,
+            AuditEvent::new("user.unlocked", &claims.sub, &target.email_id, json!({})),
+        );
+    }
+
+    lock_status_for(&mut cache_connection, target.email_id).map(Json)
+}
+
+/// Called by the IAM service after each login attempt. A success clears the failed login
+/// count; a failure adds to it and locks the account for `FAILED_LOGIN_LOCK_SECONDS` once
+/// `FAILED_LOGIN_LOCK_THRESHOLD` is reached. The response tells the caller whether to refuse
+/// the login.
+#[openapi]
+#[post("/login-attempts", format = "json", data = "<attempt>")]
+pub fn record_login_attempt(
+    iam_service: IamService,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    attempt: Json<LoginAttemptRequest>,
+) -> Result<Json<AccountLockStatus>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, &attempt.email_id)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    if attempt.succeeded {
+        lockouts::reset_failures(&mut cache_connection, &target.email_id)
+            .map_err(|_| Status::ServiceUnavailable)?;
+        return lock_status_for(&mut cache_connection, target.email_id).map(Json);
+    }
+
+    let failures = lockouts::record_failure(
+        &mut cache_connection,
+        &target.email_id,
+        config::failed_login_window_seconds(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+    let threshold = config::failed_login_lock_threshold();
+    let already_locked = lockouts::active_lock(&mut cache_connection, &target.email_id)
+        .map_err(|_| Status::ServiceUnavailable)?
+        .is_some();
+
+    if threshold > 0 && failures >= threshold && !already_locked {
+        let now = Utc::now();
+        let account_lock = AccountLock {
+            email_id: target.email_id.clone(),
+            reason: format!("{} failed logins", failures),
+            locked_by: "system".to_string(),
+            locked_at: now,
+            unlock_at: Some(now + Duration::seconds(config::failed_login_lock_seconds())),
+        };
+        lockouts::lock(&mut cache_connection, &account_lock)
+            .map_err(|_| Status::ServiceUnavailable)?;
+
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                "user.locked",
+                &iam_service.0.sub,
+                &target.email_id,
+                json!({
+                    "reason": account_lock.reason,
+                    "unlock_at": account_lock.unlock_at,
+                    "failed_logins": failures,
+                }),
+            ),
+        );
+    }
+
+    lock_status_for(&mut cache_connection, target.email_id).map(Json)
+}
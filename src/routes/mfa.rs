@@ -0,0 +1,389 @@
+use crate::db::audit::{self, AuditEvent};
+use crate::db::mfa::{self, MfaFactor, MfaRequirement};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::redis::random_key;
+use crate::emails::EmailTemplates;
+use crate::middlewares::admin::Admin;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::iam_service::IamService;
+use crate::middlewares::internal_caller::InternalCaller;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{MfaEnrollmentRequest, MfaRequirementRequest};
+use crate::models::response::{AppResponse, MfaResetResponse, MfaStatusResponse, UserResponse};
+use crate::models::schema::{App, User};
+use crate::permissions::require::{ReadUsers, ViewAccessReports};
+use crate::policy::PolicySet;
+use crate::routes::authz::{app_resource, authorize, subject_for};
+use crate::routes::groups::find_user;
+use crate::routes::passwords::authorize_on_user;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+/// Fills in the MFA flags `UserResponse::from` leaves unset.
+pub(crate) fn with_mfa_flags(
+    cache_connection: &mut redis::Connection,
+    mut users: Vec<UserResponse>,
+) -> Result<Vec<UserResponse>, Status> {
+    let emails: Vec<String> = users.iter().map(|u| u.email_id.clone()).collect();
+    let flags =
+        mfa::flags_for(cache_connection, &emails).map_err(|_| Status::InternalServerError)?;
+    for (user, flags) in users.iter_mut().zip(flags) {
+        user.mfa_enrolled = flags.enrolled;
+        user.mfa_required = flags.required;
+    }
+    Ok(users)
+}
+
+fn mfa_status_for(
+    cache_connection: &mut redis::Connection,
+    email_id: String,
+    app_requires_mfa: bool,
+) -> Result<MfaStatusResponse, Status> {
+    let factors =
+        mfa::factors(cache_connection, &email_id).map_err(|_| Status::ServiceUnavailable)?;
+    let user_requirement = mfa::user_requirement(cache_connection, &email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    Ok(MfaStatusResponse {
+        email_id,
+        enrolled: !factors.is_empty(),
+        factors,
+        required: user_requirement.is_some() || app_requires_mfa,
+        user_requirement,
+        app_requires_mfa,
+    })
+}
+
+fn mfa_reset_email(templates: &EmailTemplates, email_id: &str) -> Result<EmailJob, Status> {
+    let rendered = templates.render_mfa_reset(email_id).map_err(|err| {
+        println!("Failed to render MFA reset email: {}", err);
+        Status::InternalServerError
+    })?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        email_id,
+        rendered.subject,
+        rendered.html,
+    ))
+}
+
+/// The user's factors and whether a login needs MFA. Pass the app's `client_id` to take its
+/// requirement into account.
+#[openapi]
+#[get("/user/<email>/mfa?<client_id>")]
+pub fn get_mfa_status(
+    _caller: InternalCaller<ReadUsers>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    email: &str,
+    client_id: Option<&str>,
+) -> Result<Json<MfaStatusResponse>, Status> {
+    use crate::models::schema::schema::app::dsl as app_dsl;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, email)?;
+    let app_requires_mfa = match client_id {
+        Some(app_client_id) => app_dsl::app
+            .filter(app_dsl::client_id.eq(app_client_id))
+            .select(app_dsl::mfa_required)
+            .first::<bool>(&mut conn)
+            .optional()
+            .map_err(|_| Status::InternalServerError)?
+            .unwrap_or(false),
+        None => false,
+    };
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    mfa_status_for(&mut cache_connection, target.email_id, app_requires_mfa).map(Json)
+}
+
+/// Requires MFA for the user in every app, or lifts that. Apps requiring MFA still do.
+/// Needs `user.require_mfa`.
+#[openapi]
+#[put(
+    "/user/<email>/mfa-requirement",
+    format = "json",
+    data = "<requirement_request>"
+)]
+pub fn set_user_mfa_requirement(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+    requirement_request: Json<MfaRequirementRequest>,
+) -> Result<Json<MfaStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = authorize_on_user(
+        &mut conn,
+        policies,
+        &claims,
+        ownerships,
+        email,
+        "user.require_mfa",
+    )?;
+
+    let requirement = requirement_request.required.then(|| MfaRequirement {
+        required_by: claims.sub.clone(),
+        required_at: Utc::now(),
+    });
+    let changed = mfa::set_user_requirement(
+        &mut cache_connection,
+        &target.email_id,
+        requirement.as_ref(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+
+    if changed {
+        let action = if requirement_request.required {
+            "mfa.required"
+        } else {
+            "mfa.requirement_removed"
+        };
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                action,
+                &claims.sub,
+                &target.email_id,
+                json!({ "scope": "user" }),
+            ),
+        );
+    }
+
+    mfa_status_for(&mut cache_connection, target.email_id, false).map(Json)
+}
+
+/// Requires a second factor from everyone signing in to the app, or lifts that.
+/// Needs `app.require_mfa`.
+#[openapi]
+#[put(
+    "/applications/<app_id>/mfa-requirement",
+    format = "json",
+    data = "<requirement_request>"
+)]
+pub fn set_app_mfa_requirement(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    policies: &State<PolicySet>,
+    app_id: i64,
+    requirement_request: Json<MfaRequirementRequest>,
+) -> Result<Json<AppResponse>, Status> {
+    use crate::models::schema::schema::app::dsl::*;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+
+    let target = match app.filter(id.eq(app_id)).first::<App>(&mut conn) {
+        Ok(target) => target,
+        Err(diesel::result::Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = app_resource(&mut conn, &target.client_id)?;
+    authorize(policies, &mut conn, &subject, "app.require_mfa", &resource)?;
+
+    let updated = diesel::update(app.filter(id.eq(app_id)))
+        .set(mfa_required.eq(requirement_request.required))
+        .get_result::<App>(&mut conn)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if target.mfa_required != updated.mfa_required {
+        let action = if requirement_request.required {
+            "mfa.required"
+        } else {
+            "mfa.requirement_removed"
+        };
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                action,
+                &claims.sub,
+                &updated.client_id,
+                json!({ "scope": "app" }),
+            ),
+        );
+    }
+
+    Ok(Json(AppResponse::from(updated)))
+}
+
+/// Removes every factor the user enrolled, e.g. after a lost authenticator, and emails them.
+/// Needs `user.reset_mfa`. Only the factors reported here are cleared: the IAM service has
+/// no reset call, so its login flow must take `GET /user/<email>/mfa` as the source of truth
+/// and have the user enroll again when it reports none.
+#[openapi]
+#[post("/user/<email>/mfa-reset")]
+pub fn reset_mfa(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    templates: &State<EmailTemplates>,
+    email: &str,
+) -> Result<Json<MfaResetResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = authorize_on_user(
+        &mut conn,
+        policies,
+        &claims,
+        ownerships,
+        email,
+        "user.reset_mfa",
+    )?;
+
+    let factors = mfa::factors(&mut cache_connection, &target.email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    if factors.is_empty() {
+        return Ok(Json(MfaResetResponse {
+            email_id: target.email_id,
+            factors_removed: 0,
+            email_job_id: None,
+        }));
+    }
+
+    let email_job = mfa_reset_email(templates, &target.email_id)?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    mfa::queue_reset(&mut pipe, &target.email_id);
+    outbox::queue_job(&mut pipe, &email_job);
+    pipe.query::<()>(&mut *cache_connection)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "mfa.reset",
+            &claims.sub,
+            &target.email_id,
+            json!({
+                "factors": factors.iter().map(|factor| &factor.id).collect::<Vec<_>>(),
+                "email_job_id": email_job.id,
+            }),
+        ),
+    );
+
+    Ok(Json(MfaResetResponse {
+        email_id: target.email_id,
+        factors_removed: factors.len(),
+        email_job_id: Some(email_job.id),
+    }))
+}
+
+/// Called by the IAM service when a user enrolls a factor.
+#[openapi]
+#[post("/mfa-factors", format = "json", data = "<enrollment>")]
+pub fn record_mfa_enrollment(
+    iam_service: IamService,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    enrollment: Json<MfaEnrollmentRequest>,
+) -> Result<Json<MfaStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, &enrollment.email_id)?;
+
+    let factor = MfaFactor {
+        id: random_key(16),
+        kind: enrollment.kind,
+        label: enrollment.label.clone(),
+        enrolled_at: Utc::now(),
+    };
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    mfa::enroll(&mut cache_connection, &target.email_id, &factor)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "mfa.enrolled",
+            &iam_service.0.sub,
+            &target.email_id,
+            json!({ "factor_id": factor.id, "kind": factor.kind }),
+        ),
+    );
+
+    mfa_status_for(&mut cache_connection, target.email_id, false).map(Json)
+}
+
+/// Called by the IAM service when a user removes one of their factors.
+#[openapi]
+#[delete("/user/<email>/mfa-factors/<factor_id>")]
+pub fn remove_mfa_factor(
+    iam_service: IamService,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    email: &str,
+    factor_id: &str,
+) -> Result<Json<MfaStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, email)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let removed = mfa::remove_factor(&mut cache_connection, &target.email_id, factor_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    if !removed {
+        return Err(Status::NotFound);
+    }
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "mfa.factor_removed",
+            &iam_service.0.sub,
+            &target.email_id,
+            json!({ "factor_id": factor_id }),
+        ),
+    );
+
+    mfa_status_for(&mut cache_connection, target.email_id, false).map(Json)
+}
+
+/// Active root users without any MFA factor enrolled.
+#[openapi]
+#[get("/reports/root-users-without-mfa")]
+pub fn get_root_users_without_mfa(
+    _admin: Admin<ViewAccessReports>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+) -> Result<Json<Vec<UserResponse>>, Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let roots = user
+        .filter(is_root.eq(true))
+        .filter(is_active.eq(true))
+        .order_by(email_id.asc())
+        .load::<User>(&mut conn)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let roots = with_mfa_flags(
+        &mut cache_connection,
+        roots.into_iter().map(UserResponse::from).collect(),
+    )?;
+
+    Ok(Json(
+        roots
+            .into_iter()
+            .filter(|root| !root.mfa_enrolled)
+            .collect(),
+    ))
+}
@@ -1,7 +1,19 @@
 use ginger_shared_rs::rocket_models::MessageResponse;
 use rocket::serde::json::Json;
 use rocket_okapi::openapi;
+pub mod access_requests;
+pub mod access_reviews;
 pub mod admin;
+pub mod admin_roles;
+pub mod api_keys;
+pub mod authz;
+pub mod domain_rules;
+pub mod groups;
+pub mod impersonation;
+pub mod lockouts;
+pub mod mfa;
+pub mod passwords;
+pub mod sessions;
 /// This is a description. <br />You can do simple html <br /> like <b>this<b/>
 #[openapi()]
 #[get("/")]
@@ -0,0 +1,388 @@
+use crate::config;
+use crate::db::audit::{self, AuditEvent};
+use crate::db::outbox::{self, EmailJob};
+use crate::db::passwords::{self, PasswordReset, PasswordRotation, RotationReason};
+use crate::db::redis::random_key;
+use crate::emails::EmailTemplates;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::iam_service::IamService;
+use crate::middlewares::internal_caller::InternalCaller;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::{
+    CompletePasswordResetRequest, PasswordChangedRequest, PasswordRotationRequest,
+    SetTemporaryPasswordRequest,
+};
+use crate::models::response::{
+    PasswordResetResponse, PasswordStatusResponse, TemporaryPasswordResponse,
+};
+use crate::models::schema::User;
+use crate::permissions::require::ReadUsers;
+use crate::policy::PolicySet;
+use crate::routes::authz::{authorize, subject_for, user_resource};
+use crate::routes::groups::find_user;
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const GENERATED_PASSWORD_LENGTH: usize = 16;
+
+/// Loads the target user and checks the caller may perform `action` on them.
+pub(crate) fn authorize_on_user(
+    conn: &mut PgConnection,
+    policies: &PolicySet,
+    claims: &UserClaims,
+    ownerships: GroupOwnerships,
+    email: &str,
+    action: &str,
+) -> Result<User, Status> {
+    let target = find_user(conn, email)?;
+    let subject = subject_for(conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(conn, &target)?;
+    authorize(policies, conn, &subject, action, &resource)?;
+    Ok(target)
+}
+
+fn set_password_hash(
+    conn: &mut PgConnection,
+    target_email: &str,
+    password: &str,
+) -> Result<(), Status> {
+    use crate::models::schema::schema::user::dsl::*;
+
+    let hashed = hash(password, DEFAULT_COST).map_err(|_| Status::InternalServerError)?;
+    diesel::update(user.filter(email_id.eq(target_email)))
+        .set(password_hash.eq(hashed))
+        .execute(conn)
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(())
+}
+
+fn password_reset_email(
+    templates: &EmailTemplates,
+    reset: &PasswordReset,
+    token: &str,
+) -> Result<EmailJob, Status> {
+    let reset_base = config::password_reset_url().map_err(|err| {
+        println!("Failed to build password reset link: {}", err);
+        Status::InternalServerError
+    })?;
+    let reset_link = format!("{}/{}", reset_base, token);
+    let rendered = templates
+        .render_password_reset(&reset_link, reset.expires_at)
+        .map_err(|err| {
+            println!("Failed to render password reset email: {}", err);
+            Status::InternalServerError
+        })?;
+
+    Ok(EmailJob::new(
+        random_key(16),
+        &reset.email_id,
+        rendered.subject,
+        rendered.html,
+    ))
+}
+
+/// Emails the user a link to choose a new password. The link works once, for an hour, and
+/// replaces any earlier one. Needs `user.reset_password`.
+#[openapi]
+#[post("/user/<email>/password-reset")]
+pub fn send_password_reset(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    templates: &State<EmailTemplates>,
+    email: &str,
+) -> Result<Json<PasswordResetResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = authorize_on_user(
+        &mut conn,
+        policies,
+        &claims,
+        ownerships,
+        email,
+        "user.reset_password",
+    )?;
+    if !target.is_active {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let (reset, token) = PasswordReset::new(&target.email_id, &claims.sub);
+    let email_job = password_reset_email(templates, &reset, &token)?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    passwords::queue_reset(&mut pipe, &reset);
+    outbox::queue_job(&mut pipe, &email_job);
+    pipe.query::<()>(&mut *cache_connection)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "password.reset_requested",
+            &claims.sub,
+            &target.email_id,
+            json!({ "expires_at": reset.expires_at, "email_job_id": email_job.id }),
+        ),
+    );
+
+    Ok(Json(PasswordResetResponse {
+        email_id: target.email_id,
+        expires_at: reset.expires_at,
+        email_job_id: email_job.id,
+    }))
+}
+
+/// Replaces the user's password with a temporary one they must change at next login. A
+/// password is generated when none is given and returned once. Needs `user.set_password`.
+#[openapi]
+#[put(
+    "/user/<email>/temporary-password",
+    format = "json",
+    data = "<password_request>"
+)]
+pub fn set_temporary_password(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+    password_request: Json<SetTemporaryPasswordRequest>,
+) -> Result<Json<TemporaryPasswordResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = authorize_on_user(
+        &mut conn,
+        policies,
+        &claims,
+        ownerships,
+        email,
+        "user.set_password",
+    )?;
+
+    let (password, generated) = match &password_request.password {
+        Some(password) if password.chars().count() < MIN_PASSWORD_LENGTH => {
+            return Err(Status::UnprocessableEntity)
+        }
+        Some(password) => (password.clone(), false),
+        None => (random_key(GENERATED_PASSWORD_LENGTH), true),
+    };
+
+    // Flag first: a temporary password must never be usable as a permanent one
+    passwords::require_rotation(
+        &mut cache_connection,
+        &PasswordRotation {
+            email_id: target.email_id.clone(),
+            reason: RotationReason::TemporaryPassword,
+            required_by: claims.sub.clone(),
+            required_at: Utc::now(),
+        },
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+    set_password_hash(&mut conn, &target.email_id, &password)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "password.temporary_set",
+            &claims.sub,
+            &target.email_id,
+            json!({ "generated": generated }),
+        ),
+    );
+
+    Ok(Json(TemporaryPasswordResponse {
+        email_id: target.email_id,
+        temporary_password: generated.then_some(password),
+        must_rotate: true,
+    }))
+}
+
+/// Flags the account so the user has to choose a new password at next login, or clears the
+/// flag. Needs `user.require_password_change`.
+#[openapi]
+#[put(
+    "/user/<email>/password-rotation",
+    format = "json",
+    data = "<rotation_request>"
+)]
+pub fn set_password_rotation(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+    rotation_request: Json<PasswordRotationRequest>,
+) -> Result<Json<PasswordStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = authorize_on_user(
+        &mut conn,
+        policies,
+        &claims,
+        ownerships,
+        email,
+        "user.require_password_change",
+    )?;
+
+    let action = if rotation_request.required {
+        passwords::require_rotation(
+            &mut cache_connection,
+            &PasswordRotation {
+                email_id: target.email_id.clone(),
+                reason: RotationReason::AdminRequest,
+                required_by: claims.sub.clone(),
+                required_at: Utc::now(),
+            },
+        )
+        .map_err(|_| Status::ServiceUnavailable)?;
+        "password.rotation_required"
+    } else {
+        passwords::clear_rotation(&mut cache_connection, &target.email_id)
+            .map_err(|_| Status::ServiceUnavailable)?;
+        "password.rotation_cleared"
+    };
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(action, &claims.sub, &target.email_id, json!({})),
+    );
+
+    password_status_for(&mut cache_connection, target.email_id).map(Json)
+}
+
+fn password_status_for(
+    cache_connection: &mut redis::Connection,
+    email_id: String,
+) -> Result<PasswordStatusResponse, Status> {
+    let rotation = passwords::get_rotation(cache_connection, &email_id)
+        .map_err(|_| Status::InternalServerError)?;
+    let reset = passwords::get_reset(cache_connection, &email_id)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(PasswordStatusResponse {
+        email_id,
+        must_rotate: rotation.is_some(),
+        rotation,
+        reset_pending_until: reset.map(|reset| reset.expires_at),
+    })
+}
+
+/// Whether the user must choose a new password at next login, for the IAM service's login
+/// flow.
+#[openapi]
+#[get("/user/<email>/password-status")]
+pub fn get_password_status(
+    _caller: InternalCaller<ReadUsers>,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    email: &str,
+) -> Result<Json<PasswordStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, email)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    password_status_for(&mut cache_connection, target.email_id).map(Json)
+}
+
+/// Sets the password chosen through a reset link. Called by the IAM service, which serves
+/// the reset page. Clears any must-rotate flag.
+#[openapi]
+#[post("/password-resets/complete", format = "json", data = "<reset_request>")]
+pub fn complete_password_reset(
+    iam_service: IamService,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    reset_request: Json<CompletePasswordResetRequest>,
+) -> Result<Json<PasswordStatusResponse>, Status> {
+    if reset_request.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let reset = passwords::get_reset(&mut cache_connection, &reset_request.email_id)
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|reset| reset.accepts(reset_request.token.trim()))
+        .ok_or(Status::NotFound)?;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, &reset.email_id)?;
+    if !target.is_active {
+        return Err(Status::Forbidden);
+    }
+
+    passwords::delete_reset(&mut cache_connection, &target.email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    set_password_hash(&mut conn, &target.email_id, &reset_request.new_password)?;
+    if let Err(err) = passwords::clear_rotation(&mut cache_connection, &target.email_id) {
+        println!(
+            "Failed to clear password rotation for {}: {}",
+            target.email_id, err
+        );
+    }
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "password.reset_completed",
+            &iam_service.0.sub,
+            &target.email_id,
+            json!({ "requested_by": reset.requested_by }),
+        ),
+    );
+
+    password_status_for(&mut cache_connection, target.email_id).map(Json)
+}
+
+/// Tells the service a user changed their password, clearing the must-rotate flag. Called
+/// by the IAM service after a password change.
+#[openapi]
+#[post(
+    "/password-rotation/complete",
+    format = "json",
+    data = "<changed_request>"
+)]
+pub fn complete_password_rotation(
+    iam_service: IamService,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    changed_request: Json<PasswordChangedRequest>,
+) -> Result<Json<PasswordStatusResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let target = find_user(&mut conn, &changed_request.email_id)?;
+
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let cleared = passwords::clear_rotation(&mut cache_connection, &target.email_id)
+        .map_err(|_| Status::ServiceUnavailable)?;
+    if cleared {
+        audit::record(
+            &mut conn,
+            AuditEvent::new(
+                "password.rotated",
+                &iam_service.0.sub,
+                &target.email_id,
+                json!({}),
+            ),
+        );
+    }
+
+    password_status_for(&mut cache_connection, target.email_id).map(Json)
+}
@@ -0,0 +1,198 @@
+use crate::config;
+use crate::db::audit::{self, AuditEvent};
+use crate::db::revocations;
+use crate::impersonation_tokens::ImpersonationTokens;
+use crate::middlewares::groups_owned::GroupOwnerships;
+use crate::middlewares::user_claims::UserClaims;
+use crate::models::request::RevokeTokenRequest;
+use crate::models::response::SessionRevocationResponse;
+use crate::models::schema::App;
+use crate::policy::PolicySet;
+use crate::routes::authz::{app_resource, authorize, subject_for, user_resource};
+use crate::routes::groups::find_user;
+use crate::user_tokens::TokenVerifier;
+use chrono::{TimeZone, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use r2d2_redis::RedisConnectionManager;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::openapi;
+use serde_json::json;
+
+/// Logs a user out everywhere: every token issued to them so far stops working, in this
+/// service and in any app checking the shared revocation list. Needs `user.revoke_sessions`.
+#[openapi]
+#[post("/user/<email>/logout")]
+pub fn force_logout_user(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    email: &str,
+) -> Result<Json<SessionRevocationResponse>, Status> {
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = find_user(&mut conn, email)?;
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(&mut conn, &target)?;
+    authorize(
+        policies,
+        &mut conn,
+        &subject,
+        "user.revoke_sessions",
+        &resource,
+    )?;
+
+    let now = Utc::now();
+    revocations::revoke_user(
+        &mut cache_connection,
+        &target.email_id,
+        now,
+        config::revocation_marker_ttl_seconds(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "sessions.revoked",
+            &claims.sub,
+            &target.email_id,
+            json!({ "scope": "user", "reason": "force_logout" }),
+        ),
+    );
+
+    Ok(Json(SessionRevocationResponse {
+        revoked: target.email_id,
+        issued_before: Some(now),
+        expires_at: None,
+    }))
+}
+
+/// Logs every user of an app out of it. Tokens issued for the app's client id so far stop
+/// working; sessions in other apps are untouched. Needs `app.revoke_sessions`.
+#[openapi]
+#[post("/applications/<app_id>/logout")]
+pub fn force_logout_app(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    app_id: i64,
+) -> Result<Json<SessionRevocationResponse>, Status> {
+    use crate::models::schema::schema::app::dsl::*;
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let target = match app.filter(id.eq(app_id)).first::<App>(&mut conn) {
+        Ok(target) => target,
+        Err(diesel::result::Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = app_resource(&mut conn, &target.client_id)?;
+    authorize(
+        policies,
+        &mut conn,
+        &subject,
+        "app.revoke_sessions",
+        &resource,
+    )?;
+
+    let now = Utc::now();
+    revocations::revoke_app(
+        &mut cache_connection,
+        &target.client_id,
+        now,
+        config::revocation_marker_ttl_seconds(),
+    )
+    .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "sessions.revoked",
+            &claims.sub,
+            &target.client_id,
+            json!({ "scope": "app", "reason": "force_logout" }),
+        ),
+    );
+
+    Ok(Json(SessionRevocationResponse {
+        revoked: target.client_id,
+        issued_before: Some(now),
+        expires_at: None,
+    }))
+}
+
+/// Denylists a single token, e.g. one that leaked, until it expires. The token has to be
+/// valid and carry a `jti`; refresh and impersonation tokens are accepted too. The caller
+/// needs `user.revoke_sessions` on its subject.
+#[allow(clippy::too_many_arguments)]
+#[openapi]
+#[post("/sessions/revoke-token", format = "json", data = "<revoke_request>")]
+pub async fn revoke_token(
+    claims: UserClaims,
+    ownerships: GroupOwnerships,
+    rdb: &State<Pool<ConnectionManager<PgConnection>>>,
+    cache_pool: &State<Pool<RedisConnectionManager>>,
+    policies: &State<PolicySet>,
+    verifier: &State<TokenVerifier>,
+    impersonation_tokens: &State<ImpersonationTokens>,
+    revoke_request: Json<RevokeTokenRequest>,
+) -> Result<Json<SessionRevocationResponse>, Status> {
+    let token = revoke_request.token.trim();
+    let (holder_email, jti, expires_at) = match verifier.decode(token).await {
+        Ok(verified) => (
+            verified.claims.sub,
+            verified.jti.ok_or(Status::UnprocessableEntity)?,
+            verified.claims.exp as i64,
+        ),
+        Err(_) => {
+            let claims = impersonation_tokens
+                .decode(token)
+                .map_err(|_| Status::UnprocessableEntity)?;
+            (claims.sub, claims.jti, claims.exp as i64)
+        }
+    };
+
+    let mut conn = rdb.get().map_err(|_| Status::InternalServerError)?;
+    let mut cache_connection = cache_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+
+    let holder = find_user(&mut conn, &holder_email)?;
+    let subject = subject_for(&mut conn, &claims.sub, ownerships.0)?;
+    let resource = user_resource(&mut conn, &holder)?;
+    authorize(
+        policies,
+        &mut conn,
+        &subject,
+        "user.revoke_sessions",
+        &resource,
+    )?;
+
+    revocations::revoke_token(&mut cache_connection, &jti, expires_at)
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    audit::record(
+        &mut conn,
+        AuditEvent::new(
+            "sessions.revoked",
+            &claims.sub,
+            &holder.email_id,
+            json!({ "scope": "token", "jti": jti, "reason": "force_logout" }),
+        ),
+    );
+
+    Ok(Json(SessionRevocationResponse {
+        revoked: jti,
+        issued_before: None,
+        expires_at: Utc.timestamp_opt(expires_at, 0).single(),
+    }))
+}
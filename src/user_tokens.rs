@@ -0,0 +1,307 @@
+use crate::impersonation_tokens;
+use ginger_shared_rs::rocket_utils::Claims;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet, KeyAlgorithm};
+use jsonwebtoken::{decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// Bearer tokens are either HS256 tokens signed with `JWT_SECRET` or RS256/ES256 tokens
+// signed by a key in the JWKS document at `JWKS_URL` or `JWKS_FILE`. The document is
+// cached and reloaded when it goes stale or a token names a `kid` it doesn't have, so a
+// signer can rotate keys by publishing the new one before it starts using it.
+
+const DEFAULT_JWKS_CACHE_SECONDS: u64 = 300;
+// Unknown `kid`s trigger a reload at most this often, so junk tokens can't hammer the issuer
+const MIN_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCESS_TOKEN_TYPE: &str = "access";
+
+/// Why a bearer token was refused. Sent back in the `WWW-Authenticate` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenRejection {
+    Missing,
+    Malformed,
+    UnsupportedAlgorithm,
+    MissingKeyId,
+    UnknownKey,
+    KeysUnavailable,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+    /// Impersonation tokens are for the impersonated user's app, not this service
+    Impersonation,
+    /// Refresh tokens and anything else that isn't an access token
+    NotAccessToken,
+    /// Cut off by a force logout or the token denylist
+    Revoked,
+    RevocationsUnavailable,
+}
+
+impl TokenRejection {
+    pub fn describe(self) -> &'static str {
+        match self {
+            TokenRejection::Missing => "no bearer token was sent",
+            TokenRejection::Malformed => "the token is not a well-formed JWT",
+            TokenRejection::UnsupportedAlgorithm => "the token's signing algorithm is not accepted",
+            TokenRejection::MissingKeyId => "the token has no kid header",
+            TokenRejection::UnknownKey => "the token was signed with an unknown key",
+            TokenRejection::KeysUnavailable => "signing keys could not be loaded",
+            TokenRejection::InvalidSignature => "the token signature is invalid",
+            TokenRejection::Expired => "the token has expired",
+            TokenRejection::NotYetValid => "the token is not valid yet",
+            TokenRejection::WrongIssuer => "the token was issued by an untrusted issuer",
+            TokenRejection::WrongAudience => "the token is not meant for this service",
+            TokenRejection::Impersonation => "impersonation tokens are not accepted here",
+            TokenRejection::NotAccessToken => "only access tokens are accepted",
+            TokenRejection::Revoked => "the token has been revoked",
+            TokenRejection::RevocationsUnavailable => "revoked tokens could not be checked",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    File(PathBuf),
+    Url(String),
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: HashMap<String, (Algorithm, DecodingKey)>,
+    loaded_at: Option<Instant>,
+    last_attempt: Option<Instant>,
+}
+
+/// A verified token with the registered claims revocation checks need besides `Claims`.
+#[derive(Deserialize)]
+pub struct VerifiedToken {
+    #[serde(flatten)]
+    pub claims: Claims,
+    pub iat: Option<i64>,
+    pub jti: Option<String>,
+}
+
+pub struct TokenVerifier {
+    shared_secret: Option<DecodingKey>,
+    source: Option<KeySource>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    cache_ttl: Duration,
+    cache: RwLock<KeyCache>,
+}
+
+fn list_from_env(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keys from a JWKS document by `kid`. Keys without a `kid` or for algorithms other than
+/// RS256 and ES256 are skipped.
+pub fn parse_jwks(raw: &str) -> Result<HashMap<String, (Algorithm, DecodingKey)>, String> {
+    let jwks: JwkSet = serde_json::from_str(raw).map_err(|err| err.to_string())?;
+
+    let mut keys = HashMap::new();
+    for jwk in &jwks.keys {
+        let Some(kid) = &jwk.common.key_id else {
+            continue;
+        };
+        let algorithm = match (&jwk.algorithm, jwk.common.key_algorithm) {
+            (AlgorithmParameters::RSA(_), None | Some(KeyAlgorithm::RS256)) => Algorithm::RS256,
+            (AlgorithmParameters::EllipticCurve(params), None | Some(KeyAlgorithm::ES256))
+                if params.curve == EllipticCurve::P256 =>
+            {
+                Algorithm::ES256
+            }
+            _ => {
+                println!("Skipping JWKS key {}: unsupported algorithm", kid);
+                continue;
+            }
+        };
+        let key = DecodingKey::from_jwk(jwk).map_err(|err| format!("key {}: {}", kid, err))?;
+        keys.insert(kid.clone(), (algorithm, key));
+    }
+    Ok(keys)
+}
+
+impl TokenVerifier {
+    pub fn new(
+        shared_secret: Option<&str>,
+        source: Option<KeySource>,
+        issuers: Vec<String>,
+        audiences: Vec<String>,
+        cache_ttl: Duration,
+    ) -> Self {
+        TokenVerifier {
+            shared_secret: shared_secret.map(|secret| DecodingKey::from_secret(secret.as_bytes())),
+            source,
+            issuers,
+            audiences,
+            cache_ttl,
+            cache: RwLock::new(KeyCache::default()),
+        }
+    }
+
+    /// `JWT_SECRET` for HS256 tokens, `JWKS_URL` or `JWKS_FILE` for RS256/ES256 ones, and
+    /// `JWT_ISSUER`/`JWT_AUDIENCE` to restrict who tokens may come from and be meant for.
+    pub fn from_env() -> Self {
+        let source = match (env::var("JWKS_URL"), env::var("JWKS_FILE")) {
+            (Ok(url), _) => Some(KeySource::Url(url)),
+            (_, Ok(path)) => Some(KeySource::File(PathBuf::from(path))),
+            _ => None,
+        };
+        let cache_seconds = env::var("JWKS_CACHE_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_JWKS_CACHE_SECONDS);
+
+        TokenVerifier::new(
+            env::var("JWT_SECRET").ok().as_deref(),
+            source,
+            list_from_env("JWT_ISSUER"),
+            list_from_env("JWT_AUDIENCE"),
+            Duration::from_secs(cache_seconds),
+        )
+    }
+
+    async fn fetch(source: &KeySource) -> Result<String, String> {
+        match source {
+            KeySource::File(path) => fs::read_to_string(path).map_err(|err| err.to_string()),
+            KeySource::Url(url) => {
+                let client = reqwest::Client::builder()
+                    .timeout(JWKS_FETCH_TIMEOUT)
+                    .build()
+                    .map_err(|err| err.to_string())?;
+                client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|err| err.to_string())?
+                    .text()
+                    .await
+                    .map_err(|err| err.to_string())
+            }
+        }
+    }
+
+    /// Reloads the JWKS document. A failed reload keeps the keys already cached.
+    async fn reload(&self, source: &KeySource) {
+        {
+            let mut cache = self.cache.write().unwrap_or_else(|err| err.into_inner());
+            cache.last_attempt = Some(Instant::now());
+        }
+
+        match Self::fetch(source).await.and_then(|raw| parse_jwks(&raw)) {
+            Ok(keys) => {
+                let mut cache = self.cache.write().unwrap_or_else(|err| err.into_inner());
+                cache.keys = keys;
+                cache.loaded_at = Some(Instant::now());
+            }
+            Err(err) => println!("Failed to load JWKS from {:?}: {}", source, err),
+        }
+    }
+
+    async fn key_for(&self, kid: &str) -> Result<(Algorithm, DecodingKey), TokenRejection> {
+        let Some(source) = &self.source else {
+            return Err(TokenRejection::UnsupportedAlgorithm);
+        };
+
+        let needs_reload = {
+            let cache = self.cache.read().unwrap_or_else(|err| err.into_inner());
+            let stale = cache
+                .loaded_at
+                .is_none_or(|loaded_at| loaded_at.elapsed() >= self.cache_ttl);
+            let may_retry = cache
+                .last_attempt
+                .is_none_or(|attempt| attempt.elapsed() >= MIN_RELOAD_INTERVAL);
+            (stale || !cache.keys.contains_key(kid)) && may_retry
+        };
+        if needs_reload {
+            self.reload(source).await;
+        }
+
+        let cache = self.cache.read().unwrap_or_else(|err| err.into_inner());
+        match cache.keys.get(kid) {
+            Some((algorithm, key)) => Ok((*algorithm, key.clone())),
+            None if cache.loaded_at.is_none() => Err(TokenRejection::KeysUnavailable),
+            None => Err(TokenRejection::UnknownKey),
+        }
+    }
+
+    /// Checks a bearer token for this service: a valid access token.
+    pub async fn verify(&self, token: &str) -> Result<VerifiedToken, TokenRejection> {
+        let verified = self.decode(token).await?;
+        match verified.claims.token_type.as_str() {
+            ACCESS_TOKEN_TYPE => Ok(verified),
+            impersonation_tokens::TOKEN_TYPE => Err(TokenRejection::Impersonation),
+            _ => Err(TokenRejection::NotAccessToken),
+        }
+    }
+
+    /// Checks the signature and registered claims of a token of any type, e.g. a refresh
+    /// token someone wants revoked.
+    pub async fn decode(&self, token: &str) -> Result<VerifiedToken, TokenRejection> {
+        let header = decode_header(token).map_err(|_| TokenRejection::Malformed)?;
+
+        let (algorithm, key) = match header.alg {
+            Algorithm::HS256 => match &self.shared_secret {
+                Some(secret) => (Algorithm::HS256, secret.clone()),
+                None => return Err(TokenRejection::UnsupportedAlgorithm),
+            },
+            Algorithm::RS256 | Algorithm::ES256 => {
+                let kid = header.kid.as_deref().ok_or(TokenRejection::MissingKeyId)?;
+                let (algorithm, key) = self.key_for(kid).await?;
+                // A key is only trusted for the algorithm it was published for
+                if algorithm != header.alg {
+                    return Err(TokenRejection::UnsupportedAlgorithm);
+                }
+                (algorithm, key)
+            }
+            _ => return Err(TokenRejection::UnsupportedAlgorithm),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.validate_nbf = true;
+        // HS256 tokens come from the IAM service itself and carry neither claim
+        let from_jwks = algorithm != Algorithm::HS256;
+        if from_jwks && !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+        }
+        if from_jwks && !self.audiences.is_empty() {
+            validation.set_audience(&self.audiences);
+        } else {
+            validation.validate_aud = false;
+        }
+
+        let verified = jsonwebtoken::decode::<VerifiedToken>(token, &key, &validation)
+            .map_err(|err| match err.kind() {
+                ErrorKind::ExpiredSignature => TokenRejection::Expired,
+                ErrorKind::ImmatureSignature => TokenRejection::NotYetValid,
+                ErrorKind::InvalidIssuer => TokenRejection::WrongIssuer,
+                ErrorKind::InvalidAudience => TokenRejection::WrongAudience,
+                ErrorKind::InvalidSignature => TokenRejection::InvalidSignature,
+                ErrorKind::MissingRequiredClaim(claim) if claim == "iss" => {
+                    TokenRejection::WrongIssuer
+                }
+                ErrorKind::MissingRequiredClaim(claim) if claim == "aud" => {
+                    TokenRejection::WrongAudience
+                }
+                _ => TokenRejection::Malformed,
+            })?
+            .claims;
+        Ok(verified)
+    }
+}
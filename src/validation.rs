@@ -0,0 +1,88 @@
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum EmailRejection {
+    Invalid,
+    DomainNotAllowed,
+}
+
+/// Trims and lowercases an email address, rejecting anything that isn't a plain
+/// `local@domain.tld` address.
+pub fn normalize_email(raw: &str) -> Result<String, EmailRejection> {
+    let email = raw.trim().to_lowercase();
+    if email.len() > MAX_EMAIL_LENGTH || email.chars().any(char::is_whitespace) {
+        return Err(EmailRejection::Invalid);
+    }
+
+    let Some((local, domain)) = email.split_once('@') else {
+        return Err(EmailRejection::Invalid);
+    };
+
+    let local_ok = !local.is_empty()
+        && local.len() <= MAX_LOCAL_PART_LENGTH
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    if local_ok && is_valid_domain(domain) {
+        Ok(email)
+    } else {
+        Err(EmailRejection::Invalid)
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Trims and lowercases a bare domain such as `example.com`, also accepting `@example.com`.
+pub fn normalize_domain(raw: &str) -> Result<String, EmailRejection> {
+    let domain = raw.trim().trim_start_matches('@').to_lowercase();
+    if is_valid_domain(&domain) {
+        Ok(domain)
+    } else {
+        Err(EmailRejection::Invalid)
+    }
+}
+
+pub fn email_domain(email: &str) -> &str {
+    email.rsplit('@').next().unwrap_or_default()
+}
+
+/// Whether `domain` is `rule` or one of its subdomains.
+pub fn domain_matches(domain: &str, rule: &str) -> bool {
+    domain == rule || domain.ends_with(&format!(".{}", rule))
+}
+
+/// Applies the invite domain allow and deny lists to a normalised email. Rules also match
+/// subdomains, so `example.com` covers `eu.example.com`. An empty allow list allows any
+/// domain that isn't denied.
+pub fn check_invite_domain(
+    email: &str,
+    allowed: &[String],
+    denied: &[String],
+) -> Result<(), EmailRejection> {
+    let domain = email_domain(email);
+
+    if denied.iter().any(|rule| domain_matches(domain, rule)) {
+        return Err(EmailRejection::DomainNotAllowed);
+    }
+
+    if !allowed.is_empty() && !allowed.iter().any(|rule| domain_matches(domain, rule)) {
+        return Err(EmailRejection::DomainNotAllowed);
+    }
+
+    Ok(())
+}